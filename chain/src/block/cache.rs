@@ -8,9 +8,12 @@
 pub mod test;
 
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::net;
+use std::sync::{Arc, Mutex};
 
-use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::script::Script;
 use bitcoin::consensus::params::Params;
 use bitcoin::hash_types::BlockHash;
 use bitcoin::network::constants::Network;
@@ -20,13 +23,31 @@ use bitcoin::util::uint::Uint256;
 use nakamoto_common::block::tree::{self, BlockTree, Branch, Error, ImportResult};
 use nakamoto_common::block::{
     self,
-    iter::Iter,
     store::Store,
-    time::{self, Clock},
+    time::{self, Clock, LocalDuration, LocalTime},
     Bits, BlockTime, Height, Work,
 };
 use nakamoto_common::nonempty::NonEmpty;
 
+use crate::block::store::{self, Index};
+
+/// Maximum number of orphan blocks kept in memory, by default.
+pub const MAX_ORPHANS: usize = 1024;
+/// Time after which an orphan block that hasn't connected is expired, by default.
+pub const ORPHAN_EXPIRY: LocalDuration = LocalDuration::from_mins(10);
+
+/// Check a block's signet solution against a block challenge.
+#[cfg(feature = "signet")]
+fn is_valid_solution(block: &Block, challenge: &Script) -> bool {
+    block::signet::verify(block, challenge).is_ok()
+}
+
+/// Without the `signet` feature, block solutions can't be verified, and are always accepted.
+#[cfg(not(feature = "signet"))]
+fn is_valid_solution(_block: &Block, _challenge: &Script) -> bool {
+    true
+}
+
 /// A block that is being stored by the block cache.
 #[derive(Debug, Clone, Copy)]
 struct CachedBlock {
@@ -49,6 +70,17 @@ impl tree::Header for CachedBlock {
     }
 }
 
+/// A block that doesn't connect to the active chain.
+#[derive(Debug, Clone, Copy)]
+struct Orphan {
+    header: BlockHeader,
+    /// Peer that supplied the block, if any. Blocks that were disconnected from the active
+    /// chain have no source.
+    source: Option<net::SocketAddr>,
+    /// Time at which the orphan was added.
+    added: LocalTime,
+}
+
 /// A chain candidate, forking off the active chain.
 #[derive(Debug)]
 struct Candidate {
@@ -61,16 +93,36 @@ struct Candidate {
 /// An implementation of [`BlockTree`] using a generic storage backend.
 /// Most of the functionality is accessible via the trait.
 ///
+/// By default, the whole active chain is kept in memory. A cache created with
+/// [`BlockCache::bounded`] only keeps a window of recent blocks in memory, and serves
+/// older blocks from the store, using an [`Index`] to look them up by hash.
+///
 /// [`BlockTree`]: ../../../nakamoto_common/block/tree/trait.BlockTree.html
 ///
 #[derive(Debug, Clone)]
 pub struct BlockCache<S: Store> {
+    /// Active chain, or the most recent part of it. The first block in the chain
+    /// is genesis, unless the cache is bounded.
     chain: NonEmpty<CachedBlock>,
+    /// Index of the blocks in `chain`, plus genesis.
     headers: HashMap<BlockHash, Height>,
-    orphans: HashMap<BlockHash, BlockHeader>,
+    /// Total work of the active chain.
+    work: Work,
+    /// Blocks that don't connect to the active chain, including stale blocks.
+    orphans: HashMap<BlockHash, Orphan>,
+    /// Maximum number of orphans, and time after which they expire.
+    orphan_limits: (usize, LocalDuration),
     checkpoints: BTreeMap<Height, BlockHash>,
     params: Params,
     store: S,
+    /// Signet block challenge, if this is a signet.
+    signet: Option<Script>,
+    /// Blocks that were found to be invalid after their headers were imported.
+    invalid: HashSet<BlockHash>,
+    /// Number of recent blocks to keep in memory, and the index used to look up older
+    /// blocks. Only set on bounded caches. The index is shared between clones, which is
+    /// safe since index entries are always checked against the store.
+    window: Option<(usize, Arc<Mutex<Index>>)>,
 }
 
 impl<S: Store<Header = BlockHeader>> BlockCache<S> {
@@ -80,8 +132,92 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         params: Params,
         checkpoints: &[(Height, BlockHash)],
     ) -> Result<Self, Error> {
-        let genesis = store.genesis();
         let length = store.len()?;
+        let mut cache = Self::new(store, params, checkpoints, None, length);
+
+        for result in cache.store.iter().skip(1) {
+            let (height, header) = result?;
+            let hash = header.block_hash();
+
+            cache.extend_chain(height, hash, header)?;
+        }
+
+        assert_eq!(length, cache.chain.len());
+        assert_eq!(length, cache.headers.len());
+
+        Ok(cache)
+    }
+
+    /// Create a new bounded `BlockCache`, which keeps at least the `window` most recent
+    /// blocks of the active chain in memory. Older blocks are read from the store, and
+    /// looked up by hash through the given index. The index is updated with any blocks
+    /// from the store that it's missing.
+    ///
+    /// # Errors
+    ///
+    /// Panics if `window` is zero.
+    ///
+    pub fn bounded(
+        store: S,
+        mut index: Index,
+        params: Params,
+        checkpoints: &[(Height, BlockHash)],
+        window: usize,
+    ) -> Result<Self, Error> {
+        assert!(window > 0, "BlockCache::bounded: window must not be empty");
+
+        let height = store.height()?;
+        let start = (height + 1).saturating_sub(window as Height);
+
+        // If the index tip is on our chain, we only have to index the blocks after it.
+        // Otherwise, the store was modified without the index, and we rebuild it.
+        let (indexed, tip) = index.tip();
+        let unindexed = if indexed <= height && store.get(indexed)?.block_hash() == tip {
+            indexed + 1
+        } else {
+            index.clear()?;
+            1
+        };
+        for h in unindexed..=height {
+            index.insert(&store.get(h)?.block_hash(), h)?;
+        }
+        index.sync()?;
+
+        let window = Some((window, Arc::new(Mutex::new(index))));
+        let mut cache = Self::new(store, params, checkpoints, window, 0);
+
+        for result in cache.store.iter().skip(1) {
+            let (_, header) = result?;
+            cache.work = cache.work + header.work();
+        }
+
+        if start > 0 {
+            let header = cache.store.get(start)?;
+            let hash = header.block_hash();
+
+            cache.chain = NonEmpty::new(CachedBlock {
+                height: start,
+                hash,
+                header,
+            });
+            cache.headers.insert(hash, start);
+        }
+        for h in start + 1..=height {
+            let header = cache.store.get(h)?;
+            cache.push(h, header.block_hash(), header);
+        }
+        Ok(cache)
+    }
+
+    /// Create an empty `BlockCache`, with only the genesis block in the active chain.
+    fn new(
+        store: S,
+        params: Params,
+        checkpoints: &[(Height, BlockHash)],
+        window: Option<(usize, Arc<Mutex<Index>>)>,
+        capacity: usize,
+    ) -> Self {
+        let genesis = store.genesis();
         let orphans = HashMap::new();
         let checkpoints = checkpoints.iter().cloned().collect();
 
@@ -91,32 +227,40 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
                 hash: genesis.block_hash(),
                 header: genesis,
             },
-            Vec::with_capacity(length - 1),
+            Vec::with_capacity(capacity.saturating_sub(1)),
         ));
-        let mut headers = HashMap::with_capacity(length);
+        let mut headers = HashMap::with_capacity(capacity);
         // Insert genesis in the headers map, but skip it during iteration.
         headers.insert(chain.head.hash, 0);
 
-        let mut cache = Self {
+        Self {
+            work: genesis.work(),
             chain,
             headers,
             orphans,
+            orphan_limits: (MAX_ORPHANS, ORPHAN_EXPIRY),
             params,
             checkpoints,
             store,
-        };
-
-        for result in cache.store.iter().skip(1) {
-            let (height, header) = result?;
-            let hash = header.block_hash();
-
-            cache.extend_chain(height, hash, header);
+            signet: None,
+            invalid: HashSet::new(),
+            window,
         }
+    }
 
-        assert_eq!(length, cache.chain.len());
-        assert_eq!(length, cache.headers.len());
+    /// Use the given block challenge to validate block solutions. Only used on signets, and
+    /// only if the `signet` feature is enabled.
+    pub fn with_signet_challenge(mut self, challenge: Script) -> Self {
+        self.signet = Some(challenge);
+        self
+    }
 
-        Ok(cache)
+    /// Set the maximum number of orphan blocks to keep, and the time after which orphans
+    /// that haven't connected are expired. Blocks that extend the active chain are always
+    /// accepted.
+    pub fn with_orphan_limits(mut self, max: usize, expiry: LocalDuration) -> Self {
+        self.orphan_limits = (max, expiry);
+        self
     }
 
     /// Iterate over a range of blocks.
@@ -128,16 +272,65 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
     fn range<'a>(
         &'a self,
         range: std::ops::Range<Height>,
-    ) -> impl Iterator<Item = &CachedBlock> + 'a {
+    ) -> impl Iterator<Item = CachedBlock> + 'a {
         assert!(
             range.start <= range.end,
             "BlockCache::range: range start must not be greater than range end"
         );
+        let end = Height::min(range.end, self.height() + 1);
 
-        self.chain
-            .iter()
-            .skip(range.start as usize)
-            .take((range.end - range.start) as usize)
+        (range.start..end).filter_map(move |height| self.block(height))
+    }
+
+    /// Get a block of the active chain by height. Blocks outside of the in-memory window
+    /// are read from the store.
+    fn block(&self, height: Height) -> Option<CachedBlock> {
+        let offset = self.chain.first().height;
+
+        if height >= offset {
+            return self.chain.get((height - offset) as usize).copied();
+        }
+        match self.store.get(height) {
+            Ok(header) => Some(CachedBlock {
+                height,
+                hash: header.block_hash(),
+                header,
+            }),
+            Err(err) => {
+                log::error!(
+                    "Error reading block at height {} from store: {}",
+                    height,
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Get the height of a block on the active chain. Blocks outside of the in-memory window
+    /// are looked up in the index, and checked against the store.
+    fn height_of(&self, hash: &BlockHash) -> Option<Height> {
+        if let Some(height) = self.headers.get(hash) {
+            return Some(*height);
+        }
+        let (_, index) = self.window.as_ref()?;
+        let offset = self.chain.first().height;
+        let candidates = match index
+            .lock()
+            .expect("the index lock is never poisoned")
+            .get(hash)
+        {
+            Ok(candidates) => candidates,
+            Err(err) => {
+                log::error!("Error reading block index: {}", err);
+                return None;
+            }
+        };
+
+        candidates
+            .into_iter()
+            .filter(|h| *h < offset)
+            .find(|h| self.block(*h).map_or(false, |b| b.hash == *hash))
     }
 
     /// Get the median time past for the blocks leading up to the given height.
@@ -171,19 +364,25 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         &mut self,
         header: BlockHeader,
         clock: &impl Clock,
+        source: Option<net::SocketAddr>,
     ) -> Result<ImportResult, Error> {
         let hash = header.block_hash();
-        let tip = self.chain.last();
+        let tip = *self.chain.last();
         let best = tip.hash;
 
-        if self.headers.contains_key(&hash) || self.orphans.contains_key(&hash) {
+        if self.orphans.contains_key(&hash) || self.contains(&hash) {
             return Err(Error::DuplicateBlock(hash));
         }
+        if self.invalid.contains(&hash) || self.invalid.contains(&header.prev_blockhash) {
+            self.invalid.insert(hash);
+
+            return Err(Error::InvalidBlockSolution(hash));
+        }
 
         // Block extends the active chain. We can fully validate it before proceeding.
         // Instead of adding the block to the main chain, we let chain selection do the job.
         if header.prev_blockhash == best {
-            self.validate(tip, &header, clock)?;
+            self.validate(&tip, &header, clock)?;
         }
 
         // Validate that the block's PoW is valid against its difficulty target, and
@@ -210,20 +409,42 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
             },
         }
 
-        if let Some(height) = self.headers.get(&header.prev_blockhash) {
+        let parent = self.height_of(&header.prev_blockhash);
+        if let Some(height) = parent {
             // Don't accept any forks from the main chain, prior to the last checkpoint.
-            if *height < self.last_checkpoint() {
-                return Err(Error::InvalidBlockHeight(*height + 1));
+            if height < self.last_checkpoint() {
+                return Err(Error::InvalidBlockHeight(height + 1));
+            }
+        }
+        // Don't let the orphan set grow without bounds. Blocks that extend the active chain
+        // are always accepted, since they are fully validated. Blocks that extend a known
+        // branch make room by evicting the oldest block that doesn't connect.
+        let (max, _) = self.orphan_limits;
+        if self.orphans.len() >= max && header.prev_blockhash != best {
+            let connects = parent.is_some()
+                || (self.orphans.contains_key(&header.prev_blockhash)
+                    && self.fork(&header.prev_blockhash).is_some());
+
+            match self.oldest_disconnected_orphan() {
+                Some(oldest) if connects => {
+                    self.orphans.remove(&oldest);
+                }
+                _ => return Err(Error::OrphanPoolFull(hash)),
             }
         }
         // We can now insert the header in the orphan set for further processing.
-        self.orphans.insert(hash, header);
+        self.orphans.insert(
+            hash,
+            Orphan {
+                header,
+                source,
+                added: clock.local_time(),
+            },
+        );
 
         // If it doesn't connect to any existing block, there's nothing left to do.
         // We know for a fact we won't discover any new branches.
-        if !self.orphans.contains_key(&header.prev_blockhash)
-            && !self.headers.contains_key(&header.prev_blockhash)
-        {
+        if !self.orphans.contains_key(&header.prev_blockhash) && parent.is_none() {
             return Err(Error::BlockMissing(header.prev_blockhash));
         }
 
@@ -246,7 +467,7 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
             let candidate_work = Branch(&branch.headers).work();
             // Work included on the active chain that would be lost if we switched to the candidate
             // branch.
-            let lost_work = Branch(self.chain_suffix(branch.fork_height)).work();
+            let lost_work = self.work_since(branch.fork_height);
             // Not interested in candidates that result in a shorter chain.
             if candidate_work < lost_work {
                 continue;
@@ -288,7 +509,7 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
 
         if let Some(branch) = best_branch {
             // Stale blocks after potential re-org.
-            let stale = self.switch_to_fork(branch)?;
+            let stale = self.switch_to_fork(branch, clock.local_time())?;
             let height = self.height();
             let hash = branch.tip;
             let header = *branch
@@ -347,17 +568,17 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
             "BlockCache::fork: the provided tip must not be on the active chain"
         );
 
-        while let Some(header) = self.orphans.get(&cursor) {
-            cursor = header.prev_blockhash;
-            headers.push_front(*header);
+        while let Some(orphan) = self.orphans.get(&cursor) {
+            cursor = orphan.header.prev_blockhash;
+            headers.push_front(orphan.header);
         }
 
-        if let Some(height) = self.headers.get(&cursor) {
+        if let Some(height) = self.height_of(&cursor) {
             assert!(!headers.is_empty());
 
             return Some(Candidate {
                 tip,
-                fork_height: *height,
+                fork_height: height,
                 fork_hash: cursor,
                 headers: headers.into(),
             });
@@ -365,6 +586,15 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         None
     }
 
+    /// Find the oldest orphan that doesn't connect to the active chain, if any.
+    fn oldest_disconnected_orphan(&self) -> Option<BlockHash> {
+        self.orphans
+            .iter()
+            .filter(|(hash, _)| self.fork(hash).is_none())
+            .min_by_key(|(_, orphan)| orphan.added)
+            .map(|(hash, _)| *hash)
+    }
+
     /// Validate a candidate branch. This function is useful for chain selection.
     fn validate_branch(&self, candidate: &Candidate, clock: &impl Clock) -> Result<(), Error> {
         let fork_header = self
@@ -373,7 +603,7 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         let mut tip = CachedBlock {
             height: candidate.fork_height,
             hash: candidate.fork_hash,
-            header: *fork_header,
+            header: fork_header,
         };
 
         for header in candidate.headers.iter() {
@@ -397,6 +627,10 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
     ) -> Result<(), Error> {
         assert_eq!(tip.hash, header.prev_blockhash);
 
+        if self.invalid.contains(&header.block_hash()) {
+            return Err(Error::InvalidBlockSolution(header.block_hash()));
+        }
+
         let compact_target = if self.params.allow_min_difficulty_blocks
             && (tip.height + 1) % self.params.difficulty_adjustment_interval() != 0
         {
@@ -462,15 +696,61 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         pow_limit_bits
     }
 
-    /// Rollback active chain to the given height. Returns the list of rolled-back headers.
-    fn rollback(&mut self, height: Height) -> Result<Vec<(Height, BlockHash)>, Error> {
+    /// Rollback active chain to the given height. Rolled-back blocks are kept as orphans.
+    /// Returns the list of rolled-back headers.
+    fn rollback(
+        &mut self,
+        height: Height,
+        now: LocalTime,
+    ) -> Result<Vec<(Height, BlockHash)>, Error> {
         let mut stale = Vec::new();
-
-        for (block, height) in self.chain.tail.drain(height as usize..).zip(height + 1..) {
-            stale.push((height, block.hash));
-
-            self.headers.remove(&block.hash);
-            self.orphans.insert(block.hash, block.header);
+        let offset = self.chain.first().height;
+
+        // On bounded caches, the rollback may go past the start of the in-memory window.
+        // In that case, we reload the window from the store.
+        if height < offset {
+            let (window, _) = self.window.as_ref().expect("the cache is bounded");
+            let start = (height + 1).saturating_sub(*window as Height);
+            let blocks = (height + 1..offset)
+                .chain(start..=height)
+                .map(|h| self.block(h).ok_or(Error::Store(store::Error::Corruption)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let (removed, window) = blocks.split_at((offset - height - 1) as usize);
+
+            for block in removed.iter().chain(self.chain.iter()) {
+                stale.push((block.height, block.hash));
+
+                self.work = self.work - block.work();
+                self.headers.remove(&block.hash);
+                self.orphans.insert(
+                    block.hash,
+                    Orphan {
+                        header: block.header,
+                        source: None,
+                        added: now,
+                    },
+                );
+            }
+            self.chain = NonEmpty::from_slice(window).expect("the window is never empty");
+            self.headers
+                .extend(self.chain.iter().map(|b| (b.hash, b.height)));
+        } else {
+            let drain = (height - offset) as usize..;
+
+            for block in self.chain.tail.drain(drain) {
+                stale.push((block.height, block.hash));
+
+                self.work = self.work - block.work();
+                self.headers.remove(&block.hash);
+                self.orphans.insert(
+                    block.hash,
+                    Orphan {
+                        header: block.header,
+                        source: None,
+                        added: now,
+                    },
+                );
+            }
         }
         self.store.rollback(height)?;
 
@@ -478,15 +758,19 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
     }
 
     /// Activate a fork candidate. Returns the list of rolled-back (stale) headers.
-    fn switch_to_fork(&mut self, branch: &Candidate) -> Result<Vec<(Height, BlockHash)>, Error> {
-        let stale = self.rollback(branch.fork_height)?;
+    fn switch_to_fork(
+        &mut self,
+        branch: &Candidate,
+        now: LocalTime,
+    ) -> Result<Vec<(Height, BlockHash)>, Error> {
+        let stale = self.rollback(branch.fork_height, now)?;
 
         for (i, header) in branch.headers.iter().enumerate() {
             self.extend_chain(
                 branch.fork_height + i as Height + 1,
                 header.block_hash(),
                 *header,
-            );
+            )?;
         }
         self.store.put(branch.headers.iter().cloned())?;
 
@@ -494,30 +778,75 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
     }
 
     /// Extend the active chain with a block.
-    fn extend_chain(&mut self, height: Height, hash: BlockHash, header: BlockHeader) {
+    fn extend_chain(
+        &mut self,
+        height: Height,
+        hash: BlockHash,
+        header: BlockHeader,
+    ) -> Result<(), Error> {
+        if let Some((_, index)) = &self.window {
+            index
+                .lock()
+                .expect("the index lock is never poisoned")
+                .insert(&hash, height)?;
+        }
+        self.orphans.remove(&hash);
+        self.work = self.work + header.work();
+        self.push(height, hash, header);
+
+        Ok(())
+    }
+
+    /// Push a block onto the in-memory chain. On bounded caches, this may evict the oldest
+    /// blocks from memory.
+    fn push(&mut self, height: Height, hash: BlockHash, header: BlockHeader) {
         assert_eq!(header.prev_blockhash, self.chain.last().hash);
 
         self.headers.insert(hash, height);
-        self.orphans.remove(&hash);
         self.chain.push(CachedBlock {
             height,
             hash,
             header,
         });
+
+        // Evict blocks in batches, so that we don't have to shift the window on every block.
+        if let Some((window, _)) = &self.window {
+            if self.chain.len() >= window * 2 {
+                let evicted = self.chain.len() - window;
+                let kept = self.chain.tail.split_off(evicted - 1);
+
+                for block in self.chain.iter() {
+                    // Genesis is always kept in the headers map.
+                    if block.height > 0 {
+                        self.headers.remove(&block.hash);
+                    }
+                }
+                self.chain = NonEmpty::from_vec(kept).expect("the window is never empty");
+            }
+        }
     }
 
-    /// Get the blocks starting from the given height.
-    fn chain_suffix(&self, height: Height) -> &[CachedBlock] {
-        &self.chain.tail[height as usize..]
+    /// Get the total work of the active chain after the given height.
+    fn work_since(&self, height: Height) -> Work {
+        let offset = self.chain.first().height;
+
+        if height >= offset {
+            return Branch(&self.chain.tail[(height - offset) as usize..]).work();
+        }
+        Branch(
+            &self
+                .range(height + 1..self.height() + 1)
+                .collect::<Vec<_>>(),
+        )
+        .work()
     }
-}
 
-impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
-    /// Import blocks into the block tree. Blocks imported this way don't have to form a chain.
-    fn import_blocks<I: Iterator<Item = BlockHeader>, C: Clock>(
+    /// Import blocks into the block tree, attributing orphans to the given source.
+    fn import<I: Iterator<Item = BlockHeader>, C: Clock>(
         &mut self,
         chain: I,
         context: &C,
+        source: Option<net::SocketAddr>,
     ) -> Result<ImportResult, Error> {
         let mut seen = BTreeSet::new();
         let mut reverted = BTreeSet::new();
@@ -527,7 +856,7 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
         let mut best_header = self.chain.last().header;
 
         for (i, header) in chain.enumerate() {
-            match self.import_block(header, context) {
+            match self.import_block(header, context, source) {
                 Ok(ImportResult::TipChanged(header, hash, height, r, c)) => {
                     seen.extend(c.iter().map(|(_, h)| h.block_hash()));
                     reverted.extend(r);
@@ -564,6 +893,51 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
             Ok(ImportResult::TipUnchanged)
         }
     }
+}
+
+impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
+    /// Import blocks into the block tree. Blocks imported this way don't have to form a chain.
+    fn import_blocks<I: Iterator<Item = BlockHeader>, C: Clock>(
+        &mut self,
+        chain: I,
+        context: &C,
+    ) -> Result<ImportResult, Error> {
+        self.import(chain, context, None)
+    }
+
+    /// Import blocks received from a peer into the block tree.
+    fn import_blocks_from<I: Iterator<Item = BlockHeader>, C: Clock>(
+        &mut self,
+        chain: I,
+        context: &C,
+        peer: net::SocketAddr,
+    ) -> Result<ImportResult, Error> {
+        self.import(chain, context, Some(peer))
+    }
+
+    /// Expire orphans that were added more than the orphan expiry ago, and still don't connect
+    /// to the active chain.
+    fn expire_orphans(&mut self, now: LocalTime) -> Vec<(net::SocketAddr, usize)> {
+        let (_, expiry) = self.orphan_limits;
+        let mut peers = BTreeMap::new();
+
+        // Blocks that connect to the active chain via other orphans are stale or side-branch
+        // blocks, and are kept, since they may still become part of the active chain.
+        let expired = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| orphan.added + expiry <= now)
+            .filter(|(hash, _)| self.fork(hash).is_none())
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>();
+
+        for hash in expired {
+            if let Some(peer) = self.orphans.remove(&hash).and_then(|o| o.source) {
+                *peers.entry(peer).or_default() += 1;
+            }
+        }
+        peers.into_iter().collect()
+    }
 
     /// Extend the active chain.
     fn extend_tip<C: Clock>(
@@ -571,14 +945,14 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
         header: BlockHeader,
         clock: &C,
     ) -> Result<ImportResult, Error> {
-        let tip = self.chain.last();
+        let tip = *self.chain.last();
         let hash = header.block_hash();
 
         if header.prev_blockhash == tip.hash {
             let height = tip.height + 1;
 
-            self.validate(tip, &header, clock)?;
-            self.extend_chain(height, hash, header);
+            self.validate(&tip, &header, clock)?;
+            self.extend_chain(height, hash, header)?;
             self.store.put(std::iter::once(header))?;
 
             Ok(ImportResult::TipChanged(
@@ -593,17 +967,63 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
         }
     }
 
+    /// Validate a full block. This checks that the block's transactions match its header, and
+    /// on signets, verifies the block solution, if the `signet` feature is enabled.
+    fn validate_block(&self, block: &Block) -> Result<(), Error> {
+        let hash = block.block_hash();
+
+        // The body could have been tampered with by the peer that sent it, in which case the
+        // header itself may still be valid.
+        if !block.check_merkle_root() || !block.check_witness_commitment() {
+            return Err(Error::InvalidBlockBody(hash));
+        }
+
+        if let Some(challenge) = &self.signet {
+            // The genesis block doesn't have a solution.
+            if hash != self.genesis().block_hash() && !is_valid_solution(block, challenge) {
+                return Err(Error::InvalidBlockSolution(hash));
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark a block as invalid. If the block is part of the active chain, the chain is rolled
+    /// back to its parent. The block and its descendants are dropped, and won't be imported
+    /// again.
+    fn invalidate_block(
+        &mut self,
+        hash: &BlockHash,
+        now: LocalTime,
+    ) -> Result<Vec<(Height, BlockHash)>, Error> {
+        self.invalid.insert(*hash);
+        self.orphans.remove(hash);
+
+        let height = match self.height_of(hash) {
+            // The genesis block is always valid.
+            Some(height) if height > 0 => height,
+            _ => return Ok(vec![]),
+        };
+        let reverted = self.rollback(height - 1, now)?;
+
+        for (_, hash) in &reverted {
+            self.orphans.remove(hash);
+        }
+        Ok(reverted)
+    }
+
     /// Get a block by hash. Only searches the active chain.
-    fn get_block(&self, hash: &BlockHash) -> Option<(Height, &BlockHeader)> {
-        self.headers
-            .get(hash)
-            .and_then(|height| self.chain.get(*height as usize))
-            .map(|blk| (blk.height, &blk.header))
+    fn get_block(&self, hash: &BlockHash) -> Option<(Height, BlockHeader)> {
+        self.height_of(hash)
+            .and_then(|height| self.block(height))
+            .map(|blk| (blk.height, blk.header))
     }
 
     /// Get a block by height.
-    fn get_block_by_height(&self, height: Height) -> Option<&BlockHeader> {
-        self.chain.get(height as usize).map(|b| &b.header)
+    fn get_block_by_height(&self, height: Height) -> Option<BlockHeader> {
+        if height > self.height() {
+            return None;
+        }
+        self.block(height).map(|b| b.header)
     }
 
     /// Get the best block hash and header.
@@ -612,13 +1032,13 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
     }
 
     /// Get the genesis block header.
-    fn genesis(&self) -> &BlockHeader {
-        &self.chain.first().header
+    fn genesis(&self) -> BlockHeader {
+        self.store.genesis()
     }
 
     /// Iterate over the longest chain, starting from genesis.
     fn iter<'a>(&'a self) -> Box<dyn DoubleEndedIterator<Item = (Height, BlockHeader)> + 'a> {
-        Box::new(Iter::new(&self.chain).map(|(i, h)| (i, h.header)))
+        Box::new((0..=self.height()).filter_map(move |h| self.block(h).map(|b| (h, b.header))))
     }
 
     /// Iterate over a range of blocks.
@@ -627,14 +1047,16 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
         range: std::ops::Range<Height>,
     ) -> Box<dyn Iterator<Item = (Height, BlockHash)> + 'a> {
         Box::new(
-            self.chain
-                .iter()
-                .map(|block| (block.height, block.hash))
-                .skip(range.start as usize)
-                .take((range.end - range.start) as usize),
+            BlockCache::range(self, range.start..Height::max(range.start, range.end))
+                .map(|block| (block.height, block.hash)),
         )
     }
 
+    /// Get the total work of the longest chain.
+    fn chain_work(&self) -> Work {
+        self.work
+    }
+
     /// Return the height of the longest chain.
     fn height(&self) -> Height {
         self.chain.last().height
@@ -659,12 +1081,63 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
 
     /// Check whether this block hash is known.
     fn is_known(&self, hash: &BlockHash) -> bool {
-        self.headers.contains_key(hash) || self.orphans.contains_key(hash)
+        self.orphans.contains_key(hash) || self.contains(hash)
     }
 
     /// Check whether this block hash is part of the active chain.
     fn contains(&self, hash: &BlockHash) -> bool {
-        self.headers.contains_key(hash)
+        self.height_of(hash).is_some()
+    }
+
+    /// Find the point at which a block forks off the active chain. Stale blocks are looked up
+    /// in the orphan set.
+    fn find_fork(&self, hash: &BlockHash) -> Option<(Height, BlockHeader)> {
+        if let Some(block) = self.get_block(hash) {
+            return Some(block);
+        }
+        let fork = self.fork(hash)?;
+
+        self.get_block_by_height(fork.fork_height)
+            .map(|header| (fork.fork_height, header))
+    }
+
+    /// Check whether a block is an ancestor of another block. Works for stale blocks as well
+    /// as blocks of the active chain.
+    fn is_ancestor(&self, ancestor: &BlockHash, hash: &BlockHash) -> bool {
+        let (fork_height, branch) = if let Some(height) = self.height_of(hash) {
+            (height, Vec::new())
+        } else if let Some(fork) = self.fork(hash) {
+            (fork.fork_height, fork.headers)
+        } else {
+            return false;
+        };
+
+        if branch.iter().any(|h| h.block_hash() == *ancestor) {
+            return true;
+        }
+        matches!(self.height_of(ancestor), Some(height) if height <= fork_height)
+    }
+
+    /// Get the tips of the known stale branches, ordered by height.
+    fn stale_tips(&self) -> Vec<(Height, BlockHeader)> {
+        let parents = self
+            .orphans
+            .values()
+            .map(|o| o.header.prev_blockhash)
+            .collect::<HashSet<_>>();
+        let mut tips = self
+            .orphans
+            .keys()
+            .filter(|hash| !parents.contains(*hash))
+            .filter_map(|hash| self.fork(hash))
+            .filter_map(|fork| {
+                let height = fork.fork_height + fork.headers.len() as Height;
+                fork.headers.last().map(|header| (height, *header))
+            })
+            .collect::<Vec<_>>();
+
+        tips.sort_by_key(|(height, header)| (*height, header.block_hash()));
+        tips
     }
 
     /// Return headers after the first known hash in the locators list, and until the stop hash
@@ -685,7 +1158,7 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
     ) -> Vec<BlockHeader> {
         if locators.is_empty() {
             if let Some((_, header)) = self.get_block(&stop_hash) {
-                return vec![*header];
+                return vec![header];
             }
             return vec![];
         }
@@ -730,7 +1203,7 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
                 // older than our last checkpoint.
                 break;
             }
-            if let Some(blk) = self.chain.get(height as usize) {
+            if let Some(blk) = self.block(height) {
                 hashes.push(blk.hash);
             }
         }
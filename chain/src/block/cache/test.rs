@@ -1,6 +1,6 @@
 use super::BlockCache;
 
-use nakamoto_common::block::time::{AdjustedTime, Clock, LocalTime};
+use nakamoto_common::block::time::{AdjustedTime, Clock, LocalDuration, LocalTime};
 use nakamoto_common::block::tree::{BlockTree, Error, ImportResult};
 use nakamoto_common::block::{BlockTime, Height, Target};
 use nakamoto_common::nonempty::NonEmpty;
@@ -26,6 +26,7 @@ use bitcoin::blockdata::constants;
 use bitcoin::consensus::params::Params;
 use bitcoin::hash_types::{BlockHash, TxMerkleNode};
 use bitcoin_hashes::hex::FromHex;
+use bitcoin_hashes::Hash;
 
 use bitcoin::util::uint::Uint256;
 
@@ -82,12 +83,12 @@ impl BlockTree for HeightCache {
         unimplemented!()
     }
 
-    fn get_block(&self, _hash: &BlockHash) -> Option<(Height, &BlockHeader)> {
+    fn get_block(&self, _hash: &BlockHash) -> Option<(Height, BlockHeader)> {
         unimplemented!()
     }
 
-    fn get_block_by_height(&self, height: Height) -> Option<&BlockHeader> {
-        self.headers.get(&height)
+    fn get_block_by_height(&self, height: Height) -> Option<BlockHeader> {
+        self.headers.get(&height).copied()
     }
 
     fn last_checkpoint(&self) -> Height {
@@ -345,7 +346,7 @@ fn prop_block_missing(import: BlockImport) -> bool {
     block::solve(&mut header);
 
     matches! {
-        cache.import_block(header, &ctx).err(),
+        cache.import_block(header, &ctx, None).err(),
         Some(Error::BlockMissing(hash)) if hash == prev_blockhash
     }
 }
@@ -354,9 +355,9 @@ fn prop_block_missing(import: BlockImport) -> bool {
 fn prop_invalid_block_target(import: BlockImport) -> bool {
     let BlockImport(mut cache, header) = import;
     let ctx = AdjustedTime::<net::SocketAddr>::new(LOCAL_TIME);
-    let genesis = *cache.genesis();
+    let genesis = cache.genesis();
 
-    assert!(cache.clone().import_block(header, &ctx).is_ok());
+    assert!(cache.clone().import_block(header, &ctx, None).is_ok());
 
     let header = BlockHeader {
         bits: genesis.bits - 1,
@@ -364,7 +365,7 @@ fn prop_invalid_block_target(import: BlockImport) -> bool {
     };
 
     matches! {
-        cache.import_block(header, &ctx).err(),
+        cache.import_block(header, &ctx, None).err(),
         Some(Error::InvalidBlockTarget(actual, expected))
             if actual == BlockHeader::u256_from_compact_target(genesis.bits - 1)
                 && expected == genesis.target()
@@ -406,7 +407,7 @@ fn test_invalid_orphan_block_target() {
     block::solve(&mut header);
 
     assert!(matches!(
-        cache.import_block(header, &clock).err(),
+        cache.import_block(header, &clock, None).err(),
         Some(Error::BlockMissing(h)) if h == prev_blockhash
     ));
 
@@ -418,7 +419,7 @@ fn test_invalid_orphan_block_target() {
     block::solve(&mut header);
 
     // Even though the header can't be connected to the main chain, we still get an error.
-    match cache.import_block(header, &clock).unwrap_err() {
+    match cache.import_block(header, &clock, None).unwrap_err() {
         Error::InvalidBlockTarget(actual, expected) => {
             assert_eq!(
                 BlockHeader::compact_target_from_u256(&actual),
@@ -460,13 +461,101 @@ fn test_invalid_orphan_block_pow() {
 
     assert!(
         matches!(
-            cache.import_block(header, &clock).err(),
+            cache.import_block(header, &clock, None).err(),
             Some(Error::InvalidBlockPoW)
         ),
         "the orphan header is not imported"
     );
 }
 
+#[test]
+fn test_orphan_limits() {
+    let network = bitcoin::Network::Regtest;
+    let genesis = constants::genesis_block(network).header;
+    let store = store::Memory::new(NonEmpty::new(genesis));
+    let mut clock = AdjustedTime::<net::SocketAddr>::new(LOCAL_TIME);
+    let params = Params::new(network);
+    let peer: net::SocketAddr = ([8, 8, 8, 8], 8333).into();
+    let expiry = LocalDuration::from_mins(1);
+
+    let mut cache = BlockCache::from(store, params, &[])
+        .unwrap()
+        .with_orphan_limits(2, expiry);
+
+    // Headers that don't connect to anything we have.
+    let orphans = (0..3)
+        .map(|i| {
+            let mut header = BlockHeader {
+                prev_blockhash: BlockHash::hash(&[i]),
+                bits: genesis.bits,
+                time: genesis.time,
+                version: genesis.version,
+                nonce: 0,
+                merkle_root: TxMerkleNode::default(),
+            };
+            block::solve(&mut header);
+            header
+        })
+        .collect::<Vec<_>>();
+
+    for orphan in &orphans[..2] {
+        assert_matches!(
+            cache.import_block(*orphan, &clock, Some(peer)),
+            Err(Error::BlockMissing(_))
+        );
+    }
+    assert_matches!(
+        cache.import_block(orphans[2], &clock, Some(peer)),
+        Err(Error::OrphanPoolFull(h)) if h == orphans[2].block_hash()
+    );
+    assert!(!cache.is_known(&orphans[2].block_hash()));
+
+    // Blocks extending the active chain are accepted, even when the pool is full.
+    let mut header = BlockHeader {
+        prev_blockhash: genesis.block_hash(),
+        time: genesis.time + TARGET_SPACING,
+        ..orphans[0]
+    };
+    block::solve(&mut header);
+    assert_matches!(
+        cache.import_block(header, &clock, Some(peer)),
+        Ok(ImportResult::TipChanged(..))
+    );
+
+    // Blocks extending a known branch evict the oldest orphan that doesn't connect.
+    let mut side = BlockHeader {
+        time: header.time + 1,
+        ..header
+    };
+    block::solve(&mut side);
+    assert_matches!(cache.import_block(side, &clock, Some(peer)), Ok(_));
+    assert!(cache.is_known(&side.block_hash()));
+    assert_eq!(
+        orphans[..2]
+            .iter()
+            .filter(|o| cache.is_known(&o.block_hash()))
+            .count(),
+        1
+    );
+
+    // Orphans are expired once they're old enough, and attributed to the peer that sent them.
+    // Side-branch blocks connect to the active chain, and are kept.
+    assert!(cache.expire_orphans(LOCAL_TIME).is_empty());
+    assert_eq!(cache.expire_orphans(LOCAL_TIME + expiry), vec![(peer, 1)]);
+    assert!(!cache.is_known(&orphans[0].block_hash()));
+    assert!(!cache.is_known(&orphans[1].block_hash()));
+    assert!(cache.is_known(&header.block_hash()));
+    assert!(cache.is_known(&side.block_hash()));
+
+    // Now that there's room, orphans are accepted again.
+    clock.set_local_time(LOCAL_TIME + expiry);
+    assert_matches!(
+        cache.import_block(orphans[2], &clock, Some(peer)),
+        Err(Error::BlockMissing(_))
+    );
+    assert!(cache.is_known(&orphans[2].block_hash()));
+}
+
 #[quickcheck]
 fn prop_invalid_block_pow(import: BlockImport) -> bool {
     let BlockImport(mut cache, header) = import;
@@ -479,7 +568,7 @@ fn prop_invalid_block_pow(import: BlockImport) -> bool {
     }
 
     matches! {
-        cache.import_block(header, &ctx).err(),
+        cache.import_block(header, &ctx, None).err(),
         Some(Error::InvalidBlockPoW)
     }
 }
@@ -577,7 +666,7 @@ fn prop_cache_import_ordered() {
             .import_blocks(headers.tail.iter().cloned(), &clock)
             .unwrap();
 
-        cache.genesis() == &headers.head
+        cache.genesis() == headers.head
             && cache.tip() == (tip.block_hash(), tip)
             && cache
                 .iter()
@@ -895,13 +984,13 @@ fn test_cache_import_unchanged() {
     let a4 = a3.next(g);
     let a5 = a4.next(g);
 
-    let e = cache.import_block(a4.block(), &ctx).err();
+    let e = cache.import_block(a4.block(), &ctx, None).err();
     assert_matches!(e, Some(Error::BlockMissing(h)) if h == a3.block().block_hash());
 
-    let r = cache.import_block(a5.block(), &ctx).unwrap();
+    let r = cache.import_block(a5.block(), &ctx, None).unwrap();
     assert_matches!(r, ImportResult::TipUnchanged);
 
-    let r = cache.import_block(a3.block(), &ctx).unwrap();
+    let r = cache.import_block(a3.block(), &ctx, None).unwrap();
     assert_matches!(r, ImportResult::TipChanged { .. });
 }
 
@@ -928,13 +1017,13 @@ fn test_cache_import_height_unchanged() {
         }
     };
 
-    cache.import_block(a1.block(), &ctx).unwrap();
-    cache.import_block(a2.block(), &ctx).unwrap();
+    cache.import_block(a1.block(), &ctx, None).unwrap();
+    cache.import_block(a2.block(), &ctx, None).unwrap();
 
     assert_eq!(cache.tip().0, a2.hash);
 
     let height = cache.height();
-    let result = cache.import_block(b2.block(), &ctx).unwrap();
+    let result = cache.import_block(b2.block(), &ctx, None).unwrap();
 
     assert_eq!(cache.tip().0, b2.hash);
     assert_eq!(
@@ -1001,6 +1090,128 @@ fn test_cache_import_back_and_forth() {
     assert_matches!(r, ImportResult::TipChanged { .. });
 }
 
+#[test]
+fn test_cache_invalidate_block() {
+    let network = bitcoin::Network::Regtest;
+    let genesis = constants::genesis_block(network).header;
+    let params = Params::new(network);
+    let store = store::Memory::new(NonEmpty::new(genesis));
+    let ctx = AdjustedTime::<net::SocketAddr>::new(LOCAL_TIME);
+    let mut cache = BlockCache::from(store, params, &[]).unwrap();
+
+    let g = &mut rand::thread_rng();
+
+    // a0 <- a1 <- a2 <- a3 *
+    let a0 = Tree::new(genesis);
+    let a1 = a0.next(g);
+    let a2 = a1.next(g);
+    let a3 = a2.next(g);
+
+    cache.import_blocks(a0.branch([&a1, &a3]), &ctx).unwrap();
+    assert_eq!(cache.tip().0, a3.hash);
+
+    // a0 <- a1 *
+    let reverted = cache.invalidate_block(&a2.hash, LOCAL_TIME).unwrap();
+    assert_eq!(reverted, vec![(2, a2.hash), (3, a3.hash)]);
+    assert_eq!(cache.tip().0, a1.hash);
+    assert!(!cache.is_known(&a2.hash));
+    assert!(!cache.is_known(&a3.hash));
+
+    // The invalid block and its descendants are not imported again.
+    assert_matches!(
+        cache.import_blocks(a1.branch([&a2, &a3]), &ctx),
+        Err(Error::BlockImportAborted(err, 0, _)) if matches!(*err, Error::InvalidBlockSolution(hash) if hash == a2.hash)
+    );
+    assert_matches!(
+        cache.import_blocks(a2.branch([&a3, &a3]), &ctx),
+        Err(Error::BlockImportAborted(err, 0, _)) if matches!(*err, Error::InvalidBlockSolution(hash) if hash == a3.hash)
+    );
+    assert_eq!(cache.tip().0, a1.hash);
+
+    // a0 <- a1 <- b2 *
+    let b2 = a1.next(g);
+
+    cache.import_blocks(a1.branch([&b2, &b2]), &ctx).unwrap();
+    assert_eq!(cache.tip().0, b2.hash);
+}
+
+#[test]
+fn test_cache_validate_block_body() {
+    use nakamoto_test::block::gen;
+
+    let network = bitcoin::Network::Regtest;
+    let genesis = constants::genesis_block(network).header;
+    let params = Params::new(network);
+    let store = store::Memory::new(NonEmpty::new(genesis));
+    let cache = BlockCache::from(store, params, &[]).unwrap();
+
+    let mut rng = fastrand::Rng::new();
+    let block = gen::block(&genesis, &mut rng);
+
+    assert_matches!(cache.validate_block(&block), Ok(()));
+
+    // A body that doesn't match the header is rejected, without the header being at fault.
+    let mut tampered = block.clone();
+    tampered.txdata.push(gen::transaction(&mut rng));
+
+    assert_matches!(
+        cache.validate_block(&tampered),
+        Err(Error::InvalidBlockBody(hash)) if hash == block.block_hash()
+    );
+}
+
+#[test]
+fn test_cache_fork_queries() {
+    let network = bitcoin::Network::Regtest;
+    let genesis = constants::genesis_block(network).header;
+    let params = Params::new(network);
+    let store = store::Memory::new(NonEmpty::new(genesis));
+    let ctx = AdjustedTime::<net::SocketAddr>::new(LOCAL_TIME);
+    let mut cache = BlockCache::from(store, params, &[]).unwrap();
+
+    let g = &mut rand::thread_rng();
+
+    // a0 <- a1 <- a2 <- a3 *
+    //           \
+    //            <- b2
+    //     \
+    //      <- c1
+    let a0 = Tree::new(genesis);
+    let a1 = a0.next(g);
+    let a2 = a1.next(g);
+    let a3 = a2.next(g);
+    let b2 = a1.next(g);
+    let c1 = a0.next(g);
+
+    cache.import_blocks(a0.branch([&a1, &a3]), &ctx).unwrap();
+    cache.import_blocks(a0.branch([&b2, &b2]), &ctx).unwrap();
+    cache.import_blocks(a0.branch([&c1, &c1]), &ctx).unwrap();
+
+    assert_eq!(cache.tip().0, a3.hash);
+    assert_eq!(cache.find_fork(&a2.hash), Some((2, a2.block())));
+    assert_eq!(cache.find_fork(&b2.hash), Some((1, a1.block())));
+    assert_eq!(cache.find_fork(&c1.hash), Some((0, a0.block())));
+    assert_eq!(cache.find_fork(&a3.next(g).hash), None);
+
+    assert!(cache.is_ancestor(&a3.hash, &a3.hash));
+    assert!(cache.is_ancestor(&a1.hash, &a3.hash));
+    assert!(cache.is_ancestor(&a1.hash, &b2.hash));
+    assert!(cache.is_ancestor(&b2.hash, &b2.hash));
+    assert!(!cache.is_ancestor(&a2.hash, &b2.hash));
+    assert!(!cache.is_ancestor(&b2.hash, &a3.hash));
+    assert!(!cache.is_ancestor(&a3.hash, &a1.hash));
+    assert!(!cache.is_ancestor(&a1.hash, &c1.hash));
+
+    assert_eq!(
+        cache
+            .stale_tips()
+            .into_iter()
+            .map(|(height, header)| (height, header.block_hash()))
+            .collect::<Vec<_>>(),
+        vec![(1, c1.hash), (2, b2.hash)]
+    );
+}
+
 #[test]
 fn test_cache_import_equal_difficulty_blocks() {
     let mut headers = vec![
@@ -1107,7 +1318,7 @@ fn test_cache_import_with_checkpoints() {
         BlockCache::from(store.clone(), params.clone(), &[(1, Default::default())]).unwrap();
     assert!(
         matches! {
-            cache.import_block(a1.block(), &ctx),
+            cache.import_block(a1.block(), &ctx, None),
             Err(Error::InvalidBlockHash(hash, 1)) if hash == a1.hash
         },
         "An incorrect checkpoint at height 1 causes an error"
@@ -1153,7 +1364,7 @@ fn test_cache_import_invalid_fork() {
         .unwrap_err();
     assert_eq!(cache.tip().0, a3.hash);
 
-    cache.import_block(c2.block(), &ctx).unwrap();
+    cache.import_block(c2.block(), &ctx, None).unwrap();
     assert_eq!(
         cache.tip().0,
         c4.hash,
@@ -1171,7 +1382,7 @@ fn test_cache_import_invalid_fork() {
     let b6 = b5.next(g);
 
     cache.import_blocks(a0.branch([&b4, &b6]), &ctx).unwrap();
-    cache.import_block(b3.block(), &ctx).unwrap_err();
+    cache.import_block(b3.block(), &ctx, None).unwrap_err();
     assert_eq!(cache.tip().0, c4.hash, "Don't switch to invalid fork");
 }
 
@@ -1208,14 +1419,14 @@ fn test_cache_import_fork_with_checkpoints() {
 
     assert!(
         matches! {
-            cache.import_block(b1.block(), &ctx),
+            cache.import_block(b1.block(), &ctx, None),
             Err(Error::InvalidBlockHeight(1))
         },
         "Can't fork passed the last checkpoint"
     );
     assert!(
         matches! {
-            cache.import_block(c2.block(), &ctx),
+            cache.import_block(c2.block(), &ctx, None),
             Err(Error::InvalidBlockHeight(2))
         },
         "Can't fork passed the last checkpoint"
@@ -1279,7 +1490,7 @@ fn test_cache_import_fork_with_future_checkpoint() {
 
     // We can do this because we don't know yet whether a2 or b2 is the correct branch.
     cache
-        .import_block(b2.block(), &ctx)
+        .import_block(b2.block(), &ctx, None)
         .expect("we can import a fork before the next checkpoint");
 }
 
@@ -1303,18 +1514,18 @@ fn test_cache_import_duplicate() {
     let a3 = a2.next(g);
 
     assert!(matches! {
-        cache.import_block(a1.block(), &ctx), Ok(_)
+        cache.import_block(a1.block(), &ctx, None), Ok(_)
     });
     assert!(matches! {
-        cache.import_block(a1.block(), &ctx),
+        cache.import_block(a1.block(), &ctx, None),
         Err(Error::DuplicateBlock(h)) if h == a1.hash
     });
 
     assert!(matches! {
-        cache.import_block(a2.block(), &ctx), Ok(_)
+        cache.import_block(a2.block(), &ctx, None), Ok(_)
     });
     assert!(matches! {
-        cache.import_block(a2.block(), &ctx), Err(Error::DuplicateBlock(_))
+        cache.import_block(a2.block(), &ctx, None), Err(Error::DuplicateBlock(_))
     });
 
     // a0 <- a1 <- a2 <- a3 *
@@ -1323,14 +1534,14 @@ fn test_cache_import_duplicate() {
     let b3 = a1.next(g);
 
     assert!(matches! {
-        cache.import_block(b3.block(), &ctx), Ok(_)
+        cache.import_block(b3.block(), &ctx, None), Ok(_)
     });
     assert!(matches! {
-        cache.import_block(b3.block(), &ctx),
+        cache.import_block(b3.block(), &ctx, None),
         Err(Error::DuplicateBlock(h)) if h == b3.hash
     });
     assert!(matches! {
-        cache.import_block(a0.block(), &ctx),
+        cache.import_block(a0.block(), &ctx, None),
         Err(Error::DuplicateBlock(h)) if h == a0.hash
     });
 }
@@ -1549,3 +1760,170 @@ fn test_cache_locate_headers() {
         "If the stop height is equal to the start height, we don't expect anything"
     );
 }
+
+#[test]
+fn test_bounded_from_store() {
+    let network = bitcoin::Network::Bitcoin;
+    let genesis = constants::genesis_block(network).header;
+    let params = Params::new(network);
+    let path = &*nakamoto_test::headers::PATH;
+    let tmp = tempfile::tempdir().unwrap();
+
+    let full = BlockCache::from(
+        store::File::open(path, genesis).unwrap(),
+        params.clone(),
+        &[],
+    )
+    .unwrap();
+    let index = store::Index::open(tmp.path().join("headers.idx")).unwrap();
+    let bounded = BlockCache::bounded(
+        store::File::open(path, genesis).unwrap(),
+        index,
+        params,
+        &[],
+        16,
+    )
+    .unwrap();
+
+    // Only the most recent blocks are kept in memory.
+    assert!(bounded.chain.len() < 32);
+    assert!(bounded.headers.len() <= 32);
+
+    assert_eq!(bounded.tip(), full.tip());
+    assert_eq!(bounded.chain_work(), full.chain_work());
+    assert_eq!(full.chain_work(), model_work(&full));
+    assert_eq!(bounded.genesis(), full.genesis());
+    assert_eq!(
+        bounded.iter().collect::<Vec<_>>(),
+        full.iter().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        bounded.iter().rev().take(64).collect::<Vec<_>>(),
+        full.iter().rev().take(64).collect::<Vec<_>>()
+    );
+
+    for (height, header) in full.iter() {
+        let hash = header.block_hash();
+
+        assert_eq!(bounded.get_block(&hash), Some((height, header)));
+        assert!(bounded.contains(&hash));
+    }
+    assert!(!bounded.is_known(&BlockHash::default()));
+
+    for height in 1..=full.height() {
+        assert_eq!(
+            bounded.median_time_past(height),
+            full.median_time_past(height)
+        );
+    }
+    let height = full.height();
+    let locators = [full.get_block_by_height(10).unwrap().block_hash()];
+
+    assert_eq!(bounded.locator_hashes(height), full.locator_hashes(height));
+    assert_eq!(
+        bounded.locate_headers(&locators, BlockHash::default(), 2000),
+        full.locate_headers(&locators, BlockHash::default(), 2000)
+    );
+    assert_eq!(
+        BlockTree::range(&bounded, 100..120).collect::<Vec<_>>(),
+        BlockTree::range(&full, 100..120).collect::<Vec<_>>()
+    );
+
+    // The index is persisted, and matches the chain.
+    drop(bounded);
+    let index = store::Index::open(tmp.path().join("headers.idx")).unwrap();
+    assert_eq!(index.tip(), (full.height(), full.tip().0));
+    assert_eq!(index.len(), full.height());
+}
+
+#[test]
+fn test_bounded_import_reorg() {
+    let network = bitcoin::Network::Regtest;
+    let genesis = constants::genesis_block(network).header;
+    let params = Params::new(network);
+    let ctx = AdjustedTime::<net::SocketAddr>::new(LOCAL_TIME);
+    let tmp = tempfile::tempdir().unwrap();
+    let g = &mut rand::thread_rng();
+
+    let mut full = BlockCache::from(
+        store::Memory::new(NonEmpty::new(genesis)),
+        params.clone(),
+        &[],
+    )
+    .unwrap();
+    let mut bounded = BlockCache::bounded(
+        store::Memory::new(NonEmpty::new(genesis)),
+        store::Index::open(tmp.path().join("headers.idx")).unwrap(),
+        params,
+        &[],
+        2,
+    )
+    .unwrap();
+
+    let a0 = Tree::new(genesis);
+
+    // a0 <- a1 <- ... <- a8 *
+    let mut a = vec![a0.next(g)];
+    for _ in 1..8 {
+        a.push(a.last().unwrap().next(g));
+    }
+    let expected = full.import_blocks(a0.branch([&a[0], &a[7]]), &ctx).unwrap();
+    let actual = bounded
+        .import_blocks(a0.branch([&a[0], &a[7]]), &ctx)
+        .unwrap();
+
+    assert_eq!(actual, expected);
+    assert_eq!(bounded.tip(), full.tip());
+    assert!(bounded.chain.len() < 4);
+
+    // Fork off a1, past the in-memory window.
+    //
+    // a0 <- a1 <- ... <- a8
+    //          \
+    //           <- b2 <- ... <- b10 *
+    let mut b = vec![a[0].next(g)];
+    for _ in 1..9 {
+        b.push(b.last().unwrap().next(g));
+    }
+    let expected = full.import_blocks(a0.branch([&b[0], &b[8]]), &ctx).unwrap();
+    let actual = bounded
+        .import_blocks(a0.branch([&b[0], &b[8]]), &ctx)
+        .unwrap();
+
+    assert_matches!(expected, ImportResult::TipChanged(_, _, 10, _, _));
+    assert_eq!(actual, expected);
+    assert_eq!(bounded.tip(), full.tip());
+    assert_eq!(bounded.chain_work(), full.chain_work());
+    assert_eq!(full.chain_work(), model_work(&full));
+
+    // Switch back to the original chain.
+    for _ in 0..4 {
+        a.push(a.last().unwrap().next(g));
+    }
+    let expected = full
+        .import_blocks(a0.branch([&a[8], &a[11]]), &ctx)
+        .unwrap();
+    let actual = bounded
+        .import_blocks(a0.branch([&a[8], &a[11]]), &ctx)
+        .unwrap();
+
+    assert_matches!(expected, ImportResult::TipChanged(_, _, 12, _, _));
+    assert_eq!(actual, expected);
+    assert_eq!(bounded.tip(), full.tip());
+    assert_eq!(
+        bounded.iter().collect::<Vec<_>>(),
+        full.iter().collect::<Vec<_>>()
+    );
+
+    for tree in a.iter().chain(b.iter()) {
+        assert_eq!(bounded.contains(&tree.hash), full.contains(&tree.hash));
+        assert_eq!(bounded.is_known(&tree.hash), full.is_known(&tree.hash));
+        assert_eq!(bounded.get_block(&tree.hash), full.get_block(&tree.hash));
+    }
+}
+
+/// Compute the chain work of a block tree, without relying on its own accounting.
+fn model_work<T: BlockTree>(tree: &T) -> Uint256 {
+    tree.iter()
+        .fold(Uint256::default(), |work, (_, header)| work + header.work())
+}
@@ -2,8 +2,10 @@
 
 pub use nakamoto_common::block::store::*;
 
+pub mod index;
 pub mod io;
 pub mod memory;
 
+pub use index::Index;
 pub use io::File;
 pub use memory::Memory;
@@ -0,0 +1,333 @@
+//! Persistent block hash index.
+//!
+//! Maps block hashes to heights, so that a block tree doesn't have to keep an in-memory
+//! index of the whole chain. The index is an open-addressing hash table stored in a file,
+//! keyed by the first eight bytes of the block hash.
+//!
+//! Entries are never removed: when the chain is rolled back, stale entries are left in place.
+//! Lookups therefore return *candidate* heights, which must be checked against the header
+//! store by the caller.
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use bitcoin::hash_types::BlockHash;
+use bitcoin_hashes::Hash;
+
+use nakamoto_common::block::store::Error;
+use nakamoto_common::block::Height;
+
+/// Initial number of slots in a new index.
+pub const DEFAULT_CAPACITY: u64 = 1 << 16;
+
+/// Size of the index file header, in bytes.
+const HEADER_SIZE: u64 = 56;
+/// Size of an index slot, in bytes.
+const SLOT_SIZE: u64 = 12;
+
+/// A persistent block hash index.
+#[derive(Debug)]
+pub struct Index {
+    path: PathBuf,
+    file: fs::File,
+    /// Number of slots.
+    capacity: u64,
+    /// Number of occupied slots.
+    len: u64,
+    /// Height of the last indexed block.
+    height: Height,
+    /// Hash of the last indexed block.
+    tip: BlockHash,
+}
+
+impl Index {
+    /// Open an index at the given path, creating it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)?;
+
+        if file.metadata()?.len() == 0 {
+            return Self::create(path, file, DEFAULT_CAPACITY);
+        }
+
+        let mut buf = [0; HEADER_SIZE as usize];
+        file.read_exact(&mut buf)?;
+
+        let capacity = u64::from_le_bytes(field(&buf[0..8]));
+        let len = u64::from_le_bytes(field(&buf[8..16]));
+        let height = u64::from_le_bytes(field(&buf[16..24]));
+        let tip = BlockHash::from_slice(&buf[24..56]).map_err(|_| Error::Corruption)?;
+
+        if capacity == 0 || file.metadata()?.len() != HEADER_SIZE + capacity * SLOT_SIZE {
+            return Err(Error::Corruption);
+        }
+
+        Ok(Self {
+            path,
+            file,
+            capacity,
+            len,
+            height,
+            tip,
+        })
+    }
+
+    /// Height and hash of the last indexed block. Since a block hash commits to all of
+    /// the block's ancestors, this can be used to check whether the index matches a chain.
+    pub fn tip(&self) -> (Height, BlockHash) {
+        (self.height, self.tip)
+    }
+
+    /// Remove all entries from the index.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.file.set_len(0)?;
+        self.capacity = DEFAULT_CAPACITY;
+        self.len = 0;
+        self.height = 0;
+        self.tip = BlockHash::default();
+        self.file.set_len(HEADER_SIZE + self.capacity * SLOT_SIZE)?;
+        self.write_header()
+    }
+
+    /// Number of entries in the index, including stale ones.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Check whether the index is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the candidate heights of a block hash.
+    pub fn get(&self, hash: &BlockHash) -> Result<Vec<Height>, Error> {
+        let key = key(hash);
+        let mut heights = Vec::new();
+        let mut slot = key % self.capacity;
+
+        loop {
+            match self.read(slot)? {
+                None => break,
+                Some((k, height)) if k == key => heights.push(height),
+                Some(_) => {}
+            }
+            slot = (slot + 1) % self.capacity;
+        }
+        Ok(heights)
+    }
+
+    /// Index a block hash at the given height. The genesis block is never indexed.
+    pub fn insert(&mut self, hash: &BlockHash, height: Height) -> Result<(), Error> {
+        assert!(
+            height > 0,
+            "Index::insert: the genesis block is not indexed"
+        );
+
+        if (self.len + 1) * 2 > self.capacity {
+            self.grow()?;
+        }
+        if self.put(key(hash), height)? {
+            self.len += 1;
+        }
+        self.height = height;
+        self.tip = *hash;
+        self.write_header()
+    }
+
+    /// Flush changes to disk.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync_data().map_err(Error::from)
+    }
+
+    /// Create a new, empty index.
+    fn create(path: PathBuf, file: fs::File, capacity: u64) -> Result<Self, Error> {
+        file.set_len(HEADER_SIZE + capacity * SLOT_SIZE)?;
+
+        let mut index = Self {
+            path,
+            file,
+            capacity,
+            len: 0,
+            height: 0,
+            tip: BlockHash::default(),
+        };
+        index.write_header()?;
+
+        Ok(index)
+    }
+
+    /// Double the index capacity, re-inserting all entries into a new file.
+    fn grow(&mut self) -> Result<(), Error> {
+        let tmp = self.path.with_extension("tmp");
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp)?;
+        let mut grown = Self::create(tmp.clone(), file, self.capacity * 2)?;
+
+        for slot in 0..self.capacity {
+            if let Some((key, height)) = self.read(slot)? {
+                grown.put(key, height)?;
+            }
+        }
+        grown.len = self.len;
+        grown.height = self.height;
+        grown.tip = self.tip;
+        grown.write_header()?;
+        grown.sync()?;
+
+        fs::rename(&tmp, &self.path)?;
+
+        self.file = grown.file;
+        self.capacity = grown.capacity;
+
+        Ok(())
+    }
+
+    /// Put an entry in the first free slot, or the slot already holding the key.
+    /// Returns `true` if a new slot was used.
+    fn put(&mut self, key: u64, height: Height) -> Result<bool, Error> {
+        let mut slot = key % self.capacity;
+
+        loop {
+            match self.read(slot)? {
+                None => {
+                    self.write(slot, key, height)?;
+                    return Ok(true);
+                }
+                Some((k, h)) if k == key => {
+                    if h != height {
+                        self.write(slot, key, height)?;
+                    }
+                    return Ok(false);
+                }
+                Some(_) => {}
+            }
+            slot = (slot + 1) % self.capacity;
+        }
+    }
+
+    /// Read a slot. Returns `None` if the slot is empty.
+    fn read(&self, slot: u64) -> Result<Option<(u64, Height)>, Error> {
+        let mut buf = [0; SLOT_SIZE as usize];
+        let mut file = &self.file;
+
+        file.seek(io::SeekFrom::Start(HEADER_SIZE + slot * SLOT_SIZE))?;
+        file.read_exact(&mut buf)?;
+
+        let key = u64::from_le_bytes(field(&buf[0..8]));
+        let height = u32::from_le_bytes(field(&buf[8..12])) as Height;
+
+        if height == 0 {
+            Ok(None)
+        } else {
+            Ok(Some((key, height)))
+        }
+    }
+
+    /// Write a slot.
+    fn write(&mut self, slot: u64, key: u64, height: Height) -> Result<(), Error> {
+        let mut buf = [0; SLOT_SIZE as usize];
+        buf[0..8].copy_from_slice(&key.to_le_bytes());
+        buf[8..12].copy_from_slice(&(height as u32).to_le_bytes());
+
+        self.file
+            .seek(io::SeekFrom::Start(HEADER_SIZE + slot * SLOT_SIZE))?;
+        self.file.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    /// Write the index file header.
+    fn write_header(&mut self) -> Result<(), Error> {
+        let mut buf = [0; HEADER_SIZE as usize];
+        buf[0..8].copy_from_slice(&self.capacity.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.len.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.height.to_le_bytes());
+        buf[24..56].copy_from_slice(&self.tip[..]);
+
+        self.file.seek(io::SeekFrom::Start(0))?;
+        self.file.write_all(&buf)?;
+
+        Ok(())
+    }
+}
+
+/// Get the index key of a block hash.
+fn key(hash: &BlockHash) -> u64 {
+    u64::from_le_bytes(field(&hash.as_inner()[0..8]))
+}
+
+/// Convert a slice into a fixed-size array.
+fn field<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut buf = [0; N];
+    buf.copy_from_slice(bytes);
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin_hashes::sha256d;
+
+    fn hash(n: u64) -> BlockHash {
+        BlockHash::from_hash(sha256d::Hash::hash(&n.to_le_bytes()))
+    }
+
+    #[test]
+    fn test_insert_get() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("headers.idx");
+        let mut index = Index::open(&path).unwrap();
+
+        assert!(index.is_empty());
+        assert!(index.get(&hash(1)).unwrap().is_empty());
+
+        index.insert(&hash(1), 1).unwrap();
+        index.insert(&hash(2), 2).unwrap();
+        index.insert(&hash(2), 2).unwrap();
+
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.tip(), (2, hash(2)));
+        assert_eq!(index.get(&hash(1)).unwrap(), vec![1]);
+        assert_eq!(index.get(&hash(2)).unwrap(), vec![2]);
+        assert!(index.get(&hash(3)).unwrap().is_empty());
+
+        // Re-open.
+        let mut index = Index::open(&path).unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.tip(), (2, hash(2)));
+        assert_eq!(index.get(&hash(2)).unwrap(), vec![2]);
+
+        index.clear().unwrap();
+        assert!(index.is_empty());
+        assert!(index.get(&hash(2)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_grow() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("headers.idx");
+        let mut index = Index::open(&path).unwrap();
+        let count = DEFAULT_CAPACITY;
+
+        for n in 1..=count {
+            index.insert(&hash(n), n).unwrap();
+        }
+        assert_eq!(index.len(), count);
+        assert!(index.capacity >= count * 2);
+
+        let index = Index::open(&path).unwrap();
+        for n in 1..=count {
+            assert_eq!(index.get(&hash(n)).unwrap(), vec![n], "height {}", n);
+        }
+    }
+}
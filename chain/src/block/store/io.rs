@@ -96,6 +96,11 @@ impl<H> File<H> {
 
         Ok(Self { file, genesis })
     }
+
+    /// Get the size of the store file, in bytes.
+    pub fn size(&self) -> io::Result<u64> {
+        self.file.metadata().map(|m| m.len())
+    }
 }
 
 impl<H: 'static + Copy + Encodable + Decodable> Store for File<H> {
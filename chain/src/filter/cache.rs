@@ -12,8 +12,9 @@ pub use nakamoto_common::block::filter::{
 pub use nakamoto_common::block::store::Store;
 
 use nakamoto_common::block::store::Genesis;
+use nakamoto_common::block::Block;
 use nakamoto_common::block::Height;
-use nakamoto_common::network::Network;
+use nakamoto_common::network::NetworkParams;
 use nakamoto_common::nonempty::NonEmpty;
 
 use crate::filter::store;
@@ -45,10 +46,10 @@ impl Decodable for StoredHeader {
 }
 
 impl Genesis for StoredHeader {
-    fn genesis(network: Network) -> Self {
+    fn from_genesis_block(genesis: &Block) -> Self {
         Self {
-            hash: FilterHash::genesis(network),
-            header: FilterHeader::genesis(network),
+            hash: FilterHash::from_genesis_block(genesis),
+            header: FilterHeader::from_genesis_block(genesis),
         }
     }
 }
@@ -76,10 +77,10 @@ impl<S: Store<Header = StoredHeader>> FilterCache<S> {
 
 impl<S> FilterCache<S> {
     /// Verify the filter header chain. Returns `true` if the chain is valid.
-    pub fn verify(&self, network: Network) -> Result<(), store::Error> {
+    pub fn verify(&self, network: &NetworkParams) -> Result<(), store::Error> {
         let mut prev_header = FilterHeader::default();
 
-        if self.headers.first().header != FilterHeader::genesis(network) {
+        if self.headers.first().header != FilterHeader::from_genesis_block(&network.genesis) {
             return Err(store::Error::Integrity);
         }
 
@@ -0,0 +1,338 @@
+//! Header store integrity checks.
+//!
+//! Verifies the block header and filter header stores, and reports the exact location of the
+//! first corrupted header, if any. Stores can then be repaired by truncating them to the last
+//! valid header; the truncated headers are downloaded again on the next sync.
+#![warn(missing_docs)]
+use std::mem;
+
+use thiserror::Error;
+
+use bitcoin::consensus::encode::{Decodable, Encodable};
+
+use nakamoto_common::block::filter::FilterHeader;
+use nakamoto_common::block::store::{self, Genesis as _, Store};
+use nakamoto_common::block::{BlockHeader, Height};
+use nakamoto_common::network::NetworkParams;
+
+use crate::block::store::File;
+use crate::filter::cache::StoredHeader;
+
+/// A corruption found in a header store.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Corruption {
+    /// The genesis header doesn't match the network.
+    #[error("genesis header doesn't match the network")]
+    InvalidGenesis,
+    /// The store ends with a partially written header.
+    #[error("partial header of {len} byte(s) at offset {offset}")]
+    PartialHeader {
+        /// Byte offset of the partial header.
+        offset: u64,
+        /// Length of the partial header, in bytes.
+        len: u64,
+    },
+    /// The header doesn't connect to the previous header.
+    #[error("header at height {height} (offset {offset}) doesn't connect to its parent")]
+    InvalidChain {
+        /// Height of the header.
+        height: Height,
+        /// Byte offset of the header.
+        offset: u64,
+    },
+    /// The header has invalid proof-of-work.
+    #[error("header at height {height} (offset {offset}) has invalid proof-of-work")]
+    InvalidWork {
+        /// Height of the header.
+        height: Height,
+        /// Byte offset of the header.
+        offset: u64,
+    },
+    /// The header doesn't match the checkpoint at its height.
+    #[error("header at height {height} (offset {offset}) doesn't match checkpoint")]
+    InvalidCheckpoint {
+        /// Height of the header.
+        height: Height,
+        /// Byte offset of the header.
+        offset: u64,
+    },
+    /// The filter header doesn't commit to its filter hash and previous filter header.
+    #[error("filter header at height {height} (offset {offset}) is invalid")]
+    InvalidFilterHeader {
+        /// Height of the filter header.
+        height: Height,
+        /// Byte offset of the filter header.
+        offset: u64,
+    },
+}
+
+/// A store verification report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Store size, in bytes.
+    pub size: u64,
+    /// Height of the store, ie. the number of whole headers stored after genesis.
+    pub height: Height,
+    /// Height of the last valid header.
+    pub valid: Height,
+    /// Number of checkpoints verified.
+    pub checkpoints: usize,
+    /// The first corruption found, if any.
+    pub corruption: Option<Corruption>,
+}
+
+impl Report {
+    /// Check whether the store is free of corruption.
+    pub fn is_ok(&self) -> bool {
+        self.corruption.is_none()
+    }
+
+    /// Number of headers that would be lost by repairing the store.
+    pub fn invalid(&self) -> Height {
+        self.height - self.valid
+    }
+}
+
+/// Verify a block header store. Checks that every header connects to its parent, has valid
+/// proof-of-work and matches the network's checkpoints.
+///
+/// Note that difficulty transitions are not checked.
+pub fn verify_headers(
+    store: &File<BlockHeader>,
+    network: &NetworkParams,
+) -> Result<Report, store::Error> {
+    let pow_limit = network.params.pow_limit;
+
+    verify(store, |height, header, prev, report| {
+        if height == 0 {
+            if *header != network.genesis() {
+                return Some(Corruption::InvalidGenesis);
+            }
+            return None;
+        }
+        let offset = offset::<BlockHeader>(height);
+        let target = header.target();
+
+        if header.prev_blockhash != prev.block_hash() {
+            return Some(Corruption::InvalidChain { height, offset });
+        }
+        if target > pow_limit || header.validate_pow(&target).is_err() {
+            return Some(Corruption::InvalidWork { height, offset });
+        }
+        if let Some((_, checkpoint)) = network.checkpoints.iter().find(|(h, _)| *h == height) {
+            if header.block_hash() != *checkpoint {
+                return Some(Corruption::InvalidCheckpoint { height, offset });
+            }
+            report.checkpoints += 1;
+        }
+        None
+    })
+}
+
+/// Verify a filter header store. Checks that every filter header commits to its filter hash
+/// and the previous filter header.
+pub fn verify_filter_headers(
+    store: &File<StoredHeader>,
+    network: &NetworkParams,
+) -> Result<Report, store::Error> {
+    verify(store, |height, stored, prev, _| {
+        if height == 0 {
+            if stored.header != FilterHeader::from_genesis_block(&network.genesis) {
+                return Some(Corruption::InvalidGenesis);
+            }
+            return None;
+        }
+        if stored.hash.filter_header(&prev.header) != stored.header {
+            return Some(Corruption::InvalidFilterHeader {
+                height,
+                offset: offset::<StoredHeader>(height),
+            });
+        }
+        None
+    })
+}
+
+/// Repair a store given its verification report, by truncating it to the last valid header.
+/// Returns the number of headers removed.
+pub fn repair<H>(store: &mut File<H>, report: &Report) -> Result<Height, store::Error>
+where
+    H: 'static + Copy + Encodable + Decodable,
+{
+    if report.is_ok() {
+        return Ok(0);
+    }
+    store.rollback(report.valid)?;
+    store.sync()?;
+
+    Ok(report.invalid())
+}
+
+/// Verify a store, given a function that checks a header against its predecessor.
+fn verify<H, F>(store: &File<H>, check: F) -> Result<Report, store::Error>
+where
+    H: 'static + Copy + Encodable + Decodable,
+    F: Fn(Height, &H, &H, &mut Report) -> Option<Corruption>,
+{
+    let size = store.size()?;
+    let header_size = mem::size_of::<H>() as u64;
+    let mut report = Report {
+        size,
+        height: size / header_size,
+        valid: 0,
+        checkpoints: 0,
+        corruption: None,
+    };
+    let mut prev = store.genesis();
+
+    for result in store.iter() {
+        let (height, header) = result?;
+
+        if let Some(corruption) = check(height, &header, &prev, &mut report) {
+            report.corruption = Some(corruption);
+            return Ok(report);
+        }
+        report.valid = height;
+        prev = header;
+    }
+
+    if size % header_size != 0 {
+        report.corruption = Some(Corruption::PartialHeader {
+            offset: report.height * header_size,
+            len: size % header_size,
+        });
+    }
+    Ok(report)
+}
+
+/// Byte offset of the header at the given height. Genesis isn't stored.
+fn offset<H>(height: Height) -> u64 {
+    (height - 1) * mem::size_of::<H>() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use nakamoto_common::network::Network;
+    use nakamoto_test::block::gen;
+
+    fn stores(
+        height: Height,
+        dir: &std::path::Path,
+    ) -> (File<BlockHeader>, File<StoredHeader>, NetworkParams) {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let mut network = NetworkParams::from(Network::Regtest);
+        let chain = gen::blockchain(network.genesis.clone(), height, &mut rng);
+        let cfheaders = gen::cfheaders_from_blocks(
+            FilterHeader::from_genesis_block(&network.genesis),
+            chain.tail.iter(),
+        );
+        network.checkpoints = vec![(4, chain.tail[3].block_hash())];
+
+        let mut headers = File::open(dir.join("headers.db"), network.genesis()).unwrap();
+        let mut filters = File::open(
+            dir.join("filters.db"),
+            StoredHeader::from_genesis_block(&network.genesis),
+        )
+        .unwrap();
+
+        headers.put(chain.tail.iter().map(|b| b.header)).unwrap();
+        filters
+            .put(
+                cfheaders
+                    .into_iter()
+                    .map(|(hash, header)| StoredHeader { hash, header }),
+            )
+            .unwrap();
+
+        (headers, filters, network)
+    }
+
+    #[test]
+    fn test_verify_valid() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (headers, filters, network) = stores(8, tmp.path());
+
+        let report = verify_headers(&headers, &network).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.height, 8);
+        assert_eq!(report.valid, 8);
+        assert_eq!(report.size, 8 * 80);
+        assert_eq!(report.checkpoints, 1);
+
+        let report = verify_filter_headers(&filters, &network).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.valid, 8);
+    }
+
+    #[test]
+    fn test_verify_repair() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (mut headers, mut filters, network) = stores(8, tmp.path());
+
+        // Overwrite the header at height 6 with the header at height 2.
+        let header = headers.get(2).unwrap();
+        headers.rollback(5).unwrap();
+        headers.put(std::iter::once(header)).unwrap();
+        headers.put((7..=8).map(|_| header)).unwrap();
+
+        let report = verify_headers(&headers, &network).unwrap();
+        assert_eq!(
+            report.corruption,
+            Some(Corruption::InvalidChain {
+                height: 6,
+                offset: 400
+            })
+        );
+        assert_eq!(report.valid, 5);
+        assert_eq!(report.invalid(), 3);
+        assert_eq!(repair(&mut headers, &report).unwrap(), 3);
+        assert!(verify_headers(&headers, &network).unwrap().is_ok());
+        assert_eq!(headers.height().unwrap(), 5);
+
+        // Write a partial filter header.
+        fs_append(&tmp.path().join("filters.db"), &[0xff; 9]);
+
+        let report = verify_filter_headers(&filters, &network).unwrap();
+        assert_eq!(
+            report.corruption,
+            Some(Corruption::PartialHeader {
+                offset: 8 * 64,
+                len: 9
+            })
+        );
+        assert_eq!(report.valid, 8);
+        assert_eq!(repair(&mut filters, &report).unwrap(), 0);
+        assert!(verify_filter_headers(&filters, &network).unwrap().is_ok());
+        assert_eq!(filters.size().unwrap(), 8 * 64);
+    }
+
+    #[test]
+    fn test_verify_checkpoint() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (headers, _, mut network) = stores(8, tmp.path());
+
+        network.checkpoints = vec![(4, headers.get(3).unwrap().block_hash())];
+
+        let report = verify_headers(&headers, &network).unwrap();
+        assert_eq!(
+            report.corruption,
+            Some(Corruption::InvalidCheckpoint {
+                height: 4,
+                offset: 240
+            })
+        );
+        assert_eq!(report.valid, 3);
+    }
+
+    fn fs_append(path: &std::path::Path, bytes: &[u8]) {
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(bytes)
+            .unwrap();
+    }
+}
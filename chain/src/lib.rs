@@ -15,5 +15,8 @@ pub use block::*;
 #[allow(clippy::inconsistent_struct_constructor)]
 pub mod filter;
 
+pub mod integrity;
+pub mod snapshot;
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,345 @@
+//! Header snapshots.
+//!
+//! A snapshot is a contiguous chain of block headers, starting after genesis, along with the
+//! matching filter header chain. It can be used to bootstrap a fresh header store without
+//! downloading headers from the network. Snapshots are trusted by hash: the snapshot file must
+//! hash to a value known in advance, and its tip must match one of the network's checkpoints.
+//!
+//! The file format is:
+//!
+//! ```text
+//! "nksnap" | version: u16 | magic: u32 | headers: Vec<BlockHeader> | filter headers: Vec<(FilterHash, FilterHeader)>
+//! ```
+//!
+//! All fields use Bitcoin consensus encoding.
+#![warn(missing_docs)]
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use bitcoin::consensus::encode::{self, Decodable, Encodable, VarInt};
+use bitcoin_hashes::{sha256d, Hash};
+use thiserror::Error;
+
+use nakamoto_common::block::store::{self, Genesis, Store};
+use nakamoto_common::block::{BlockHash, BlockHeader, Height};
+use nakamoto_common::network::NetworkParams;
+use nakamoto_common::nonempty::NonEmpty;
+
+use crate::block::store::memory::Memory;
+use crate::filter::cache::{FilterCache, StoredHeader};
+
+/// Snapshot file prefix.
+const PREFIX: &[u8; 6] = b"nksnap";
+/// Snapshot format version.
+const VERSION: u16 = 1;
+
+/// A snapshot error.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// An I/O error.
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    /// A storage error.
+    #[error("storage error: {0}")]
+    Store(#[from] store::Error),
+    /// An error decoding the snapshot.
+    #[error("error decoding snapshot: {0}")]
+    Decoding(#[from] encode::Error),
+    /// The file is not a snapshot, or uses an unsupported version.
+    #[error("invalid snapshot format")]
+    InvalidFormat,
+    /// The snapshot is for a different network.
+    #[error("snapshot is for a different network (magic {0:#x})")]
+    InvalidNetwork(u32),
+    /// The snapshot doesn't hash to the expected value.
+    #[error("snapshot hash {0} doesn't match expected hash {1}")]
+    InvalidHash(sha256d::Hash, sha256d::Hash),
+    /// The header chain is invalid at the given height.
+    #[error("invalid header chain at height {0}")]
+    InvalidChain(Height),
+    /// The snapshot doesn't match a checkpoint.
+    #[error("header {0} at height {1} doesn't match checkpoint")]
+    InvalidCheckpoint(BlockHash, Height),
+    /// The snapshot tip isn't a known checkpoint.
+    #[error("snapshot tip at height {0} is not a checkpoint")]
+    UnknownTip(Height),
+    /// The filter header chain is invalid.
+    #[error("invalid filter header chain")]
+    InvalidFilterHeaders,
+}
+
+/// A header snapshot.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Network magic of the network this snapshot is for.
+    pub magic: u32,
+    /// Block headers, starting at height `1`.
+    pub headers: Vec<BlockHeader>,
+    /// Filter headers, starting at height `1`.
+    pub filter_headers: Vec<StoredHeader>,
+}
+
+impl Snapshot {
+    /// Load a snapshot from a file, checking that it hashes to the expected value.
+    /// The snapshot should then be verified with [`Snapshot::verify`].
+    pub fn load<P: AsRef<Path>>(path: P, hash: &sha256d::Hash) -> Result<Self, Error> {
+        let mut bytes = Vec::new();
+        fs::File::open(path)?.read_to_end(&mut bytes)?;
+
+        let actual = sha256d::Hash::hash(&bytes);
+        if actual != *hash {
+            return Err(Error::InvalidHash(actual, *hash));
+        }
+        Self::decode(bytes.as_slice())
+    }
+
+    /// Decode a snapshot.
+    pub fn decode<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut prefix = [0; 6];
+        reader.read_exact(&mut prefix)?;
+
+        if &prefix != PREFIX || u16::consensus_decode(&mut reader)? != VERSION {
+            return Err(Error::InvalidFormat);
+        }
+        let magic = u32::consensus_decode(&mut reader)?;
+
+        // Nb. The counts are not used to pre-allocate, since they aren't trusted yet.
+        let count = VarInt::consensus_decode(&mut reader)?.0;
+        let mut headers = Vec::new();
+        for _ in 0..count {
+            headers.push(BlockHeader::consensus_decode(&mut reader)?);
+        }
+
+        let count = VarInt::consensus_decode(&mut reader)?.0;
+        let mut filter_headers = Vec::new();
+        for _ in 0..count {
+            filter_headers.push(StoredHeader::consensus_decode(&mut reader)?);
+        }
+
+        Ok(Self {
+            magic,
+            headers,
+            filter_headers,
+        })
+    }
+
+    /// Encode a snapshot. Returns the number of bytes written.
+    pub fn encode<W: Write>(&self, mut writer: W) -> Result<usize, Error> {
+        let mut len = 0;
+
+        writer.write_all(PREFIX)?;
+        len += PREFIX.len();
+        len += VERSION.consensus_encode(&mut writer)?;
+        len += self.magic.consensus_encode(&mut writer)?;
+        len += VarInt(self.headers.len() as u64).consensus_encode(&mut writer)?;
+
+        for header in &self.headers {
+            len += header.consensus_encode(&mut writer)?;
+        }
+        len += VarInt(self.filter_headers.len() as u64).consensus_encode(&mut writer)?;
+
+        for header in &self.filter_headers {
+            len += header.consensus_encode(&mut writer)?;
+        }
+        Ok(len)
+    }
+
+    /// Create a snapshot from a header store and filter header store, up to the given height.
+    pub fn from_stores<H, F>(
+        magic: u32,
+        headers: &H,
+        filter_headers: &F,
+        height: Height,
+    ) -> Result<Self, Error>
+    where
+        H: Store<Header = BlockHeader>,
+        F: Store<Header = StoredHeader>,
+    {
+        let headers = headers
+            .iter()
+            .skip(1)
+            .take(height as usize)
+            .map(|r| r.map(|(_, h)| h))
+            .collect::<Result<Vec<_>, _>>()?;
+        let filter_headers = filter_headers
+            .iter()
+            .skip(1)
+            .take(height as usize)
+            .map(|r| r.map(|(_, h)| h))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if headers.len() != filter_headers.len() {
+            return Err(Error::InvalidFilterHeaders);
+        }
+        Ok(Self {
+            magic,
+            headers,
+            filter_headers,
+        })
+    }
+
+    /// Get the snapshot height.
+    pub fn height(&self) -> Height {
+        self.headers.len() as Height
+    }
+
+    /// Get the hash of the snapshot file contents. This is what [`Snapshot::load`] checks against.
+    pub fn hash(&self) -> Result<sha256d::Hash, Error> {
+        let mut bytes = Vec::new();
+        self.encode(&mut bytes)?;
+
+        Ok(sha256d::Hash::hash(&bytes))
+    }
+
+    /// Verify the snapshot against the given network. This checks that the headers form a
+    /// chain from genesis with valid proof-of-work, that every checkpoint in range matches,
+    /// that the tip is itself a checkpoint, and that the filter header chain is valid.
+    ///
+    /// Note that difficulty transitions are not checked; the snapshot tip being a checkpoint
+    /// is what guarantees the headers are part of the best chain.
+    pub fn verify(&self, network: &NetworkParams) -> Result<(), Error> {
+        if self.magic != network.magic {
+            return Err(Error::InvalidNetwork(self.magic));
+        }
+        let mut prev = network.genesis_hash();
+        for (i, header) in self.headers.iter().enumerate() {
+            let height = i as Height + 1;
+            let hash = header.block_hash();
+
+            if header.prev_blockhash != prev || header.validate_pow(&header.target()).is_err() {
+                return Err(Error::InvalidChain(height));
+            }
+            prev = hash;
+        }
+        for (height, checkpoint) in &network.checkpoints {
+            if let Some(header) = self.headers.get((*height as usize).wrapping_sub(1)) {
+                let hash = header.block_hash();
+
+                if hash != *checkpoint {
+                    return Err(Error::InvalidCheckpoint(hash, *height));
+                }
+            }
+        }
+
+        let height = self.height();
+        if !network
+            .checkpoints
+            .iter()
+            .any(|(h, hash)| *h == height && *hash == prev)
+        {
+            return Err(Error::UnknownTip(height));
+        }
+
+        // Let the filter cache verify the filter header chain.
+        let filters = FilterCache::from(Memory::new(NonEmpty::from((
+            StoredHeader::from_genesis_block(&network.genesis),
+            self.filter_headers.clone(),
+        ))))?;
+
+        if self.filter_headers.len() != self.headers.len() || filters.verify(network).is_err() {
+            return Err(Error::InvalidFilterHeaders);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nakamoto_common::block::filter::FilterHeader;
+    use nakamoto_common::network::Network;
+    use nakamoto_test::block::gen;
+
+    fn snapshot(height: Height) -> (Snapshot, NetworkParams) {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let mut network = NetworkParams::from(Network::Regtest);
+        let chain = gen::blockchain(network.genesis.clone(), height, &mut rng);
+        let filter_headers = gen::cfheaders_from_blocks(
+            FilterHeader::from_genesis_block(&network.genesis),
+            chain.tail.iter(),
+        )
+        .into_iter()
+        .map(|(hash, header)| StoredHeader { hash, header })
+        .collect();
+
+        network.checkpoints = vec![(height, chain.last().block_hash())];
+
+        let snapshot = Snapshot {
+            magic: network.magic,
+            headers: chain.tail.iter().map(|b| b.header).collect(),
+            filter_headers,
+        };
+        (snapshot, network)
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let (snapshot, network) = snapshot(16);
+        let mut bytes = Vec::new();
+
+        snapshot.encode(&mut bytes).unwrap();
+
+        let decoded = Snapshot::decode(bytes.as_slice()).unwrap();
+        decoded.verify(&network).unwrap();
+
+        assert_eq!(decoded.headers, snapshot.headers);
+        assert_eq!(decoded.height(), 16);
+        assert_eq!(decoded.hash().unwrap(), sha256d::Hash::hash(&bytes));
+    }
+
+    #[test]
+    fn test_load() {
+        let (snapshot, _) = snapshot(8);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("headers.snapshot");
+
+        snapshot.encode(fs::File::create(&path).unwrap()).unwrap();
+
+        let hash = snapshot.hash().unwrap();
+        Snapshot::load(&path, &hash).unwrap();
+
+        assert!(matches!(
+            Snapshot::load(&path, &sha256d::Hash::default()),
+            Err(Error::InvalidHash(h, _)) if h == hash
+        ));
+    }
+
+    #[test]
+    fn test_verify() {
+        let (snapshot, network) = snapshot(8);
+        snapshot.verify(&network).unwrap();
+
+        // The tip must be a checkpoint.
+        let mut invalid = snapshot.clone();
+        invalid.headers.pop();
+        invalid.filter_headers.pop();
+        assert!(matches!(
+            invalid.verify(&network),
+            Err(Error::UnknownTip(7))
+        ));
+
+        // Headers must form a chain.
+        let mut invalid = snapshot.clone();
+        invalid.headers.swap(2, 3);
+        assert!(matches!(
+            invalid.verify(&network),
+            Err(Error::InvalidChain(3))
+        ));
+
+        // The filter header chain must be valid.
+        let mut invalid = snapshot.clone();
+        invalid.filter_headers.swap(2, 3);
+        assert!(matches!(
+            invalid.verify(&network),
+            Err(Error::InvalidFilterHeaders)
+        ));
+
+        // The snapshot must be for the right network.
+        assert!(matches!(
+            snapshot.verify(&NetworkParams::from(Network::Mainnet)),
+            Err(Error::InvalidNetwork(_))
+        ));
+    }
+}
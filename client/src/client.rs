@@ -14,34 +14,41 @@ pub use crossbeam_channel as chan;
 use nakamoto_chain::block::{store, Block};
 use nakamoto_chain::filter;
 use nakamoto_chain::filter::cache::FilterCache;
+use nakamoto_chain::integrity;
+use nakamoto_chain::snapshot::Snapshot;
 use nakamoto_chain::{block::cache::BlockCache, filter::BlockFilter};
 
 use nakamoto_common::block::filter::Filters;
 use nakamoto_common::block::store::{Genesis as _, Store as _};
-use nakamoto_common::block::time::AdjustedTime;
+use nakamoto_common::block::time::{AdjustedTime, LocalDuration};
 use nakamoto_common::block::tree::{self, BlockTree, ImportResult};
 use nakamoto_common::block::{BlockHash, BlockHeader, Height, Transaction};
 use nakamoto_common::nonempty::NonEmpty;
 use nakamoto_common::p2p::peer::{Source, Store as _};
 
-pub use nakamoto_common::network::{Network, Services};
+pub use nakamoto_common::network::{Network, NetworkParams, Services, Signet};
 pub use nakamoto_common::p2p::Domain;
 
 use nakamoto_p2p as p2p;
+use nakamoto_p2p::bitcoin::hashes::sha256d;
 use nakamoto_p2p::bitcoin::network::constants::ServiceFlags;
 use nakamoto_p2p::bitcoin::network::message::NetworkMessage;
 use nakamoto_p2p::bitcoin::network::Address;
+use nakamoto_p2p::bitcoin::Txid;
+use nakamoto_p2p::protocol::fees::FeeBump;
+use nakamoto_p2p::protocol::invmgr::Broadcast;
 use nakamoto_p2p::protocol::Protocol;
 use nakamoto_p2p::protocol::{self, Link};
 use nakamoto_p2p::protocol::{cbfmgr, invmgr, peermgr, syncmgr};
 
 pub use nakamoto_p2p::event;
-pub use nakamoto_p2p::protocol::{Command, CommandError, Peer};
+pub use nakamoto_p2p::protocol::{Command, CommandError, Peer, Progress, SyncStatus};
 pub use nakamoto_p2p::reactor::Reactor;
 
 pub use crate::error::Error;
 pub use crate::event::Event;
 pub use crate::handle;
+pub use crate::mempool;
 pub use crate::peer;
 pub use crate::spv;
 
@@ -50,8 +57,8 @@ pub use crate::spv;
 pub struct Config {
     /// Client listen addresses.
     pub listen: Vec<net::SocketAddr>,
-    /// Bitcoin network.
-    pub network: Network,
+    /// Bitcoin network. Custom networks are supported via [`NetworkParams`].
+    pub network: NetworkParams,
     /// Peers to connect to.
     pub connect: Vec<net::SocketAddr>,
     /// Network domains to connect to.
@@ -70,6 +77,30 @@ pub struct Config {
     pub services: ServiceFlags,
     /// Protocol hooks.
     pub hooks: protocol::Hooks,
+    /// Mempool monitoring configuration. When set, transactions relayed by peers that pay
+    /// to watched scripts are reported via [`Event::TxUnconfirmed`]. Disabled by default,
+    /// since it uses more bandwidth.
+    pub mempool: Option<invmgr::MempoolConfig>,
+    /// Header snapshot to bootstrap the header and filter header stores from, on first start.
+    pub snapshot: Option<SnapshotConfig>,
+    /// Number of recent block headers to keep in memory. Older headers are read from disk,
+    /// and looked up through an index stored next to the header store. When `None`, all
+    /// block headers are kept in memory.
+    pub header_window: Option<usize>,
+    /// Number of reverted blocks from which a re-org is reported via [`Event::DeepReorg`].
+    pub deep_reorg_depth: usize,
+    /// Time after which submitted transactions that haven't confirmed are dropped, and no
+    /// longer re-announced.
+    pub pending_expiry: LocalDuration,
+}
+
+/// Header snapshot configuration. See [`nakamoto_chain::snapshot`].
+#[derive(Debug, Clone)]
+pub struct SnapshotConfig {
+    /// Path to the snapshot file.
+    pub path: PathBuf,
+    /// Expected hash of the snapshot file.
+    pub hash: sha256d::Hash,
 }
 
 impl Config {
@@ -97,6 +128,9 @@ impl From<Config> for p2p::protocol::Config {
             connect: cfg.connect,
             target_outbound_peers: cfg.target_outbound_peers,
             max_inbound_peers: cfg.max_inbound_peers,
+            mempool: cfg.mempool,
+            deep_reorg_depth: cfg.deep_reorg_depth,
+            mempool_expiry: cfg.pending_expiry,
             ..Self::default()
         }
     }
@@ -106,7 +140,7 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             listen: vec![([0, 0, 0, 0], 0).into()],
-            network: Network::default(),
+            network: NetworkParams::default(),
             connect: Vec::new(),
             domains: Domain::all(),
             timeout: time::Duration::from_secs(60),
@@ -116,6 +150,11 @@ impl Default for Config {
             services: ServiceFlags::NONE,
             name: "self",
             hooks: protocol::Hooks::default(),
+            mempool: None,
+            snapshot: None,
+            header_window: None,
+            deep_reorg_depth: p2p::protocol::syncmgr::DEEP_REORG_DEPTH,
+            pending_expiry: mempool::PENDING_TRANSACTION_EXPIRY,
         }
     }
 }
@@ -156,6 +195,7 @@ pub struct Client<R: Reactor<Publisher>> {
     blocks: event::Subscriber<(Block, Height)>,
     filters: event::Subscriber<(BlockFilter, BlockHash, Height)>,
     subscriber: event::Subscriber<Event>,
+    mempool: mempool::Persister,
 
     reactor: R,
 }
@@ -190,11 +230,14 @@ impl<R: Reactor<Publisher>> Client<R> {
             move |e, p| spv.process(e, p)
         });
 
+        let mempool = mempool::Persister::default();
+
         let publisher = Publisher::new()
             .register(event_pub)
             .register(blocks_pub)
             .register(filters_pub)
-            .register(publisher);
+            .register(publisher)
+            .register(mempool.clone());
 
         let reactor = R::new(publisher, commands)?;
 
@@ -206,6 +249,7 @@ impl<R: Reactor<Publisher>> Client<R> {
             blocks,
             filters,
             subscriber,
+            mempool,
         })
     }
 
@@ -221,33 +265,42 @@ impl<R: Reactor<Publisher>> Client<R> {
     /// Start the client process. This function is meant to be run in its own thread.
     pub fn run(mut self) -> Result<(), Error> {
         let home = self.config.root.join(".nakamoto");
-        let dir = home.join(self.config.network.as_str());
+        let dir = home.join(&self.config.network.name);
         let listen = self.config.listen.clone();
 
         fs::create_dir_all(&dir)?;
 
         let genesis = self.config.network.genesis();
-        let params = self.config.network.params();
+        let params = self.config.network.params.clone();
 
-        log::info!("Initializing client ({:?})..", self.config.network);
+        log::info!("Initializing client ({})..", self.config.network.name);
         log::info!(
             "Genesis block hash is {}",
             self.config.network.genesis_hash()
         );
 
         let path = dir.join("headers.db");
-        let store = match store::File::create(&path, genesis) {
+        let mut store = match store::File::create(&path, genesis) {
             Ok(store) => {
                 log::info!("Initializing new block store {:?}", path);
                 store
             }
             Err(store::Error::Io(e)) if e.kind() == io::ErrorKind::AlreadyExists => {
                 log::info!("Found existing store {:?}", path);
-                let store = store::File::open(path, genesis)?;
+                let mut store = store::File::open(path, genesis)?;
 
                 if store.check().is_err() {
-                    log::warn!("Corruption detected in header store, healing..");
-                    store.heal()?; // Rollback store to the last valid header.
+                    let report = integrity::verify_headers(&store, &self.config.network)?;
+
+                    if let Some(corruption) = &report.corruption {
+                        log::warn!("Corruption detected in header store: {}", corruption);
+                        log::warn!(
+                            "Rolling back {} header(s) to height {}..",
+                            report.invalid(),
+                            report.valid
+                        );
+                        integrity::repair(&mut store, &report)?;
+                    }
                 }
                 log::info!("Store height = {}", store.height()?);
                 log::info!("Loading block headers from store..");
@@ -257,28 +310,32 @@ impl<R: Reactor<Publisher>> Client<R> {
             Err(err) => return Err(err.into()),
         };
 
-        let local_time = SystemTime::now().into();
-        let checkpoints = self.config.network.checkpoints().collect::<Vec<_>>();
-        let clock = AdjustedTime::<net::SocketAddr>::new(local_time);
-        let cache = BlockCache::from(store, params, &checkpoints)?;
-        let rng = fastrand::Rng::new();
-
         log::info!("Initializing block filters..");
 
-        let cfheaders_genesis = filter::cache::StoredHeader::genesis(self.config.network);
+        let cfheaders_genesis =
+            filter::cache::StoredHeader::from_genesis_block(&self.config.network.genesis);
         let cfheaders_path = dir.join("filters.db");
-        let cfheaders_store = match store::File::create(&cfheaders_path, cfheaders_genesis) {
+        let mut cfheaders_store = match store::File::create(&cfheaders_path, cfheaders_genesis) {
             Ok(store) => {
                 log::info!("Initializing new filter header store {:?}", cfheaders_path);
                 store
             }
             Err(store::Error::Io(e)) if e.kind() == io::ErrorKind::AlreadyExists => {
                 log::info!("Found existing store {:?}", cfheaders_path);
-                let store = store::File::open(cfheaders_path, cfheaders_genesis)?;
+                let mut store = store::File::open(cfheaders_path, cfheaders_genesis)?;
 
                 if store.check().is_err() {
-                    log::warn!("Corruption detected in filter store, healing..");
-                    store.heal()?; // Rollback store to the last valid header.
+                    let report = integrity::verify_filter_headers(&store, &self.config.network)?;
+
+                    if let Some(corruption) = &report.corruption {
+                        log::warn!("Corruption detected in filter store: {}", corruption);
+                        log::warn!(
+                            "Rolling back {} filter header(s) to height {}..",
+                            report.invalid(),
+                            report.valid
+                        );
+                        integrity::repair(&mut store, &report)?;
+                    }
                 }
                 log::info!("Filters height = {}", store.height()?);
                 log::info!("Loading filter headers from store..");
@@ -288,9 +345,41 @@ impl<R: Reactor<Publisher>> Client<R> {
             Err(err) => return Err(err.into()),
         };
 
+        // On first start, bootstrap the stores from a header snapshot, if one was configured.
+        if let Some(cfg) = &self.config.snapshot {
+            if store.height()? == 0 && cfheaders_store.height()? == 0 {
+                log::info!("Loading header snapshot from {:?}..", cfg.path);
+
+                let snapshot = Snapshot::load(&cfg.path, &cfg.hash)?;
+                snapshot.verify(&self.config.network)?;
+
+                store.put(snapshot.headers.into_iter())?;
+                cfheaders_store.put(snapshot.filter_headers.into_iter())?;
+
+                log::info!("Imported {} header(s) from snapshot", store.height()?);
+            }
+        }
+
+        let local_time = SystemTime::now().into();
+        let checkpoints = self.config.network.checkpoints.clone();
+        let clock = AdjustedTime::<net::SocketAddr>::new(local_time);
+        let mut cache = if let Some(window) = self.config.header_window {
+            log::info!("Loading block header index..");
+
+            let index = store::Index::open(dir.join("headers.idx"))?;
+            BlockCache::bounded(store, index, params, &checkpoints, window)?
+        } else {
+            BlockCache::from(store, params, &checkpoints)?
+        };
+        let rng = fastrand::Rng::new();
+
+        if let Some(challenge) = &self.config.network.challenge {
+            cache = cache.with_signet_challenge(challenge.clone());
+        }
+
         let filters = FilterCache::from(cfheaders_store)?;
         log::info!("Verifying filter headers..");
-        filters.verify(self.config.network)?; // Verify store integrity.
+        filters.verify(&self.config.network)?; // Verify store integrity.
 
         log::info!("Loading peer addresses..");
 
@@ -322,16 +411,39 @@ impl<R: Reactor<Publisher>> Client<R> {
 
         log::trace!("{:#?}", peers);
 
+        log::info!("Loading pending transactions..");
+
+        let mut pending = mempool::Store::open(dir.join("mempool.json"))?;
+        let expired = pending.expire(local_time, self.config.pending_expiry);
+
+        if !expired.is_empty() {
+            log::info!("{} pending transaction(s) expired", expired.len());
+            pending.flush()?;
+        }
+        if !pending.is_empty() {
+            log::info!("Re-announcing {} pending transaction(s)..", pending.len());
+
+            // The commands are processed as soon as the protocol starts. Submitting the
+            // transactions again resumes their re-broadcast, status tracking and expiry.
+            // Parents are submitted before their children.
+            // Transactions are broadcast the same way they were originally.
+            let txs = pending.iter().map(|p| p.transaction.clone()).collect();
+
+            for tx in protocol::policy::sort_package(txs) {
+                let (broadcast, submitted) = pending
+                    .get(&tx.txid())
+                    .map_or((Broadcast::All, local_time), |p| (p.broadcast, p.submitted));
+
+                self.handle
+                    .send(Command::ResubmitTransaction(tx, broadcast, submitted))
+                    .ok();
+            }
+        }
+        self.mempool.set(pending);
+
         if self.config.connect.is_empty() && peers.is_empty() {
             log::info!("Address book is empty. Trying DNS seeds..");
-            peers.seed(
-                self.config
-                    .network
-                    .seeds()
-                    .iter()
-                    .map(|s| (*s, self.config.network.port())),
-                Source::Dns,
-            )?;
+            peers.seed(self.config.network.seed_addrs(), Source::Dns)?;
             peers.flush()?;
 
             log::info!("{} seeds added to address book", peers.len());
@@ -339,7 +451,6 @@ impl<R: Reactor<Publisher>> Client<R> {
 
         let cfg = p2p::protocol::Config {
             network: self.config.network,
-            params: self.config.network.params(),
             target: self.config.name,
             connect: self.config.connect,
             domains: self.config.domains,
@@ -347,6 +458,8 @@ impl<R: Reactor<Publisher>> Client<R> {
             max_inbound_peers: self.config.max_inbound_peers,
             services: self.config.services,
             hooks: self.config.hooks,
+            mempool: self.config.mempool,
+            deep_reorg_depth: self.config.deep_reorg_depth,
             ..p2p::protocol::Config::default()
         };
 
@@ -369,6 +482,8 @@ impl<R: Reactor<Publisher>> Client<R> {
             services: self.config.services,
             hooks: self.config.hooks,
             domains: self.config.domains,
+            mempool: self.config.mempool,
+            deep_reorg_depth: self.config.deep_reorg_depth,
             ..p2p::protocol::Config::from(
                 self.config.name,
                 self.config.network,
@@ -376,7 +491,7 @@ impl<R: Reactor<Publisher>> Client<R> {
             )
         };
 
-        log::info!("Initializing client ({:?})..", cfg.network);
+        log::info!("Initializing client ({})..", cfg.network.name);
         log::info!("Genesis block hash is {}", cfg.network.genesis_hash());
         log::info!("Chain height is {}", cache.height());
 
@@ -396,7 +511,7 @@ impl<R: Reactor<Publisher>> Client<R> {
     /// Create a new handle to communicate with the client.
     pub fn handle(&self) -> Handle<R> {
         Handle {
-            network: self.config.network,
+            network: self.config.network.clone(),
             events: self.events.clone(),
             waker: self.reactor.waker(),
             commands: self.handle.clone(),
@@ -410,7 +525,7 @@ impl<R: Reactor<Publisher>> Client<R> {
 
 /// An instance of [`handle::Handle`] for [`Client`].
 pub struct Handle<R: Reactor<Publisher>> {
-    network: Network,
+    network: NetworkParams,
     commands: chan::Sender<Command>,
     events: event::Subscriber<protocol::Event>,
     blocks: event::Subscriber<(Block, Height)>,
@@ -426,7 +541,7 @@ where
 {
     fn clone(&self) -> Self {
         Self {
-            network: self.network,
+            network: self.network.clone(),
             blocks: self.blocks.clone(),
             commands: self.commands.clone(),
             events: self.events.clone(),
@@ -479,8 +594,8 @@ impl<R: Reactor<Publisher>> handle::Handle for Handle<R>
 where
     R::Waker: Sync,
 {
-    fn network(&self) -> Network {
-        self.network
+    fn network_params(&self) -> NetworkParams {
+        self.network.clone()
     }
 
     fn get_tip(&self) -> Result<(Height, BlockHeader), handle::Error> {
@@ -490,12 +605,95 @@ where
         Ok(receive.recv()?)
     }
 
+    fn sync_status(&self) -> Result<SyncStatus, handle::Error> {
+        let (transmit, receive) = chan::bounded::<SyncStatus>(1);
+        self.command(Command::GetSyncStatus(transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
+    fn get_header(&self, hash: &BlockHash) -> Result<Option<(Height, BlockHeader)>, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::GetHeader(*hash, transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
+    fn get_headers(
+        &self,
+        range: RangeInclusive<Height>,
+    ) -> Result<Vec<BlockHeader>, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::GetHeaders(range, transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
+    fn is_ancestor(&self, ancestor: &BlockHash, hash: &BlockHash) -> Result<bool, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::IsAncestor(*ancestor, *hash, transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
+    fn find_fork(&self, hash: &BlockHash) -> Result<Option<(Height, BlockHeader)>, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::FindFork(*hash, transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
+    fn get_stale_tips(&self) -> Result<Vec<(Height, BlockHeader)>, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::GetStaleTips(transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
     fn get_block(&self, hash: &BlockHash) -> Result<(), handle::Error> {
         self.command(Command::GetBlock(*hash))?;
 
         Ok(())
     }
 
+    fn fetch_block(&self, hash: &BlockHash) -> Result<(Block, Height), handle::Error> {
+        let events = self.events();
+        let hash = *hash;
+
+        if self.get_header(&hash)?.is_none() {
+            return Err(handle::Error::UnknownBlock);
+        }
+        self.command(Command::GetBlock(hash))?;
+
+        // Give the block requests a chance to time out before we do.
+        let timeout =
+            time::Duration::from(invmgr::REQUEST_TIMEOUT) * (invmgr::MAX_ATTEMPTS as u32 + 1);
+
+        event::wait(
+            &events,
+            |e| match e {
+                protocol::Event::InventoryManager(invmgr::Event::BlockProcessed {
+                    block,
+                    height,
+                }) if block.block_hash() == hash => Some(Ok((block, height))),
+                protocol::Event::InventoryManager(invmgr::Event::BlockFailed {
+                    hash: h,
+                    attempts,
+                }) if h == hash => Some(Err(handle::Error::FetchBlock { hash, attempts })),
+                _ => None,
+            },
+            self.timeout.max(timeout),
+        )?
+    }
+
+    fn fetch_block_at(&self, height: Height) -> Result<(Block, Height), handle::Error> {
+        let header = self
+            .get_block_by_height(height)?
+            .ok_or(handle::Error::UnknownBlock)?;
+
+        self.fetch_block(&header.block_hash())
+    }
+
     fn get_filters(&self, range: RangeInclusive<Height>) -> Result<(), handle::Error> {
         assert!(
             !range.is_empty(),
@@ -599,9 +797,45 @@ where
     fn submit_transaction(
         &self,
         tx: Transaction,
+    ) -> Result<NonEmpty<net::SocketAddr>, handle::Error> {
+        self.submit_transaction_with(tx, Broadcast::All)
+    }
+
+    fn submit_transaction_with(
+        &self,
+        tx: Transaction,
+        broadcast: Broadcast,
+    ) -> Result<NonEmpty<net::SocketAddr>, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::SubmitTransaction(tx, broadcast, transmit))?;
+
+        receive.recv()?.map_err(handle::Error::Command)
+    }
+
+    fn submit_package(
+        &self,
+        txs: Vec<Transaction>,
+    ) -> Result<NonEmpty<net::SocketAddr>, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::SubmitPackage(txs, transmit))?;
+
+        receive.recv()?.map_err(handle::Error::Command)
+    }
+
+    fn pending_transactions(&self) -> Result<Vec<Transaction>, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::GetPendingTransactions(transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
+    fn bump_fee(
+        &self,
+        txid: Txid,
+        bump: FeeBump,
     ) -> Result<NonEmpty<net::SocketAddr>, handle::Error> {
         let (transmit, receive) = chan::bounded(1);
-        self.command(Command::SubmitTransaction(tx, transmit))?;
+        self.command(Command::BumpFee(txid, bump, transmit))?;
 
         receive.recv()?.map_err(handle::Error::Command)
     }
@@ -31,6 +31,9 @@ pub enum Error {
     /// An error coming from the filter store.
     #[error(transparent)]
     FilterStore(#[from] chain::filter::store::Error),
+    /// An error loading the header snapshot.
+    #[error("error loading snapshot: {0}")]
+    Snapshot(#[from] chain::snapshot::Error),
     /// An error coming from the peer store.
     #[error("error loading peers: {0}")]
     PeerStore(io::Error),
@@ -1,7 +1,8 @@
 //! Client events.
 use std::fmt;
 
-use bitcoin::{Transaction, Txid};
+use bitcoin::{OutPoint, Transaction, Txid};
+use nakamoto_common::block::time::{LocalDuration, LocalTime};
 use nakamoto_common::block::{BlockHash, BlockHeader, Height};
 
 use crate::spv::TxStatus;
@@ -30,6 +31,24 @@ pub enum Event {
         /// Height of the block when it was part of the main chain.
         height: Height,
     },
+    /// The main chain was re-organized. This event follows the [`Event::BlockDisconnected`]
+    /// and [`Event::BlockConnected`] events of the re-org, and describes it as a whole.
+    Reorg {
+        /// Height of the last block shared by the old and new main chains.
+        fork_height: Height,
+        /// Blocks removed from the main chain, in ascending height order.
+        reverted: Vec<(Height, BlockHash)>,
+        /// Blocks added to the main chain, in ascending height order.
+        connected: Vec<(Height, BlockHeader)>,
+    },
+    /// A re-org at least as deep as [`crate::client::Config::deep_reorg_depth`] happened.
+    /// This is unusual on a healthy network, and may indicate an attack.
+    DeepReorg {
+        /// Height of the last block shared by the old and new main chains.
+        fork_height: Height,
+        /// Number of blocks reverted.
+        depth: usize,
+    },
     /// A block has matched one of the filters and is ready to be processed.
     /// This event usually precedes [`Event::TxStatusChanged`] events.
     BlockMatched {
@@ -42,6 +61,17 @@ pub enum Event {
         /// Transactions in this block.
         transactions: Vec<Transaction>,
     },
+    /// Block header sync progressed. Emitted while headers are being synced.
+    HeaderSyncProgress {
+        /// Height of our header chain.
+        height: Height,
+        /// Best height known from our peers.
+        best: Height,
+        /// Average number of headers synced per second.
+        headers_per_sec: f64,
+        /// Estimated time until headers are synced, if it can be estimated.
+        eta: Option<LocalDuration>,
+    },
     /// A filter was processed. If it matched any of the scripts in the watchlist,
     /// the corresponding block was scheduled for download, and a [`Event::BlockMatched`]
     /// event will eventually be fired.
@@ -60,6 +90,32 @@ pub enum Event {
         /// The new transaction status.
         status: TxStatus,
     },
+    /// A watched outpoint was spent in a block of the active chain.
+    /// See [`crate::handle::Handle::watch_outpoint`].
+    OutpointSpent {
+        /// The spent outpoint.
+        outpoint: OutPoint,
+        /// The transaction spending the outpoint.
+        spending_tx: Transaction,
+        /// Height of the block in which the outpoint was spent.
+        height: Height,
+    },
+    /// The block spending a watched outpoint was reverted due to a re-org, and the outpoint
+    /// is unspent again. It may be spent again in a block of the new active chain.
+    OutpointReverted {
+        /// The outpoint.
+        outpoint: OutPoint,
+        /// The reverted transaction which was spending the outpoint.
+        spending_tx: Transaction,
+    },
+    /// An unconfirmed transaction paying to one of the watched scripts was seen on the
+    /// network. Only emitted if mempool monitoring is enabled.
+    TxUnconfirmed {
+        /// The unconfirmed transaction.
+        transaction: Transaction,
+        /// Time at which the transaction was first seen.
+        first_seen: LocalTime,
+    },
     /// Compact filters have been synced and processed up to this point and matching blocks have
     /// been fetched.
     ///
@@ -90,6 +146,26 @@ impl fmt::Display for Event {
             Self::BlockDisconnected { hash, height } => {
                 write!(fmt, "block {} disconnected at height {}", hash, height)
             }
+            Self::Reorg {
+                fork_height,
+                reverted,
+                connected,
+            } => {
+                write!(
+                    fmt,
+                    "chain re-organized at height {} ({} block(s) reverted, {} connected)",
+                    fork_height,
+                    reverted.len(),
+                    connected.len()
+                )
+            }
+            Self::DeepReorg { fork_height, depth } => {
+                write!(
+                    fmt,
+                    "deep re-org of {} block(s) at height {}",
+                    depth, fork_height
+                )
+            }
             Self::BlockMatched { hash, height, .. } => {
                 write!(
                     fmt,
@@ -97,6 +173,9 @@ impl fmt::Display for Event {
                     hash, height
                 )
             }
+            Self::HeaderSyncProgress { height, best, .. } => {
+                write!(fmt, "headers synced up to height {} of {}", height, best)
+            }
             Self::FilterProcessed {
                 height, matched, ..
             } => {
@@ -109,6 +188,33 @@ impl fmt::Display for Event {
             Self::TxStatusChanged { txid, status } => {
                 write!(fmt, "transaction {} status changed: {}", txid, status)
             }
+            Self::OutpointSpent {
+                outpoint,
+                spending_tx,
+                height,
+            } => {
+                write!(
+                    fmt,
+                    "outpoint {} spent by transaction {} at height {}",
+                    outpoint,
+                    spending_tx.txid(),
+                    height
+                )
+            }
+            Self::OutpointReverted {
+                outpoint,
+                spending_tx,
+            } => {
+                write!(
+                    fmt,
+                    "outpoint {} spend by transaction {} reverted",
+                    outpoint,
+                    spending_tx.txid()
+                )
+            }
+            Self::TxUnconfirmed { transaction, .. } => {
+                write!(fmt, "unconfirmed transaction {} seen", transaction.txid())
+            }
             Self::Synced { height, .. } => write!(fmt, "filters synced up to height {}", height),
         }
     }
@@ -1,20 +1,24 @@
 //! Node handles are created from nodes by users of the library, to communicate with the underlying
 //! protocol instance.
+use std::iter;
 use std::net;
 use std::ops::{RangeBounds, RangeInclusive};
 
 use bitcoin::network::constants::ServiceFlags;
 use bitcoin::network::Address;
-use bitcoin::Script;
+use bitcoin::util::merkleblock::MerkleBlockError;
+use bitcoin::{MerkleBlock, OutPoint, Script, Txid};
 use crossbeam_channel as chan;
 use thiserror::Error;
 
 use nakamoto_common::block::filter::BlockFilter;
 use nakamoto_common::block::tree::ImportResult;
 use nakamoto_common::block::{self, Block, BlockHash, BlockHeader, Height, Transaction};
-use nakamoto_common::network::Network;
+use nakamoto_common::network::{Network, NetworkParams};
 use nakamoto_common::nonempty::NonEmpty;
-use nakamoto_p2p::protocol::{self, Command, CommandError, GetFiltersError, Peer};
+use nakamoto_p2p::protocol::fees::FeeBump;
+use nakamoto_p2p::protocol::invmgr::Broadcast;
+use nakamoto_p2p::protocol::{self, Command, CommandError, GetFiltersError, Peer, SyncStatus};
 use nakamoto_p2p::{bitcoin::network::message::NetworkMessage, protocol::Link};
 
 use crate::client::Event;
@@ -34,6 +38,23 @@ pub enum Error {
     /// The operation timed out.
     #[error("the operation timed out")]
     Timeout,
+    /// The block is not on the active chain.
+    #[error("block not found on the active chain")]
+    UnknownBlock,
+    /// The block couldn't be fetched from the network.
+    #[error("failed to fetch block {hash} after {attempts} attempt(s)")]
+    FetchBlock {
+        /// Block hash.
+        hash: BlockHash,
+        /// Number of block requests made.
+        attempts: usize,
+    },
+    /// The transaction is not included in the block.
+    #[error("transaction {0} not found in block")]
+    UnknownTransaction(Txid),
+    /// The transaction proof is invalid.
+    #[error("invalid transaction proof: {0:?}")]
+    InvalidProof(MerkleBlockError),
     /// An I/O error occured.
     #[error(transparent)]
     Io(#[from] std::io::Error),
@@ -62,12 +83,77 @@ impl<T> From<chan::SendError<T>> for Error {
 
 /// A handle for communicating with a node process.
 pub trait Handle: Sized + Send + Sync + Clone {
-    /// Get the Bitcoin network the client is configured for.
-    fn network(&self) -> Network;
+    /// Get the Bitcoin network the client is configured for. On custom signets, this is
+    /// [`Network::Signet`]; use [`Handle::network_params`] to tell signets apart.
+    fn network(&self) -> Network {
+        self.network_params().network
+    }
+    /// Get the parameters of the network the client is configured for, eg. its name and
+    /// magic number.
+    fn network_params(&self) -> NetworkParams;
     /// Get the tip of the chain.
     fn get_tip(&self) -> Result<(Height, BlockHeader), Error>;
+    /// Get the synchronization status of headers, filter headers, filters and blocks.
+    fn sync_status(&self) -> Result<SyncStatus, Error>;
+    /// Get a block header by hash, along with its height. Only headers on the active chain
+    /// are returned.
+    fn get_header(&self, hash: &BlockHash) -> Result<Option<(Height, BlockHeader)>, Error>;
+    /// Get the active chain headers in the given height range. Heights past the tip are
+    /// ignored.
+    fn get_headers(&self, range: RangeInclusive<Height>) -> Result<Vec<BlockHeader>, Error>;
+    /// Check whether a block is an ancestor of another block. A block is considered to be
+    /// its own ancestor.
+    fn is_ancestor(&self, ancestor: &BlockHash, hash: &BlockHash) -> Result<bool, Error>;
+    /// Find the point at which a block's branch forks off the active chain. Returns the
+    /// block itself if it is on the active chain, and `None` if the block is unknown or
+    /// doesn't connect.
+    fn find_fork(&self, hash: &BlockHash) -> Result<Option<(Height, BlockHeader)>, Error>;
+    /// Get the tips of known stale branches, ie. branches that fork off the active chain.
+    fn get_stale_tips(&self) -> Result<Vec<(Height, BlockHeader)>, Error>;
     /// Get a full block from the network.
     fn get_block(&self, hash: &BlockHash) -> Result<(), Error>;
+    /// Fetch a full block on the active chain from the network, and wait for it to be
+    /// received. Returns an error if the block couldn't be fetched after retrying.
+    fn fetch_block(&self, hash: &BlockHash) -> Result<(Block, Height), Error>;
+    /// Fetch the full block at the given height of the active chain from the network.
+    /// See [`Handle::fetch_block`].
+    fn fetch_block_at(&self, height: Height) -> Result<(Block, Height), Error>;
+    /// Get a proof that a transaction is included in a block of the active chain.
+    ///
+    /// The block is fetched from the network, and its transactions are checked against the
+    /// merkle root of our own header before the proof is created. The proof can be checked
+    /// with [`Handle::verify_tx_proof`].
+    fn get_tx_proof(&self, txid: &Txid, block: &BlockHash) -> Result<MerkleBlock, Error> {
+        let (block, _) = self.fetch_block(block)?;
+
+        // Nb. The block header is the one we have stored, since it has the requested hash.
+        if !block.check_merkle_root() {
+            return Err(Error::InvalidProof(MerkleBlockError::MerkleRootMismatch));
+        }
+        if !block.txdata.iter().any(|tx| tx.txid() == *txid) {
+            return Err(Error::UnknownTransaction(*txid));
+        }
+        Ok(MerkleBlock::from_block(
+            &block,
+            &iter::once(*txid).collect(),
+        ))
+    }
+    /// Verify a transaction proof against the active chain. Returns the height of the block
+    /// and the transactions proven to be included in it.
+    fn verify_tx_proof(&self, proof: &MerkleBlock) -> Result<(Height, Vec<Txid>), Error> {
+        let (height, _) = self
+            .get_header(&proof.header.block_hash())?
+            .ok_or(Error::UnknownBlock)?;
+
+        let mut matches = Vec::new();
+        let mut indexes = Vec::new();
+
+        proof
+            .extract_matches(&mut matches, &mut indexes)
+            .map_err(Error::InvalidProof)?;
+
+        Ok((height, matches))
+    }
     /// Get compact filters from the network.
     fn get_filters(&self, range: RangeInclusive<Height>) -> Result<(), Error>;
     /// Subscribe to blocks received.
@@ -105,6 +191,26 @@ pub trait Handle: Sized + Send + Sync + Clone {
 
         Ok(())
     }
+    /// Watch an outpoint for spends, starting from the given height.
+    ///
+    /// The script of the output is used to match compact filters, since filters include the
+    /// scripts spent by a block's inputs. When the outpoint is spent in a block of the active
+    /// chain, an [`Event::OutpointSpent`] event is emitted. If that block is reverted, an
+    /// [`Event::OutpointReverted`] event follows, and the outpoint is watched again.
+    fn watch_outpoint(
+        &self,
+        outpoint: OutPoint,
+        script: Script,
+        from: Height,
+    ) -> Result<(), Error> {
+        self.command(Command::WatchOutpoint {
+            outpoint,
+            script,
+            from,
+        })?;
+
+        Ok(())
+    }
     /// Broadcast a message to peers matching the predicate.
     ///
     /// To only broadcast to peers that have completed the handshake, filter
@@ -127,6 +233,40 @@ pub trait Handle: Sized + Send + Sync + Clone {
     ///
     /// Returns the peer(s) the transaction was announced to, or an error if no peers were found.
     fn submit_transaction(&self, txs: Transaction) -> Result<NonEmpty<net::SocketAddr>, Error>;
+    /// Submit a transaction to the network, using the given broadcast strategy.
+    ///
+    /// With [`Broadcast::Single`] or [`Broadcast::Ephemeral`], the transaction is sent to a
+    /// single peer, which makes it harder to link the transaction to our address. It is sent to
+    /// a different peer if it isn't relayed back to us by other peers within
+    /// [`protocol::invmgr::PRIVATE_BROADCAST_TIMEOUT`].
+    ///
+    /// Returns the peer(s) the transaction was announced to, or an error if no peers were found.
+    fn submit_transaction_with(
+        &self,
+        tx: Transaction,
+        broadcast: Broadcast,
+    ) -> Result<NonEmpty<net::SocketAddr>, Error>;
+    /// Submit a package of dependent transactions to the network, eg. a parent transaction
+    /// and a child paying for it.
+    ///
+    /// The transactions may be given in any order. Parents are announced first, and children
+    /// are only announced to peers once they requested the parents, or after a short delay.
+    /// The fee rate is checked for the package as a whole.
+    ///
+    /// Returns the peer(s) the parents were announced to, or an error if no peers were found.
+    fn submit_package(&self, txs: Vec<Transaction>) -> Result<NonEmpty<net::SocketAddr>, Error>;
+    /// Get the transactions submitted with [`Handle::submit_transaction`] that are not yet
+    /// confirmed. These are re-announced periodically, and across client restarts, until they
+    /// confirm or expire.
+    fn pending_transactions(&self) -> Result<Vec<Transaction>, Error>;
+    /// Bump the fee of a transaction previously submitted with [`Handle::submit_transaction`],
+    /// either by replacing it, or by spending one of its outputs.
+    ///
+    /// The fee-bumping transaction must be signed. Unsigned fee bumps can be built with
+    /// [`protocol::fees::build_replacement`] and [`protocol::fees::build_child`].
+    ///
+    /// Returns the peer(s) the fee-bumping transaction was announced to.
+    fn bump_fee(&self, txid: Txid, bump: FeeBump) -> Result<NonEmpty<net::SocketAddr>, Error>;
     /// Import block headers into the node.
     /// This may cause the node to broadcast header or inventory messages to its peers.
     fn import_headers(
@@ -6,6 +6,7 @@ pub mod client;
 pub mod error;
 pub mod event;
 pub mod handle;
+pub mod mempool;
 pub mod peer;
 pub mod spv;
 
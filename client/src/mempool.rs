@@ -0,0 +1,363 @@
+//! Persistence of submitted transactions that are pending confirmation.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{fs, io};
+
+use bitcoin::consensus::encode;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::{Transaction, Txid};
+use microserde::json::{Number, Object, Value};
+
+use nakamoto_common::block::time::{LocalDuration, LocalTime};
+use nakamoto_p2p::protocol::invmgr::Broadcast;
+use nakamoto_p2p::protocol::{self, invmgr};
+
+/// Time after which a pending transaction is dropped, and no longer re-announced.
+pub const PENDING_TRANSACTION_EXPIRY: LocalDuration = invmgr::MEMPOOL_EXPIRY;
+
+/// A submitted transaction, pending confirmation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pending {
+    /// The transaction.
+    pub transaction: Transaction,
+    /// How the transaction is broadcast.
+    pub broadcast: Broadcast,
+    /// Time at which the transaction was first submitted.
+    pub submitted: LocalTime,
+}
+
+/// A file-backed store of pending transactions.
+#[derive(Debug)]
+pub struct Store {
+    pending: BTreeMap<Txid, Pending>,
+    path: PathBuf,
+}
+
+impl Store {
+    /// Open the store at the given path. If there is no file at this path, the store starts
+    /// out empty, and the file is created on the first flush.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut pending = BTreeMap::new();
+
+        let s = match fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+
+        if !s.is_empty() {
+            let val = microserde::json::from_str(&s)
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+
+            match val {
+                Value::Object(obj) => {
+                    for (_, v) in obj.into_iter() {
+                        let p = Pending::from_json(v)
+                            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))?;
+
+                        pending.insert(p.transaction.txid(), p);
+                    }
+                }
+                _ => return Err(io::ErrorKind::InvalidData.into()),
+            }
+        }
+
+        Ok(Self { pending, path })
+    }
+
+    /// Add a pending transaction. If the transaction is already in the store, it is left
+    /// unchanged. Returns whether the transaction was added.
+    pub fn insert(
+        &mut self,
+        transaction: Transaction,
+        broadcast: Broadcast,
+        submitted: LocalTime,
+    ) -> bool {
+        let txid = transaction.txid();
+
+        if self.pending.contains_key(&txid) {
+            return false;
+        }
+        self.pending.insert(
+            txid,
+            Pending {
+                transaction,
+                broadcast,
+                submitted,
+            },
+        );
+        true
+    }
+
+    /// Remove a transaction, eg. because it was confirmed.
+    pub fn remove(&mut self, txid: &Txid) -> Option<Pending> {
+        self.pending.remove(txid)
+    }
+
+    /// Get a pending transaction.
+    pub fn get(&self, txid: &Txid) -> Option<&Pending> {
+        self.pending.get(txid)
+    }
+
+    /// Remove and return the transactions submitted longer than `expiry` ago.
+    pub fn expire(&mut self, now: LocalTime, expiry: LocalDuration) -> Vec<Pending> {
+        let expired = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.submitted + expiry <= now)
+            .map(|(txid, _)| *txid)
+            .collect::<Vec<_>>();
+
+        expired
+            .iter()
+            .filter_map(|txid| self.pending.remove(txid))
+            .collect()
+    }
+
+    /// Iterate over the pending transactions.
+    pub fn iter(&self) -> impl Iterator<Item = &Pending> {
+        self.pending.values()
+    }
+
+    /// Number of pending transactions.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether there are no pending transactions.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Write the pending transactions to disk.
+    pub fn flush(&self) -> io::Result<()> {
+        let obj: Object = self
+            .pending
+            .iter()
+            .map(|(txid, p)| (txid.to_string(), p.to_json()))
+            .collect();
+        let mut s = microserde::json::to_string(&Value::Object(obj));
+        s.push('\n');
+
+        // Write to a temporary file first, so that the store is never left half-written.
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, s)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+impl Pending {
+    fn to_json(&self) -> Value {
+        let mut obj = Object::new();
+
+        obj.insert(
+            "transaction".to_owned(),
+            Value::String(encode::serialize_hex(&self.transaction)),
+        );
+        obj.insert(
+            "broadcast".to_owned(),
+            Value::String(
+                match self.broadcast {
+                    Broadcast::All => "all",
+                    Broadcast::Single => "single",
+                    Broadcast::Ephemeral => "ephemeral",
+                }
+                .to_owned(),
+            ),
+        );
+        obj.insert(
+            "submitted".to_owned(),
+            Value::Number(Number::U64(self.submitted.block_time() as u64)),
+        );
+        Value::Object(obj)
+    }
+
+    fn from_json(v: Value) -> Option<Self> {
+        let obj = match v {
+            Value::Object(obj) => obj,
+            _ => return None,
+        };
+        let transaction = match obj.get("transaction") {
+            Some(Value::String(hex)) => {
+                let bytes = Vec::<u8>::from_hex(hex).ok()?;
+                encode::deserialize(&bytes).ok()?
+            }
+            _ => return None,
+        };
+        // Transactions stored before the broadcast strategy was recorded are broadcast to all.
+        let broadcast = match obj.get("broadcast").map(|v| match v {
+            Value::String(s) => s.as_str(),
+            _ => "",
+        }) {
+            None | Some("all") => Broadcast::All,
+            Some("single") => Broadcast::Single,
+            Some("ephemeral") => Broadcast::Ephemeral,
+            Some(_) => return None,
+        };
+        let submitted = match obj.get("submitted") {
+            Some(Value::Number(Number::U64(secs))) => LocalTime::from_secs(*secs),
+            _ => return None,
+        };
+
+        Some(Self {
+            transaction,
+            broadcast,
+            submitted,
+        })
+    }
+}
+
+/// Keeps a [`Store`] up to date with the transactions announced and confirmed by the protocol.
+/// Until a store is set, events are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct Persister {
+    store: Arc<Mutex<Option<Store>>>,
+}
+
+impl Persister {
+    /// Set the store to keep up to date.
+    pub fn set(&self, store: Store) {
+        *self.store.lock().unwrap() = Some(store);
+    }
+}
+
+impl protocol::event::Publisher for Persister {
+    fn publish(&mut self, event: protocol::Event) {
+        let mut store = self.store.lock().unwrap();
+        let store = if let Some(store) = &mut *store {
+            store
+        } else {
+            return;
+        };
+
+        let changed = match event {
+            protocol::Event::InventoryManager(invmgr::Event::Announced {
+                transaction,
+                broadcast,
+            }) => store.insert(transaction, broadcast, LocalTime::now()),
+            protocol::Event::InventoryManager(invmgr::Event::Confirmed { transaction, .. }) => {
+                store.remove(&transaction.txid()).is_some()
+            }
+            protocol::Event::InventoryManager(invmgr::Event::Replaced { txid, .. })
+            | protocol::Event::InventoryManager(invmgr::Event::Expired { txid }) => {
+                store.remove(&txid).is_some()
+            }
+            _ => false,
+        };
+
+        if changed {
+            if let Err(err) = store.flush() {
+                log::error!("Error writing pending transactions to disk: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nakamoto_common::block::BlockHash;
+    use nakamoto_p2p::protocol::event::Publisher as _;
+    use nakamoto_test::block::gen;
+
+    #[test]
+    fn test_save_and_load() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("mempool.json");
+        let mut rng = fastrand::Rng::new();
+        let now = LocalTime::from_secs(1_600_000_000);
+
+        let old = gen::transaction(&mut rng);
+        let new = gen::transaction(&mut rng);
+
+        {
+            let mut store = Store::open(&path).unwrap();
+            assert!(store.is_empty());
+
+            assert!(store.insert(old.clone(), Broadcast::All, now));
+            assert!(store.insert(
+                new.clone(),
+                Broadcast::Ephemeral,
+                now + LocalDuration::from_mins(60)
+            ));
+            assert!(!store.insert(
+                new.clone(),
+                Broadcast::All,
+                now + LocalDuration::from_mins(120)
+            ));
+
+            store.flush().unwrap();
+        }
+
+        let mut store = Store::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+
+        let expired = store.expire(now + PENDING_TRANSACTION_EXPIRY, PENDING_TRANSACTION_EXPIRY);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].transaction, old);
+        assert_eq!(
+            store.iter().cloned().collect::<Vec<_>>(),
+            vec![Pending {
+                transaction: new,
+                broadcast: Broadcast::Ephemeral,
+                submitted: now + LocalDuration::from_mins(60),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_persister() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("mempool.json");
+        let mut rng = fastrand::Rng::new();
+        let mut persister = Persister::default();
+
+        let tx = gen::transaction(&mut rng);
+        let announced = protocol::Event::InventoryManager(invmgr::Event::Announced {
+            transaction: tx.clone(),
+            broadcast: Broadcast::All,
+        });
+
+        // Without a store, events are ignored.
+        persister.publish(announced.clone());
+        assert!(!path.exists());
+
+        persister.set(Store::open(&path).unwrap());
+        persister.publish(announced);
+        assert_eq!(
+            Store::open(&path)
+                .unwrap()
+                .iter()
+                .map(|p| p.transaction.clone())
+                .collect::<Vec<_>>(),
+            vec![tx.clone()]
+        );
+
+        persister.publish(protocol::Event::InventoryManager(
+            invmgr::Event::Confirmed {
+                transaction: tx,
+                block: BlockHash::default(),
+                height: 1,
+            },
+        ));
+        assert!(Store::open(&path).unwrap().is_empty());
+
+        // Expired transactions are removed from the store.
+        let tx = gen::transaction(&mut rng);
+
+        persister.publish(protocol::Event::InventoryManager(
+            invmgr::Event::Announced {
+                transaction: tx.clone(),
+                broadcast: Broadcast::All,
+            },
+        ));
+        assert_eq!(Store::open(&path).unwrap().len(), 1);
+
+        persister.publish(protocol::Event::InventoryManager(invmgr::Event::Expired {
+            txid: tx.txid(),
+        }));
+        assert!(Store::open(&path).unwrap().is_empty());
+    }
+}
@@ -6,12 +6,12 @@ pub mod utxos;
 #[cfg(test)]
 mod tests;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::{fmt, net};
 
 use p2p::event::Emitter;
 
-use bitcoin::{Block, Txid};
+use bitcoin::{Block, OutPoint, Transaction, Txid};
 
 use nakamoto_common::block::{BlockHash, Height};
 use nakamoto_p2p as p2p;
@@ -33,6 +33,15 @@ pub enum TxStatus {
         /// Peer acknowledging the transaction.
         peer: net::SocketAddr,
     },
+    /// Transaction was requested from us, or announced to us without having been requested,
+    /// by several peers.
+    ///
+    /// This means the transaction propagated through the network, and is likely to be in the
+    /// mempool of other nodes. Transactions that don't propagate are re-announced periodically.
+    Propagated {
+        /// Peers known to have the transaction so far.
+        peers: Vec<net::SocketAddr>,
+    },
     /// Transaction was included in a block. This event is fired after
     /// a block from the main chain is scanned.
     Confirmed {
@@ -55,6 +64,15 @@ pub enum TxStatus {
         /// Block of the included transaction.
         block: BlockHash,
     },
+    /// Transaction was replaced in the mempool by another transaction of ours, eg. to bump
+    /// its fee. It will no longer be announced, and will probably never be included in a block.
+    Replaced {
+        /// Transaction replacing the given transaction.
+        replaced_by: Txid,
+    },
+    /// Transaction didn't confirm within the configured expiry, and was dropped. It will no
+    /// longer be announced.
+    Expired,
 }
 
 impl fmt::Display for TxStatus {
@@ -64,6 +82,9 @@ impl fmt::Display for TxStatus {
             Self::Acknowledged { peer } => {
                 write!(fmt, "transaction was acknowledged by peer {}", peer)
             }
+            Self::Propagated { peers } => {
+                write!(fmt, "transaction was relayed by {} peer(s)", peers.len())
+            }
             Self::Confirmed { height, block } => write!(
                 fmt,
                 "transaction was included in block {} at height {}",
@@ -75,6 +96,10 @@ impl fmt::Display for TxStatus {
                 "transaction was replaced by {} in block {}",
                 replaced_by, block
             ),
+            Self::Replaced { replaced_by } => {
+                write!(fmt, "transaction was replaced by {}", replaced_by)
+            }
+            Self::Expired => write!(fmt, "transaction has expired"),
         }
     }
 }
@@ -94,6 +119,9 @@ pub struct Mapper {
     block_height: Height,
     /// Filter heights that have been matched, and for which we are awaiting a block to process.
     pending: HashSet<Height>,
+    /// Transactions we submitted, which are not yet confirmed.
+    /// Used to detect conflicting spends in matched blocks.
+    unconfirmed: HashMap<Txid, Transaction>,
 }
 
 impl Mapper {
@@ -104,6 +132,7 @@ impl Mapper {
         let filter_height = 0;
         let block_height = 0;
         let pending = HashSet::new();
+        let unconfirmed = HashMap::new();
 
         Self {
             tip,
@@ -111,6 +140,7 @@ impl Mapper {
             filter_height,
             block_height,
             pending,
+            unconfirmed,
         }
     }
 
@@ -125,28 +155,131 @@ impl Mapper {
             protocol::Event::SyncManager(syncmgr::Event::BlockConnected { header, height }) => {
                 emitter.emit(Event::BlockConnected { header, height });
             }
+            protocol::Event::SyncManager(syncmgr::Event::HeaderSyncProgress {
+                height,
+                best,
+                headers_per_sec,
+                eta,
+            }) => {
+                emitter.emit(Event::HeaderSyncProgress {
+                    height,
+                    best,
+                    headers_per_sec,
+                    eta,
+                });
+            }
             protocol::Event::SyncManager(syncmgr::Event::BlockDisconnected { hash, height }) => {
                 emitter.emit(Event::BlockDisconnected { hash, height });
             }
+            protocol::Event::SyncManager(syncmgr::Event::Reorg {
+                fork_height,
+                reverted,
+                connected,
+            }) => {
+                emitter.emit(Event::Reorg {
+                    fork_height,
+                    reverted,
+                    connected,
+                });
+            }
+            protocol::Event::SyncManager(syncmgr::Event::DeepReorg { fork_height, depth }) => {
+                log::warn!(
+                    "Deep re-org of {} block(s) detected at height {}",
+                    depth,
+                    fork_height
+                );
+                emitter.emit(Event::DeepReorg { fork_height, depth });
+            }
             protocol::Event::InventoryManager(invmgr::Event::BlockProcessed { block, height }) => {
                 self.process_block(block, height, emitter);
             }
+            protocol::Event::InventoryManager(invmgr::Event::Announced { transaction, .. }) => {
+                self.unconfirmed.insert(transaction.txid(), transaction);
+            }
+            protocol::Event::InventoryManager(invmgr::Event::TxReceived {
+                transaction,
+                first_seen,
+                ..
+            }) => {
+                emitter.emit(Event::TxUnconfirmed {
+                    transaction,
+                    first_seen,
+                });
+            }
+            protocol::Event::InventoryManager(invmgr::Event::Replaced { txid, replaced_by }) => {
+                self.unconfirmed.remove(&txid);
+
+                emitter.emit(Event::TxStatusChanged {
+                    txid,
+                    status: TxStatus::Replaced { replaced_by },
+                });
+            }
+            protocol::Event::InventoryManager(invmgr::Event::Expired { txid }) => {
+                self.unconfirmed.remove(&txid);
+
+                emitter.emit(Event::TxStatusChanged {
+                    txid,
+                    status: TxStatus::Expired,
+                });
+            }
             protocol::Event::InventoryManager(invmgr::Event::Confirmed {
                 transaction,
                 height,
                 block,
             }) => {
+                let txid = transaction.txid();
+
+                self.unconfirmed.remove(&txid);
+
                 emitter.emit(Event::TxStatusChanged {
-                    txid: transaction.txid(),
+                    txid,
                     status: TxStatus::Confirmed { height, block },
                 });
             }
+            protocol::Event::InventoryManager(invmgr::Event::Reverted { transaction }) => {
+                let txid = transaction.txid();
+
+                // Reverted transactions are unconfirmed again, and may be replaced
+                // by a conflicting transaction in a future block.
+                self.unconfirmed.insert(txid, transaction);
+
+                emitter.emit(Event::TxStatusChanged {
+                    txid,
+                    status: TxStatus::Reverted,
+                });
+            }
             protocol::Event::InventoryManager(invmgr::Event::Acknowledged { txid, peer }) => {
                 emitter.emit(Event::TxStatusChanged {
                     txid,
                     status: TxStatus::Acknowledged { peer },
                 });
             }
+            protocol::Event::InventoryManager(invmgr::Event::Propagated { txid, peers }) => {
+                emitter.emit(Event::TxStatusChanged {
+                    txid,
+                    status: TxStatus::Propagated { peers },
+                });
+            }
+            protocol::Event::InventoryManager(invmgr::Event::OutpointSpent {
+                outpoint,
+                spending_tx,
+                height,
+            }) => {
+                emitter.emit(Event::OutpointSpent {
+                    outpoint,
+                    spending_tx,
+                    height,
+                });
+            }
+            protocol::Event::InventoryManager(invmgr::Event::OutpointReverted {
+                outpoint,
+                spending_tx,
+            }) => {
+                emitter.emit(Event::OutpointReverted {
+                    outpoint,
+                    spending_tx,
+                });
+            }
             protocol::Event::FilterManager(cbfmgr::Event::FilterProcessed {
                 block,
                 height,
@@ -194,9 +327,11 @@ impl Mapper {
         let hash = block.block_hash();
 
         log::debug!("Received block {} at height {}", hash, height);
-        debug_assert!(height >= self.block_height);
 
-        self.block_height = height;
+        // Nb. Blocks below the current height are processed when a rescan is rewound.
+        self.block_height = Height::max(height, self.block_height);
+
+        let stale = self.conflicts(&block);
 
         emitter.emit(Event::BlockMatched {
             height,
@@ -204,6 +339,46 @@ impl Mapper {
             header: block.header,
             transactions: block.txdata,
         });
+
+        for (txid, replaced_by) in stale {
+            self.unconfirmed.remove(&txid);
+
+            emitter.emit(Event::TxStatusChanged {
+                txid,
+                status: TxStatus::Stale {
+                    replaced_by,
+                    block: hash,
+                },
+            });
+        }
+    }
+
+    /// Find unconfirmed transactions of ours that conflict with a transaction in the given block,
+    /// ie. that spend one of the same outputs. Returns the stale transaction and the transaction
+    /// replacing it.
+    fn conflicts(&self, block: &Block) -> Vec<(Txid, Txid)> {
+        if self.unconfirmed.is_empty() {
+            return Vec::new();
+        }
+        let spent = self
+            .unconfirmed
+            .iter()
+            .flat_map(|(txid, tx)| tx.input.iter().map(move |i| (i.previous_output, *txid)))
+            .collect::<HashMap<OutPoint, Txid>>();
+        let mut stale = Vec::new();
+
+        for tx in &block.txdata {
+            let replaced_by = tx.txid();
+
+            for input in &tx.input {
+                if let Some(txid) = spent.get(&input.previous_output) {
+                    if *txid != replaced_by && !stale.iter().any(|(t, _)| t == txid) {
+                        stale.push((*txid, replaced_by));
+                    }
+                }
+            }
+        }
+        stale
     }
 
     fn process_filter(
@@ -213,13 +388,12 @@ impl Mapper {
         matched: bool,
         emitter: &Emitter<Event>,
     ) {
-        debug_assert!(height >= self.filter_height);
-
         if matched {
             log::debug!("Filter matched for block #{}", height);
             self.pending.insert(height);
         }
-        self.filter_height = height;
+        // Nb. Filters below the current height are processed when a rescan is rewound.
+        self.filter_height = Height::max(height, self.filter_height);
 
         emitter.emit(Event::FilterProcessed {
             height,
@@ -146,6 +146,13 @@ fn test_tx_status_ordering() {
     assert!(
         TxStatus::Acknowledged {
             peer: ([0, 0, 0, 0], 0).into()
+        } < TxStatus::Propagated {
+            peers: vec![([0, 0, 0, 0], 0).into()]
+        }
+    );
+    assert!(
+        TxStatus::Propagated {
+            peers: vec![([0, 0, 0, 0], 0).into()]
         } < TxStatus::Confirmed {
             height: 0,
             block: BlockHash::default(),
@@ -164,4 +171,198 @@ fn test_tx_status_ordering() {
                 block: BlockHash::default()
             }
     );
+    assert!(
+        TxStatus::Stale {
+            replaced_by: Default::default(),
+            block: BlockHash::default()
+        } < TxStatus::Replaced {
+            replaced_by: Default::default(),
+        }
+    );
+}
+
+#[test]
+fn test_tx_propagated() {
+    let mut rng = fastrand::Rng::new();
+    let tx = gen::transaction(&mut rng);
+    let txid = tx.txid();
+    let peer1: net::SocketAddr = ([88, 88, 88, 88], 8333).into();
+    let peer2: net::SocketAddr = ([99, 99, 99, 99], 8333).into();
+    let peer3: net::SocketAddr = ([77, 77, 77, 77], 8333).into();
+
+    let mut spv = super::Mapper::new();
+    let (mut publish, subscribe) = p2p::event::broadcast(move |e, p| spv.process(e, p));
+    let subscriber = subscribe.subscribe();
+
+    publish.broadcast(protocol::Event::InventoryManager(
+        invmgr::Event::Announced {
+            transaction: tx,
+            broadcast: invmgr::Broadcast::All,
+        },
+    ));
+    for peers in [vec![peer1, peer2], vec![peer1, peer2, peer3]] {
+        publish.broadcast(protocol::Event::InventoryManager(
+            invmgr::Event::Propagated { txid, peers },
+        ));
+    }
+
+    let statuses = subscriber
+        .try_iter()
+        .filter_map(|e| match e {
+            Event::TxStatusChanged { txid: t, status } if t == txid => Some(status),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        statuses,
+        vec![
+            TxStatus::Propagated {
+                peers: vec![peer1, peer2]
+            },
+            TxStatus::Propagated {
+                peers: vec![peer1, peer2, peer3]
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_tx_reverted_and_stale() {
+    let mut rng = fastrand::Rng::with_seed(1);
+    let network = Network::Regtest;
+    let chain = gen::blockchain(network.genesis_block(), 2, &mut rng);
+    let tip = chain.last().header;
+
+    let tx = gen::transaction(&mut rng);
+    let txid = tx.txid();
+    let block1 = gen::block_with(&tip, vec![gen::coinbase(&mut rng), tx.clone()], &mut rng);
+    let height = chain.len() as Height;
+
+    // A conflicting transaction, spending one of the same inputs.
+    let mut conflict = gen::transaction(&mut rng);
+    conflict.input[0].previous_output = tx.input[0].previous_output;
+    let block2 = gen::block_with(
+        &tip,
+        vec![gen::coinbase(&mut rng), conflict.clone()],
+        &mut rng,
+    );
+
+    let mut spv = super::Mapper::new();
+    let (mut publish, subscribe) = p2p::event::broadcast(move |e, p| spv.process(e, p));
+    let subscriber = subscribe.subscribe();
+
+    publish.broadcast(protocol::Event::InventoryManager(
+        invmgr::Event::Announced {
+            transaction: tx.clone(),
+            broadcast: invmgr::Broadcast::All,
+        },
+    ));
+    publish.broadcast(protocol::Event::InventoryManager(
+        invmgr::Event::Confirmed {
+            transaction: tx.clone(),
+            height,
+            block: block1.block_hash(),
+        },
+    ));
+    publish.broadcast(protocol::Event::SyncManager(
+        syncmgr::Event::BlockDisconnected {
+            hash: block1.block_hash(),
+            height,
+        },
+    ));
+    publish.broadcast(protocol::Event::InventoryManager(invmgr::Event::Reverted {
+        transaction: tx,
+    }));
+    publish.broadcast(protocol::Event::FilterManager(
+        cbfmgr::Event::FilterProcessed {
+            block: block2.block_hash(),
+            height,
+            matched: true,
+        },
+    ));
+    publish.broadcast(protocol::Event::InventoryManager(
+        invmgr::Event::BlockProcessed {
+            block: block2.clone(),
+            height,
+        },
+    ));
+
+    let statuses = subscriber
+        .try_iter()
+        .filter_map(|e| match e {
+            Event::TxStatusChanged { txid: t, status } if t == txid => Some(status),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        statuses,
+        vec![
+            TxStatus::Confirmed {
+                height,
+                block: block1.block_hash()
+            },
+            TxStatus::Reverted,
+            TxStatus::Stale {
+                replaced_by: conflict.txid(),
+                block: block2.block_hash()
+            }
+        ]
+    );
+}
+
+#[test]
+fn test_outpoint_spent_after_rewind() {
+    let mut rng = fastrand::Rng::with_seed(1);
+    let network = Network::Regtest;
+    let chain = gen::blockchain(network.genesis_block(), 12, &mut rng);
+    let spending_tx = gen::transaction(&mut rng);
+    let outpoint = spending_tx.input[0].previous_output;
+    let (height, block) = (5, chain[5].clone());
+
+    let mut spv = super::Mapper::new();
+    let (mut publish, subscribe) = p2p::event::broadcast(move |e, p| spv.process(e, p));
+    let subscriber = subscribe.subscribe();
+
+    // Filters are processed up to height 10, then the rescan is rewound to height 5.
+    for (height, matched) in [(10, false), (5, true)] {
+        publish.broadcast(protocol::Event::FilterManager(
+            cbfmgr::Event::FilterProcessed {
+                block: chain[height as usize].block_hash(),
+                height,
+                matched,
+            },
+        ));
+    }
+    publish.broadcast(protocol::Event::InventoryManager(
+        invmgr::Event::BlockProcessed {
+            block: block.clone(),
+            height,
+        },
+    ));
+    publish.broadcast(protocol::Event::InventoryManager(
+        invmgr::Event::OutpointSpent {
+            outpoint,
+            spending_tx: spending_tx.clone(),
+            height,
+        },
+    ));
+
+    let events = subscriber.try_iter().collect::<Vec<_>>();
+
+    assert!(events.iter().any(
+        |e| matches!(e, Event::BlockMatched { height: 5, hash, .. } if *hash == block.block_hash())
+    ));
+    assert!(events.iter().any(|e| matches!(
+        e,
+        Event::OutpointSpent { outpoint: o, spending_tx: tx, height: 5 }
+        if *o == outpoint && *tx == spending_tx
+    )));
+    assert!(
+        !events
+            .iter()
+            .any(|e| matches!(e, Event::Synced { height, .. } if *height < 10)),
+        "The sync height never goes backwards"
+    );
 }
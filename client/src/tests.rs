@@ -10,7 +10,7 @@ use bitcoin::network::constants::ServiceFlags;
 use nakamoto_chain::block::cache::BlockCache;
 use nakamoto_chain::block::store;
 use nakamoto_chain::filter::cache::FilterCache;
-use nakamoto_common::block::Height;
+use nakamoto_common::block::{Height, Work};
 use nakamoto_common::network::Services;
 use nakamoto_p2p::protocol;
 use nakamoto_p2p::protocol::syncmgr;
@@ -34,10 +34,13 @@ fn network(
 > {
     let mut handles = Vec::new();
 
-    for cfg in cfgs.iter().cloned() {
-        let checkpoints = cfg.network.checkpoints().collect::<Vec<_>>();
+    for mut cfg in cfgs.iter().cloned() {
+        // These nodes form their own network, which never reaches the minimum chain work.
+        cfg.network.minimum_chain_work = Work::default();
+
+        let checkpoints = cfg.network.checkpoints.clone();
         let genesis = cfg.network.genesis();
-        let params = cfg.network.params();
+        let params = cfg.network.params.clone();
 
         let node = Client::new(cfg)?;
         let handle = node.handle();
@@ -154,7 +157,7 @@ fn test_multiple_handle_events() {
 
     let cfg = Config::default();
     let genesis = cfg.network.genesis();
-    let params = cfg.network.params();
+    let params = cfg.network.params.clone();
     let client: Client<Reactor> = Client::new(cfg).unwrap();
     let store = store::Memory::new((genesis, vec![]).into());
     let cache = BlockCache::from(store, params, &[]).unwrap();
@@ -195,7 +198,7 @@ fn test_multiple_handle_events() {
 fn test_handle_shutdown() {
     let cfg = Config::default();
     let genesis = cfg.network.genesis();
-    let params = cfg.network.params();
+    let params = cfg.network.params.clone();
     let client: Client<Reactor> = Client::new(cfg).unwrap();
     let handle = client.handle();
     let store = store::Memory::new((genesis, vec![]).into());
@@ -222,3 +225,80 @@ fn test_client_dropped() {
         Err(client::handle::Error::Disconnected)
     ));
 }
+
+#[test]
+fn test_verify_tx_proof() {
+    use bitcoin::util::merkleblock::MerkleBlockError;
+    use bitcoin::MerkleBlock;
+    use nakamoto_test::block::gen;
+
+    let mut rng = fastrand::Rng::new();
+    let network = nakamoto_common::network::Network::Regtest;
+    let mut handle = mock::Client::new(network).handle();
+
+    let block = gen::block(&network.genesis(), &mut rng);
+    let tx = rng.usize(..block.txdata.len());
+    let txid = block.txdata[tx].txid();
+    let proof = MerkleBlock::from_block(&block, &std::iter::once(txid).collect());
+
+    assert!(matches!(
+        handle.verify_tx_proof(&proof),
+        Err(client::handle::Error::UnknownBlock)
+    ));
+
+    handle.tip = (1, block.header);
+    assert_eq!(handle.verify_tx_proof(&proof).unwrap(), (1, vec![txid]));
+
+    // A proof for a different set of transactions doesn't match the header's merkle root.
+    let other = gen::block(&network.genesis(), &mut rng);
+    let forged = MerkleBlock {
+        header: block.header,
+        txn: MerkleBlock::from_block(&other, &std::iter::once(other.txdata[0].txid()).collect())
+            .txn,
+    };
+    assert!(matches!(
+        handle.verify_tx_proof(&forged),
+        Err(client::handle::Error::InvalidProof(
+            MerkleBlockError::MerkleRootMismatch
+        ))
+    ));
+}
+
+#[test]
+fn test_mempool_monitoring() {
+    use nakamoto_p2p::protocol::invmgr;
+    use nakamoto_test::block::gen;
+
+    logger::init(log::Level::Debug);
+
+    fn config(name: &'static str) -> Config {
+        Config {
+            name,
+            mempool: Some(invmgr::MempoolConfig::default()),
+            ..Config::default()
+        }
+    }
+
+    let mut rng = fastrand::Rng::new();
+    let nodes = network(&[config("alice"), config("bob")]).unwrap();
+    let (alice, _, _) = &nodes[0];
+    let (bob, _, _) = &nodes[1];
+
+    let tx = gen::transaction(&mut rng);
+    let script = tx.output[0].script_pubkey.clone();
+    let events = alice.subscribe();
+
+    alice.rescan(.., std::iter::once(script)).unwrap();
+    bob.wait_for_peers(1, ServiceFlags::NONE).unwrap();
+    bob.submit_transaction(tx.clone()).unwrap();
+
+    event::wait(
+        &events,
+        |e| match e {
+            client::Event::TxUnconfirmed { transaction, .. } if transaction == tx => Some(()),
+            _ => None,
+        },
+        time::Duration::from_secs(10),
+    )
+    .expect("Alice sees the unconfirmed transaction paying to her script");
+}
@@ -7,16 +7,20 @@ use nakamoto_chain::filter::BlockFilter;
 
 use nakamoto_common::block::tree::{self, ImportResult};
 use nakamoto_common::block::{BlockHash, BlockHeader, Height, Transaction};
-use nakamoto_common::network::Network;
+use nakamoto_common::network::{Network, NetworkParams};
 use nakamoto_common::nonempty::NonEmpty;
 
 use nakamoto_p2p::bitcoin::network::constants::ServiceFlags;
 use nakamoto_p2p::bitcoin::network::message::NetworkMessage;
 use nakamoto_p2p::bitcoin::network::Address;
+use nakamoto_p2p::bitcoin::Txid;
 use nakamoto_p2p::protocol;
+use nakamoto_p2p::protocol::fees::FeeBump;
+use nakamoto_p2p::protocol::invmgr::Broadcast;
 use nakamoto_p2p::protocol::Command;
 use nakamoto_p2p::protocol::Link;
 use nakamoto_p2p::protocol::Peer;
+use nakamoto_p2p::protocol::SyncStatus;
 
 use crate::client::{chan, Event};
 use crate::handle::{self, Handle};
@@ -96,20 +100,61 @@ pub struct TestHandle {
 }
 
 impl Handle for TestHandle {
-    fn network(&self) -> Network {
-        self.network
+    fn network_params(&self) -> NetworkParams {
+        NetworkParams::from(self.network)
     }
 
     fn get_tip(&self) -> Result<(Height, BlockHeader), handle::Error> {
         Ok(self.tip)
     }
 
+    fn sync_status(&self) -> Result<SyncStatus, handle::Error> {
+        unimplemented!()
+    }
+
+    fn get_header(&self, hash: &BlockHash) -> Result<Option<(Height, BlockHeader)>, handle::Error> {
+        let (height, header) = self.tip;
+
+        if header.block_hash() == *hash {
+            Ok(Some((height, header)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn get_headers(
+        &self,
+        _range: RangeInclusive<Height>,
+    ) -> Result<Vec<BlockHeader>, handle::Error> {
+        unimplemented!()
+    }
+
+    fn is_ancestor(&self, _ancestor: &BlockHash, _hash: &BlockHash) -> Result<bool, handle::Error> {
+        unimplemented!()
+    }
+
+    fn find_fork(&self, _hash: &BlockHash) -> Result<Option<(Height, BlockHeader)>, handle::Error> {
+        unimplemented!()
+    }
+
+    fn get_stale_tips(&self) -> Result<Vec<(Height, BlockHeader)>, handle::Error> {
+        unimplemented!()
+    }
+
     fn get_block(&self, hash: &BlockHash) -> Result<(), handle::Error> {
         self.command(Command::GetBlock(*hash))?;
 
         Ok(())
     }
 
+    fn fetch_block(&self, _hash: &BlockHash) -> Result<(Block, Height), handle::Error> {
+        unimplemented!()
+    }
+
+    fn fetch_block_at(&self, _height: Height) -> Result<(Block, Height), handle::Error> {
+        unimplemented!()
+    }
+
     fn get_filters(&self, range: RangeInclusive<Height>) -> Result<(), handle::Error> {
         let (transmit, receive) = chan::bounded(1);
         self.command(Command::GetFilters(range, transmit))?;
@@ -172,6 +217,33 @@ impl Handle for TestHandle {
         unimplemented!()
     }
 
+    fn submit_transaction_with(
+        &self,
+        _tx: Transaction,
+        _broadcast: Broadcast,
+    ) -> Result<NonEmpty<net::SocketAddr>, handle::Error> {
+        unimplemented!()
+    }
+
+    fn submit_package(
+        &self,
+        _txs: Vec<Transaction>,
+    ) -> Result<NonEmpty<net::SocketAddr>, handle::Error> {
+        unimplemented!()
+    }
+
+    fn pending_transactions(&self) -> Result<Vec<Transaction>, handle::Error> {
+        unimplemented!()
+    }
+
+    fn bump_fee(
+        &self,
+        _txid: Txid,
+        _bump: FeeBump,
+    ) -> Result<NonEmpty<net::SocketAddr>, handle::Error> {
+        unimplemented!()
+    }
+
     fn wait<F, T>(&self, _f: F) -> Result<T, handle::Error>
     where
         F: FnMut(protocol::Event) -> Option<T>,
@@ -26,3 +26,10 @@ pub const TESTNET: &[(u64, &str)] = &[
 
 /// Regtest checkpoints.
 pub const REGTEST: &[(u64, &str)] = &[];
+
+/// Signet checkpoints. These are for the default signet.
+#[rustfmt::skip]
+pub const SIGNET: &[(u64, &str)] = &[
+    (47200,  "000000187d4440e5bff91488b700a140441e089a8aaea707414982460edbfe54"),
+    (150000, "0000013d778ba3f914530f11f6b69869c9fab54acff85acd7b8201d111f19b7f"),
+];
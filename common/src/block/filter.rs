@@ -10,7 +10,7 @@ pub use bitcoin::util::bip158::BlockFilter;
 
 use super::Height;
 use crate::block::store::{self, Genesis};
-use crate::network::Network;
+use crate::block::Block;
 
 impl Genesis for FilterHeader {
     /// Filter header for the genesis block.
@@ -30,8 +30,8 @@ impl Genesis for FilterHeader {
     ///     ).unwrap()
     /// );
     /// ```
-    fn genesis(network: Network) -> Self {
-        let filter = BlockFilter::genesis(network);
+    fn from_genesis_block(genesis: &Block) -> Self {
+        let filter = BlockFilter::from_genesis_block(genesis);
         filter.filter_header(&FilterHeader::default())
     }
 }
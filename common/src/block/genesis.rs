@@ -26,3 +26,12 @@ pub const REGTEST: &[u8; 32] = &[
     0x28, 0xc3, 0x4f, 0x3a, 0x5e, 0x33, 0x2a, 0x1f,
     0xc7, 0xb2, 0xb7, 0x3c, 0xf1, 0x88, 0x91, 0x0f,
 ];
+
+#[rustfmt::skip]
+/// Bitcoin signet genesis hash.
+pub const SIGNET: &[u8; 32] = &[
+    0xf6, 0x1e, 0xee, 0x3b, 0x63, 0xa3, 0x80, 0xa4,
+    0x77, 0xa0, 0x63, 0xaf, 0x32, 0xb2, 0xbb, 0xc9,
+    0x7c, 0x9f, 0xf9, 0xf0, 0x1f, 0x2c, 0x42, 0x25,
+    0xe9, 0x73, 0x98, 0x81, 0x08, 0x00, 0x00, 0x00,
+];
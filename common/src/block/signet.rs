@@ -0,0 +1,260 @@
+//! Signet block solutions, as specified in BIP 325.
+//!
+//! On signet, blocks are not secured by proof-of-work alone: each block must carry a solution
+//! to the network's *block challenge*, a script that is usually a k-of-n multisig. The solution
+//! is committed to in the coinbase witness commitment output, after the [`SIGNET_HEADER`].
+//!
+//! Verifying a solution requires the full block, since the solution lives in the coinbase.
+#![warn(missing_docs)]
+use std::io;
+
+use thiserror::Error;
+
+use bitcoin::blockdata::opcodes::all::OP_RETURN;
+use bitcoin::blockdata::script::{Builder, Instruction, Script};
+use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+use bitcoin::consensus::encode::{self, Decodable};
+use bitcoin::util::hash::bitcoin_merkle_root;
+use bitcoin_hashes::sha256d;
+
+use super::Block;
+
+/// Block challenge of the default, public signet.
+pub const DEFAULT_CHALLENGE: &str = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+
+/// Header of the signet solution push in the coinbase witness commitment.
+pub const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+
+/// Prefix of the coinbase witness commitment output script.
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// A signet block solution error.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The block has no coinbase transaction.
+    #[error("block has no coinbase transaction")]
+    MissingCoinbase,
+    /// The coinbase has no witness commitment output.
+    #[error("coinbase has no witness commitment")]
+    MissingWitnessCommitment,
+    /// The solution could not be decoded.
+    #[error("malformed block solution: {0}")]
+    Malformed(#[from] encode::Error),
+    /// The solution has trailing data.
+    #[error("block solution has extraneous data")]
+    ExtraneousData,
+    /// The solution does not satisfy the block challenge.
+    #[error("block solution does not satisfy challenge: {0}")]
+    Script(#[from] bitcoin::blockdata::script::Error),
+}
+
+/// Verify a block's signet solution against a block challenge.
+///
+/// Note that the genesis block has no solution, and should not be passed to this function.
+///
+/// *Only available with the `signet` feature.*
+#[cfg(feature = "signet")]
+pub fn verify(block: &Block, challenge: &Script) -> Result<(), Error> {
+    let (_, to_sign) = transactions(block, challenge)?;
+
+    challenge.verify(0, 0, &encode::serialize(&to_sign))?;
+
+    Ok(())
+}
+
+/// Construct the virtual `to_spend` and `to_sign` transactions of a block. The block solution
+/// is valid if `to_sign` validly spends the first output of `to_spend`, which is locked with
+/// the block challenge.
+pub fn transactions(
+    block: &Block,
+    challenge: &Script,
+) -> Result<(Transaction, Transaction), Error> {
+    let mut coinbase = block.txdata.first().ok_or(Error::MissingCoinbase)?.clone();
+    let commitment = coinbase
+        .output
+        .iter_mut()
+        .rev()
+        .find(|o| {
+            let bytes = o.script_pubkey.as_bytes();
+            bytes.len() >= 38 && bytes.starts_with(&WITNESS_COMMITMENT_HEADER)
+        })
+        .ok_or(Error::MissingWitnessCommitment)?;
+
+    let mut script_sig = Script::new();
+    let mut witness = Vec::new();
+
+    // If there is no solution, the challenge must be satisfiable without one, eg. `OP_TRUE`.
+    if let Some((script, solution)) = clear_commitment_section(&commitment.script_pubkey) {
+        let mut cursor = io::Cursor::new(&solution);
+
+        script_sig = Script::consensus_decode(&mut cursor)?;
+        witness = Vec::<Vec<u8>>::consensus_decode(&mut cursor)?;
+
+        if cursor.position() as usize != solution.len() {
+            return Err(Error::ExtraneousData);
+        }
+        commitment.script_pubkey = script;
+    }
+
+    // The merkle root of the block, with the solution removed from the coinbase.
+    let merkle_root: sha256d::Hash = bitcoin_merkle_root(
+        std::iter::once(coinbase.txid().as_hash())
+            .chain(block.txdata.iter().skip(1).map(|tx| tx.txid().as_hash()))
+            .collect::<Vec<_>>()
+            .into_iter(),
+    );
+
+    let mut data = Vec::with_capacity(72);
+    data.extend(encode::serialize(&block.header.version));
+    data.extend(encode::serialize(&block.header.prev_blockhash));
+    data.extend(encode::serialize(&merkle_root));
+    data.extend(encode::serialize(&block.header.time));
+
+    let to_spend = Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: Builder::new().push_int(0).push_slice(&data).into_script(),
+            sequence: 0,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: challenge.clone(),
+        }],
+    };
+    let to_sign = Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig,
+            sequence: 0,
+            witness,
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    };
+
+    Ok((to_spend, to_sign))
+}
+
+/// Find the signet solution in a witness commitment script. Returns the script with the
+/// solution removed, and the solution, if found.
+fn clear_commitment_section(script: &Script) -> Option<(Script, Vec<u8>)> {
+    let mut builder = Builder::new();
+    let mut solution = None;
+
+    for instruction in script.instructions() {
+        match instruction.ok()? {
+            Instruction::PushBytes(bytes) => {
+                if solution.is_none()
+                    && bytes.len() > SIGNET_HEADER.len()
+                    && bytes.starts_with(&SIGNET_HEADER)
+                {
+                    solution = Some(bytes[SIGNET_HEADER.len()..].to_vec());
+                    builder = builder.push_slice(&SIGNET_HEADER);
+                } else {
+                    builder = builder.push_slice(bytes);
+                }
+            }
+            Instruction::Op(op) => {
+                builder = builder.push_opcode(op);
+            }
+        }
+    }
+    solution.map(|s| (builder.into_script(), s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin::blockdata::block::BlockHeader;
+    use bitcoin::blockdata::opcodes::all::OP_PUSHNUM_1;
+    use bitcoin::hash_types::TxMerkleNode;
+
+    fn block(commitment: Script) -> Block {
+        let coinbase = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(1).into_script(),
+                sequence: 0xffffffff,
+                witness: vec![vec![0; 32]],
+            }],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: commitment,
+            }],
+        };
+        let merkle_root = TxMerkleNode::from_hash(coinbase.txid().as_hash());
+
+        Block {
+            header: BlockHeader {
+                version: 1,
+                prev_blockhash: Default::default(),
+                merkle_root,
+                time: 1598918400,
+                bits: 0x1e0377ae,
+                nonce: 0,
+            },
+            txdata: vec![coinbase],
+        }
+    }
+
+    fn commitment(solution: Option<&[u8]>) -> Script {
+        let mut builder = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(&[[0xaa, 0x21, 0xa9, 0xed].as_ref(), &[0; 32]].concat());
+
+        if let Some(solution) = solution {
+            builder = builder.push_slice(&[SIGNET_HEADER.as_ref(), solution].concat());
+        }
+        builder.into_script()
+    }
+
+    #[test]
+    #[cfg(feature = "signet")]
+    fn test_trivial_challenge() {
+        let challenge = Builder::new().push_opcode(OP_PUSHNUM_1).into_script();
+
+        // No solution is needed for an `OP_TRUE` challenge.
+        verify(&block(commitment(None)), &challenge).unwrap();
+        // An empty solution is also valid.
+        verify(&block(commitment(Some(&[0x0, 0x0]))), &challenge).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "signet")]
+    fn test_invalid_solution() {
+        let challenge = crate::network::Signet::default().challenge;
+
+        assert!(matches!(
+            verify(&block(commitment(None)), &challenge),
+            Err(Error::Script(_))
+        ));
+        assert!(matches!(
+            verify(&block(commitment(Some(&[0x0, 0x0, 0x0]))), &challenge),
+            Err(Error::ExtraneousData)
+        ));
+        assert!(matches!(
+            verify(&block(Script::new()), &challenge),
+            Err(Error::MissingWitnessCommitment)
+        ));
+    }
+
+    #[test]
+    fn test_solution_is_removed_from_merkle_root() {
+        let challenge = Builder::new().push_opcode(OP_PUSHNUM_1).into_script();
+        // A header push without any data is not a solution, and is left in place.
+        let (a, _) = transactions(&block(commitment(Some(&[]))), &challenge).unwrap();
+        let (b, _) = transactions(&block(commitment(Some(&[0x0, 0x0]))), &challenge).unwrap();
+
+        assert_eq!(a.txid(), b.txid());
+    }
+}
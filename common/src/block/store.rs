@@ -2,7 +2,7 @@
 #![allow(clippy::len_without_is_empty)]
 use crate::block::Height;
 
-use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::block::{Block, BlockHeader};
 use bitcoin::consensus::encode;
 use bitcoin::hash_types::FilterHash;
 use bitcoin::util::bip158::BlockFilter;
@@ -26,25 +26,29 @@ pub enum Error {
 }
 
 /// Represents an object (such as a header), that has a genesis.
-pub trait Genesis {
-    /// Create a genesis header.
-    fn genesis(network: Network) -> Self;
+pub trait Genesis: Sized {
+    /// Create a genesis header from the genesis block.
+    fn from_genesis_block(genesis: &Block) -> Self;
+
+    /// Create a genesis header for one of the built-in networks.
+    fn genesis(network: Network) -> Self {
+        Self::from_genesis_block(&network.genesis_block())
+    }
 }
 
 /// Genesis implementation for `bitcoin`'s header.
 impl Genesis for BlockHeader {
-    fn genesis(network: Network) -> Self {
-        network.genesis()
+    fn from_genesis_block(genesis: &Block) -> Self {
+        genesis.header
     }
 }
 
 /// Genesis implementation for `bitcoin`'s `FilterHash`.
 impl Genesis for FilterHash {
-    fn genesis(network: Network) -> Self {
+    fn from_genesis_block(genesis: &Block) -> Self {
         use bitcoin::hashes::Hash;
 
-        let genesis = network.genesis_block();
-        let filter = BlockFilter::new_script_filter(&genesis, |_| {
+        let filter = BlockFilter::new_script_filter(genesis, |_| {
             panic!("{}: genesis block should have no inputs", source!())
         })
         .unwrap();
@@ -55,10 +59,8 @@ impl Genesis for FilterHash {
 
 /// Genesis implementation for `bitcoin`'s `BlockFilter`.
 impl Genesis for BlockFilter {
-    fn genesis(network: Network) -> Self {
-        let genesis = network.genesis_block();
-
-        BlockFilter::new_script_filter(&genesis, |_| {
+    fn from_genesis_block(genesis: &Block) -> Self {
+        BlockFilter::new_script_filter(genesis, |_| {
             panic!("{}: genesis block should have no inputs", source!())
         })
         .unwrap()
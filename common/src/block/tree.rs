@@ -1,15 +1,16 @@
 //! Types and functions relating to block trees.
 #![warn(missing_docs)]
 use std::collections::BTreeMap;
+use std::net;
 
-use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::block::{Block, BlockHeader};
 use bitcoin::consensus::params::Params;
 use bitcoin::hash_types::BlockHash;
 
 use thiserror::Error;
 
 use crate::block::store;
-use crate::block::time::Clock;
+use crate::block::time::{Clock, LocalTime};
 use crate::block::{Bits, BlockTime, Height, Target, Work};
 use crate::nonempty::NonEmpty;
 
@@ -36,6 +37,14 @@ pub enum Error {
     #[error("block timestamp {0} is invalid")]
     InvalidBlockTime(BlockTime, std::cmp::Ordering),
 
+    /// The block's transactions don't match its header.
+    #[error("block {0} doesn't match its header")]
+    InvalidBlockBody(BlockHash),
+
+    /// The block's signet solution is invalid.
+    #[error("invalid block solution for block {0}")]
+    InvalidBlockSolution(BlockHash),
+
     /// The block is already known.
     #[error("duplicate block {0}")]
     DuplicateBlock(BlockHash),
@@ -44,6 +53,10 @@ pub enum Error {
     #[error("block missing: {0}")]
     BlockMissing(BlockHash),
 
+    /// The block is orphan, and was rejected because the orphan pool is full.
+    #[error("orphan block {0} rejected: orphan pool is full")]
+    OrphanPoolFull(BlockHash),
+
     /// A block import was aborted. FIXME: Move this error out of here.
     #[error("block import aborted at height {2}: {0} ({1} block(s) imported)")]
     BlockImportAborted(Box<Self>, usize, Height),
@@ -109,6 +122,22 @@ pub trait BlockTree {
         chain: I,
         context: &C,
     ) -> Result<ImportResult, Error>;
+    /// Import a chain of block headers received from a peer. Blocks that don't connect to
+    /// the tree are kept as orphans, and attributed to the peer, so that it can be held
+    /// accountable if they never connect. See [`BlockTree::expire_orphans`].
+    fn import_blocks_from<I: Iterator<Item = BlockHeader>, C: Clock>(
+        &mut self,
+        chain: I,
+        context: &C,
+        _peer: net::SocketAddr,
+    ) -> Result<ImportResult, Error> {
+        self.import_blocks(chain, context)
+    }
+    /// Expire orphan blocks that haven't connected to the tree for too long. Returns the
+    /// peers that supplied the expired orphans, with the number of orphans expired per peer.
+    fn expire_orphans(&mut self, _now: LocalTime) -> Vec<(net::SocketAddr, usize)> {
+        Vec::new()
+    }
     /// Attempts to extend the active chain. Returns `Ok` with `ImportResult::TipUnchanged` if
     /// the block didn't connect, and `Err` if the block was invalid.
     fn extend_tip<C: Clock>(
@@ -116,10 +145,25 @@ pub trait BlockTree {
         header: BlockHeader,
         context: &C,
     ) -> Result<ImportResult, Error>;
+    /// Validate a full block, given its body. This checks the parts of a block that can't be
+    /// checked from its header alone, eg. the merkle root, or the block solution on signets.
+    fn validate_block(&self, _block: &Block) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Mark a block as invalid, eg. because [`BlockTree::validate_block`] failed. If the block
+    /// is part of the active chain, the chain is rolled back to the block's parent. Returns the
+    /// rolled-back blocks.
+    fn invalidate_block(
+        &mut self,
+        _hash: &BlockHash,
+        _now: LocalTime,
+    ) -> Result<Vec<(Height, BlockHash)>, Error> {
+        Ok(vec![])
+    }
     /// Get a block by hash.
-    fn get_block(&self, hash: &BlockHash) -> Option<(Height, &BlockHeader)>;
+    fn get_block(&self, hash: &BlockHash) -> Option<(Height, BlockHeader)>;
     /// Get a block by height.
-    fn get_block_by_height(&self, height: Height) -> Option<&BlockHeader>;
+    fn get_block_by_height(&self, height: Height) -> Option<BlockHeader>;
     /// Iterate over the longest chain, starting from genesis.
     fn chain<'a>(&'a self) -> Box<dyn Iterator<Item = BlockHeader> + 'a> {
         Box::new(self.iter().map(|(_, h)| h))
@@ -143,7 +187,7 @@ pub trait BlockTree {
     /// Get the tip of the longest chain.
     fn tip(&self) -> (BlockHash, BlockHeader);
     /// Get the last block of the longest chain.
-    fn best_block(&self) -> (Height, &BlockHeader) {
+    fn best_block(&self) -> (Height, BlockHeader) {
         let height = self.height();
         (
             height,
@@ -151,12 +195,17 @@ pub trait BlockTree {
                 .expect("the best block is always present"),
         )
     }
+    /// Get the total proof-of-work of the longest chain, including genesis.
+    fn chain_work(&self) -> Work {
+        self.iter()
+            .fold(Work::default(), |work, (_, header)| work + header.work())
+    }
     /// Get the height of the last checkpoint block.
     fn last_checkpoint(&self) -> Height;
     /// Known checkpoints.
     fn checkpoints(&self) -> BTreeMap<Height, BlockHash>;
     /// Return the genesis block header.
-    fn genesis(&self) -> &BlockHeader {
+    fn genesis(&self) -> BlockHeader {
         self.get_block_by_height(0)
             .expect("the genesis block is always present")
     }
@@ -164,6 +213,25 @@ pub trait BlockTree {
     fn is_known(&self, hash: &BlockHash) -> bool;
     /// Check whether a block hash is part of the active chain.
     fn contains(&self, hash: &BlockHash) -> bool;
+    /// Find the point at which the given block forks off the active chain, ie. its most
+    /// recent ancestor on the active chain. For blocks of the active chain, this is the
+    /// block itself. Returns `None` if the block is unknown or doesn't connect to the chain.
+    fn find_fork(&self, hash: &BlockHash) -> Option<(Height, BlockHeader)> {
+        self.get_block(hash)
+    }
+    /// Check whether a block is an ancestor of another block. A block is considered an
+    /// ancestor of itself.
+    fn is_ancestor(&self, ancestor: &BlockHash, hash: &BlockHash) -> bool {
+        match (self.get_block(ancestor), self.get_block(hash)) {
+            (Some((a, _)), Some((b, _))) => a <= b,
+            _ => false,
+        }
+    }
+    /// Get the tips of the known stale branches, ie. branches that fork off the active chain
+    /// but aren't part of it, with their heights.
+    fn stale_tips(&self) -> Vec<(Height, BlockHeader)> {
+        Vec::new()
+    }
     /// Return the headers corresponding to the given locators, up to a maximum.
     fn locate_headers(
         &self,
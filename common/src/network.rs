@@ -1,14 +1,17 @@
 //! Bitcoin peer network. Eg. *Mainnet*.
 
 use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::script::Script;
+use bitcoin::consensus::encode;
 use bitcoin::consensus::params::Params;
 use bitcoin::hash_types::BlockHash;
 use bitcoin::network::constants::ServiceFlags;
+use bitcoin::util::uint::Uint256;
 use bitcoin_hashes::hex::FromHex;
 
-use bitcoin_hashes::sha256d;
+use bitcoin_hashes::{sha256d, Hash};
 
-use crate::block::Height;
+use crate::block::{signet, Height, Work};
 
 /// Peer services supported by nakamoto.
 #[derive(Debug, Copy, Clone)]
@@ -43,6 +46,8 @@ pub enum Network {
     Testnet,
     /// Bitcoin regression test net.
     Regtest,
+    /// Bitcoin signet. Custom signets can be configured with [`Signet`].
+    Signet,
 }
 
 impl Default for Network {
@@ -57,6 +62,7 @@ impl From<Network> for bitcoin::Network {
             Network::Mainnet => Self::Bitcoin,
             Network::Testnet => Self::Testnet,
             Network::Regtest => Self::Regtest,
+            Network::Signet => Self::Signet,
         }
     }
 }
@@ -68,6 +74,7 @@ impl Network {
             Network::Mainnet => 8333,
             Network::Testnet => 18333,
             Network::Regtest => 18334,
+            Network::Signet => 38333,
         }
     }
 
@@ -79,6 +86,7 @@ impl Network {
             Network::Mainnet => &checkpoints::MAINNET,
             Network::Testnet => &checkpoints::TESTNET,
             Network::Regtest => &checkpoints::REGTEST,
+            Network::Signet => &checkpoints::SIGNET,
         }
         .iter()
         .cloned()
@@ -96,6 +104,7 @@ impl Network {
             Network::Mainnet => "mainnet",
             Network::Testnet => "testnet",
             Network::Regtest => "regtest",
+            Network::Signet => "signet",
         }
     }
 
@@ -121,6 +130,7 @@ impl Network {
                 "testnet-seed.bluematt.me",
             ],
             Network::Regtest => &[], // No seeds
+            Network::Signet => &["seed.signet.bitcoin.sprovoost.nl"],
         }
     }
 }
@@ -135,6 +145,11 @@ impl Network {
     /// let genesis = network.genesis();
     ///
     /// assert_eq!(network.genesis_hash(), genesis.block_hash());
+    ///
+    /// let network = Network::Signet;
+    /// let genesis = network.genesis();
+    ///
+    /// assert_eq!(network.genesis_hash(), genesis.block_hash());
     /// ```
     pub fn genesis(&self) -> BlockHeader {
         self.genesis_block().header
@@ -150,12 +165,12 @@ impl Network {
     /// Get the hash of the genesis block of this network.
     pub fn genesis_hash(&self) -> BlockHash {
         use crate::block::genesis;
-        use bitcoin_hashes::Hash;
 
         let hash = match self {
             Self::Mainnet => genesis::MAINNET,
             Self::Testnet => genesis::TESTNET,
             Self::Regtest => genesis::REGTEST,
+            Self::Signet => genesis::SIGNET,
         };
         BlockHash::from(
             sha256d::Hash::from_slice(hash)
@@ -172,4 +187,170 @@ impl Network {
     pub fn magic(&self) -> u32 {
         bitcoin::Network::from(*self).magic()
     }
+
+    /// Get the minimum total work of the active chain, below which we don't consider
+    /// ourselves synced. Taken from Bitcoin Core 0.21's `nMinimumChainWork`.
+    pub fn minimum_chain_work(&self) -> Work {
+        match self {
+            Network::Mainnet => Uint256([0xd716a517fe2c5008, 0x1533efd8, 0, 0]),
+            Network::Testnet => Uint256([0x6ec4ac88cf2272c6, 0x1db, 0, 0]),
+            // Signets may be custom, so we can't assume a minimum.
+            Network::Regtest | Network::Signet => Uint256([0; 4]),
+        }
+    }
+}
+
+/// Network parameters. Bundles everything that depends on the network we're connecting to.
+///
+/// The built-in networks are available via [`Network`], and custom networks can be defined
+/// by modifying one of them, eg. to run against a private chain with its own genesis:
+///
+/// ```
+/// use nakamoto_common::network::{Network, NetworkParams};
+///
+/// let network = NetworkParams {
+///     name: String::from("private"),
+///     magic: 0xcafebabe,
+///     ..NetworkParams::from(Network::Regtest)
+/// };
+/// assert_eq!(network.genesis_hash(), Network::Regtest.genesis_hash());
+/// ```
+#[derive(Debug, Clone)]
+pub struct NetworkParams {
+    /// The built-in network these parameters are based on. Determines eg. the
+    /// proof-of-work limit and address encoding.
+    pub network: Network,
+    /// Network name. Used to name the data directory.
+    pub name: String,
+    /// Genesis block.
+    pub genesis: Block,
+    /// Network magic number.
+    pub magic: u32,
+    /// Default port.
+    pub port: u16,
+    /// DNS seeds, as `host` or `host:port`.
+    pub seeds: Vec<String>,
+    /// Block checkpoints.
+    pub checkpoints: Vec<(Height, BlockHash)>,
+    /// Consensus parameters.
+    pub params: Params,
+    /// Signet block challenge. Only set on signets.
+    pub challenge: Option<Script>,
+    /// Minimum total work of the active chain. Until our chain has at least this much
+    /// work, we don't consider ourselves synced, and header chains that don't reach it
+    /// are not trusted.
+    pub minimum_chain_work: Work,
+}
+
+impl Default for NetworkParams {
+    fn default() -> Self {
+        Self::from(Network::default())
+    }
+}
+
+impl From<Network> for NetworkParams {
+    fn from(network: Network) -> Self {
+        Self {
+            network,
+            name: network.as_str().to_owned(),
+            genesis: network.genesis_block(),
+            magic: network.magic(),
+            port: network.port(),
+            seeds: network.seeds().iter().map(|s| s.to_string()).collect(),
+            checkpoints: network.checkpoints().collect(),
+            params: network.params(),
+            challenge: match network {
+                Network::Signet => Some(Signet::default().challenge),
+                _ => None,
+            },
+            minimum_chain_work: network.minimum_chain_work(),
+        }
+    }
+}
+
+impl NetworkParams {
+    /// Get the genesis block header.
+    pub fn genesis(&self) -> BlockHeader {
+        self.genesis.header
+    }
+
+    /// Get the hash of the genesis block.
+    pub fn genesis_hash(&self) -> BlockHash {
+        self.genesis.block_hash()
+    }
+
+    /// Get the seeds as `host:port` strings, using the default port for seeds without one.
+    pub fn seed_addrs(&self) -> impl Iterator<Item = String> + '_ {
+        self.seeds.iter().map(move |s| {
+            if s.contains(':') {
+                s.clone()
+            } else {
+                format!("{}:{}", s, self.port)
+            }
+        })
+    }
+}
+
+/// Signet parameters. Signets share the genesis block and port of [`Network::Signet`], but
+/// each signet has its own block challenge, from which the network magic is derived.
+///
+/// ```
+/// use nakamoto_common::network::{Network, Signet};
+///
+/// assert_eq!(Signet::default().magic(), Network::Signet.magic());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signet {
+    /// Block challenge. Every block must include a solution to this script.
+    pub challenge: Script,
+    /// Seed nodes, as `host` or `host:port`. Used to bootstrap the client's address book.
+    pub seeds: Vec<String>,
+}
+
+impl Default for Signet {
+    /// The default, public signet.
+    fn default() -> Self {
+        Self {
+            challenge: Script::from(Vec::<u8>::from_hex(signet::DEFAULT_CHALLENGE).unwrap()),
+            seeds: Network::Signet
+                .seeds()
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl Signet {
+    /// Create a custom signet, given a block challenge and seed nodes.
+    pub fn custom(challenge: Script, seeds: Vec<String>) -> Self {
+        Self { challenge, seeds }
+    }
+
+    /// Get the network parameters of this signet.
+    pub fn network_params(&self) -> NetworkParams {
+        let mut network = NetworkParams::from(Network::Signet);
+
+        network.magic = self.magic();
+        network.seeds = self.seeds.clone();
+        network.challenge = Some(self.challenge.clone());
+
+        // Custom signets each get their own data directory, keyed by network magic, and
+        // don't share the checkpoints of the default signet.
+        if *self != Self::default() {
+            network.name = format!("signet-{:08x}", network.magic);
+            network.checkpoints.clear();
+        }
+        network
+    }
+
+    /// Get the network magic number of this signet. This is the first four bytes of the
+    /// double SHA-256 of the serialized challenge.
+    pub fn magic(&self) -> u32 {
+        let hash = sha256d::Hash::hash(&encode::serialize(&self.challenge));
+        let mut magic = [0; 4];
+        magic.copy_from_slice(&hash[..4]);
+
+        u32::from_le_bytes(magic)
+    }
 }
@@ -0,0 +1,180 @@
+//! Header store maintenance: verification, repair and compaction.
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use bitcoin::consensus::encode::{Decodable, Encodable};
+
+use nakamoto_chain::block::store::{self, Genesis as _, Store};
+use nakamoto_chain::filter::cache::StoredHeader;
+use nakamoto_chain::integrity::{self, Report};
+use nakamoto_common::block::{BlockHeader, Height};
+
+use crate::headers::{Error, Stores};
+use crate::NetworkParams;
+
+/// Verification reports of the block header and filter header stores.
+#[derive(Debug, Clone)]
+pub struct Reports {
+    /// Block header store report.
+    pub headers: Report,
+    /// Filter header store report.
+    pub filters: Report,
+}
+
+impl Reports {
+    /// Check whether both stores are free of corruption.
+    pub fn is_ok(&self) -> bool {
+        self.headers.is_ok() && self.filters.is_ok()
+    }
+}
+
+/// Verify the block header and filter header stores. The stores are not modified.
+pub fn verify(stores: &Stores, network: &NetworkParams) -> Result<Reports, Error> {
+    let headers = open(&stores.headers, network.genesis())?;
+    let filters = open(
+        &stores.filters,
+        StoredHeader::from_genesis_block(&network.genesis),
+    )?;
+
+    Ok(Reports {
+        headers: integrity::verify_headers(&headers, network)?,
+        filters: integrity::verify_filter_headers(&filters, network)?,
+    })
+}
+
+/// Repair the stores by truncating them to their last valid header. Filter headers above the
+/// last valid block header are also removed. Truncated headers are downloaded again the next
+/// time the client syncs. Returns the reports from before the repair.
+pub fn repair(stores: &Stores, network: &NetworkParams) -> Result<Reports, Error> {
+    let reports = verify(stores, network)?;
+
+    let mut headers = open(&stores.headers, network.genesis())?;
+    integrity::repair(&mut headers, &reports.headers)?;
+
+    let mut filters = open(
+        &stores.filters,
+        StoredHeader::from_genesis_block(&network.genesis),
+    )?;
+    integrity::repair(&mut filters, &reports.filters)?;
+
+    // Filter headers are only meaningful for blocks we have.
+    if filters.height()? > headers.height()? {
+        filters.rollback(headers.height()?)?;
+        filters.sync()?;
+    }
+    Ok(reports)
+}
+
+/// Compact the stores. Each store is rewritten to a new file containing only its valid
+/// headers, which then atomically replaces the original. Returns the number of bytes
+/// reclaimed.
+pub fn compact(stores: &Stores, network: &NetworkParams) -> Result<u64, Error> {
+    let reports = verify(stores, network)?;
+    let height = reports.headers.valid;
+
+    let mut reclaimed = rewrite::<BlockHeader>(&stores.headers, network.genesis(), height)?;
+    reclaimed += rewrite::<StoredHeader>(
+        &stores.filters,
+        StoredHeader::from_genesis_block(&network.genesis),
+        reports.filters.valid.min(height),
+    )?;
+
+    Ok(reclaimed)
+}
+
+/// Open an existing store, without modifying it.
+fn open<H>(path: &Path, genesis: H) -> Result<store::File<H>, Error> {
+    if !path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("store {:?} not found", path),
+        )
+        .into());
+    }
+    store::File::open(path, genesis).map_err(Error::from)
+}
+
+/// Rewrite a store up to the given height. Returns the number of bytes reclaimed.
+fn rewrite<H>(path: &Path, genesis: H, height: Height) -> Result<u64, Error>
+where
+    H: 'static + Copy + Encodable + Decodable,
+{
+    let store = open(path, genesis)?;
+    let size = store.size()?;
+    let tmp = path.with_extension("tmp");
+
+    {
+        let mut file = io::BufWriter::new(fs::File::create(&tmp)?);
+        for result in store.iter().skip(1).take(height as usize) {
+            let (_, header) = result?;
+            header.consensus_encode(&mut file)?;
+        }
+        file.flush()?;
+        file.get_ref().sync_all()?;
+    }
+    let compacted = fs::metadata(&tmp)?.len();
+    fs::rename(&tmp, path)?;
+
+    Ok(size - compacted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nakamoto_common::block::filter::FilterHeader;
+    use nakamoto_common::network::Network;
+    use nakamoto_test::block::gen;
+
+    #[test]
+    fn test_verify_repair_compact() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let network = NetworkParams::from(Network::Regtest);
+        let chain = gen::blockchain(network.genesis.clone(), 8, &mut rng);
+        let cfheaders = gen::cfheaders_from_blocks(
+            FilterHeader::from_genesis_block(&network.genesis),
+            chain.tail.iter(),
+        );
+
+        let tmp = tempfile::tempdir().unwrap();
+        let stores = Stores::new(tmp.path(), &network);
+
+        assert!(verify(&stores, &network).is_err(), "stores must exist");
+        {
+            let mut headers = stores.open_headers(&network).unwrap();
+            let mut filters = stores.open_filters(&network).unwrap();
+
+            // Only store the first six block headers, followed by garbage.
+            headers
+                .put(chain.tail.iter().take(6).map(|b| b.header))
+                .unwrap();
+            headers.put(std::iter::once(chain.tail[0].header)).unwrap();
+            filters
+                .put(
+                    cfheaders
+                        .into_iter()
+                        .map(|(hash, header)| StoredHeader { hash, header }),
+                )
+                .unwrap();
+        }
+
+        let reports = verify(&stores, &network).unwrap();
+        assert!(!reports.is_ok());
+        assert_eq!(reports.headers.valid, 6);
+        assert!(reports.filters.is_ok());
+
+        // Compacting drops the invalid header, and the filter headers above it.
+        assert_eq!(compact(&stores, &network).unwrap(), 80 + 64 * 2);
+
+        let reports = verify(&stores, &network).unwrap();
+        assert!(reports.is_ok());
+        assert_eq!(reports.headers.height, 6);
+        assert_eq!(reports.filters.height, 6);
+
+        // Repairing a valid store is a no-op.
+        let reports = repair(&stores, &network).unwrap();
+        assert!(reports.is_ok());
+        assert_eq!(verify(&stores, &network).unwrap().headers.height, 6);
+    }
+}
@@ -0,0 +1,330 @@
+//! Export and import of block header and filter header chains.
+//!
+//! Headers are written in the same format as the header stores: a flat sequence of
+//! consensus-encoded block headers, and a flat sequence of `(FilterHash, FilterHeader)`
+//! pairs for filter headers. A filter header dump always covers the same height range
+//! as the block header dump it was exported with.
+use std::fs;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use thiserror::Error;
+
+use bitcoin::consensus::encode::{self, Decodable, Encodable};
+
+use nakamoto_chain::block::cache::BlockCache;
+use nakamoto_chain::block::store;
+use nakamoto_chain::filter::cache::{FilterCache, StoredHeader};
+use nakamoto_common::block::filter::{self, Filters};
+use nakamoto_common::block::store::{Genesis as _, Store};
+use nakamoto_common::block::time::AdjustedTime;
+use nakamoto_common::block::tree::{self, BlockTree};
+use nakamoto_common::block::{BlockHeader, Height};
+
+use crate::NetworkParams;
+
+/// An error exporting or importing headers.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// An I/O error.
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    /// A storage error.
+    #[error("storage error: {0}")]
+    Store(#[from] store::Error),
+    /// A block header chain error.
+    #[error(transparent)]
+    Chain(#[from] tree::Error),
+    /// An error importing filter headers.
+    #[error(transparent)]
+    Filter(#[from] filter::Error),
+    /// An error coming from the filter store.
+    #[error(transparent)]
+    FilterStore(#[from] nakamoto_chain::filter::store::Error),
+    /// An error decoding a header dump.
+    #[error("error decoding headers: {0}")]
+    Decoding(#[from] encode::Error),
+    /// The requested range is not available in the store.
+    #[error("range {0:?} is not available (store height = {1})")]
+    InvalidRange(RangeInclusive<Height>, Height),
+    /// The imported headers don't connect to the active chain.
+    #[error("headers don't connect to the active chain")]
+    Disconnected,
+    /// The imported filter headers don't match the block headers, or conflict
+    /// with the filter headers already in the store.
+    #[error("filter headers don't match at height {0}")]
+    FilterMismatch(Height),
+}
+
+/// Header stores of a network, under the client root.
+#[derive(Debug, Clone)]
+pub struct Stores {
+    /// Block header store path.
+    pub headers: PathBuf,
+    /// Filter header store path.
+    pub filters: PathBuf,
+}
+
+impl Stores {
+    /// Get the store paths for the given root and network. These are the same stores the
+    /// client uses.
+    pub fn new(root: &Path, network: &NetworkParams) -> Self {
+        let dir = root.join(".nakamoto").join(&network.name);
+
+        Self {
+            headers: dir.join("headers.db"),
+            filters: dir.join("filters.db"),
+        }
+    }
+
+    /// Open the block header store, creating it if necessary.
+    pub(crate) fn open_headers(
+        &self,
+        network: &NetworkParams,
+    ) -> Result<store::File<BlockHeader>, Error> {
+        open(&self.headers, network.genesis())
+    }
+
+    /// Open the filter header store, creating it if necessary.
+    pub(crate) fn open_filters(
+        &self,
+        network: &NetworkParams,
+    ) -> Result<store::File<StoredHeader>, Error> {
+        open(
+            &self.filters,
+            StoredHeader::from_genesis_block(&network.genesis),
+        )
+    }
+}
+
+/// Get the height of the block header store.
+pub fn height(stores: &Stores, network: &NetworkParams) -> Result<Height, Error> {
+    stores.open_headers(network)?.height().map_err(Error::from)
+}
+
+/// Export a range of block headers, and optionally the matching filter headers.
+/// Returns the number of headers exported.
+pub fn export<W: Write>(
+    stores: &Stores,
+    network: &NetworkParams,
+    range: RangeInclusive<Height>,
+    headers: W,
+    filters: Option<W>,
+) -> Result<usize, Error> {
+    let store = stores.open_headers(network)?;
+    let count = write(&store, range.clone(), headers)?;
+
+    if let Some(writer) = filters {
+        let store = stores.open_filters(network)?;
+        write(&store, range, writer)?;
+    }
+    Ok(count)
+}
+
+/// Import block headers, and optionally the matching filter headers. Block headers are fully
+/// validated before being stored, and must connect to the active chain. Filter headers must
+/// cover the same range as the block headers. Returns the new block header height.
+pub fn import<R: Read>(
+    stores: &Stores,
+    network: &NetworkParams,
+    headers: R,
+    filters: Option<R>,
+) -> Result<Height, Error> {
+    let headers = read::<BlockHeader, _>(headers)?;
+    let store = stores.open_headers(network)?;
+    let clock = AdjustedTime::<std::net::SocketAddr>::new(SystemTime::now().into());
+    let mut cache = BlockCache::from(store, network.params.clone(), &network.checkpoints)?;
+
+    if let Some(challenge) = &network.challenge {
+        cache = cache.with_signet_challenge(challenge.clone());
+    }
+    cache.import_blocks(headers.iter().cloned(), &clock)?;
+
+    // Every imported header should now be part of the active chain.
+    let start = match headers.first() {
+        Some(first) => match cache.get_block(&first.block_hash()) {
+            Some((height, _)) => height,
+            None => return Err(Error::Disconnected),
+        },
+        None => return Ok(cache.height()),
+    };
+    if headers.iter().any(|h| !cache.contains(&h.block_hash())) {
+        return Err(Error::Disconnected);
+    }
+
+    if let Some(reader) = filters {
+        let filter_headers = read::<StoredHeader, _>(reader)?;
+        let mut filters = FilterCache::from(stores.open_filters(network)?)?;
+
+        if filter_headers.len() != headers.len() {
+            return Err(Error::FilterMismatch(
+                start + filter_headers.len() as Height,
+            ));
+        }
+        let tip = filters.height();
+
+        if start > tip + 1 {
+            return Err(Error::FilterMismatch(tip + 1));
+        }
+        // Filter headers we already have must match, the rest are appended.
+        for (i, stored) in filter_headers.iter().enumerate() {
+            let height = start + i as Height;
+
+            if height <= tip && filters.get_header(height) != Some((stored.hash, stored.header)) {
+                return Err(Error::FilterMismatch(height));
+            }
+        }
+        let skip = (tip + 1 - start) as usize;
+
+        filters.import_headers(
+            filter_headers
+                .into_iter()
+                .skip(skip)
+                .map(|s| (s.hash, s.header))
+                .collect(),
+        )?;
+        filters.verify(network)?;
+    }
+    Ok(cache.height())
+}
+
+/// Open a header store, healing it if necessary.
+fn open<H>(path: &Path, genesis: H) -> Result<store::File<H>, Error>
+where
+    H: 'static + Copy + Encodable + Decodable,
+{
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let store = store::File::open(path, genesis)?;
+
+    if store.check().is_err() {
+        log::warn!("Corruption detected in store {:?}, healing..", path);
+        store.heal()?; // Rollback store to the last valid header.
+    }
+    Ok(store)
+}
+
+/// Write a range of headers from a store.
+fn write<S: Store, W: Write>(
+    store: &S,
+    range: RangeInclusive<Height>,
+    mut writer: W,
+) -> Result<usize, Error>
+where
+    S::Header: Encodable,
+{
+    let height = store.height()?;
+
+    // The genesis header isn't part of the store file, so it can't be exported.
+    if *range.start() == 0 || range.start() > range.end() || *range.end() > height {
+        return Err(Error::InvalidRange(range, height));
+    }
+    let mut count = 0;
+    for height in range {
+        store.get(height)?.consensus_encode(&mut writer)?;
+        count += 1;
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Read all headers from a reader.
+fn read<H: Decodable, R: Read>(mut reader: R) -> Result<Vec<H>, Error> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let mut cursor = io::Cursor::new(&bytes);
+    let mut headers = Vec::new();
+
+    while (cursor.position() as usize) < bytes.len() {
+        headers.push(H::consensus_decode(&mut cursor)?);
+    }
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nakamoto_common::block::filter::FilterHeader;
+    use nakamoto_common::network::Network;
+    use nakamoto_test::block::gen;
+
+    #[test]
+    fn test_export_import() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let network = NetworkParams::from(Network::Regtest);
+        let chain = gen::blockchain(network.genesis.clone(), 16, &mut rng);
+        let cfheaders = gen::cfheaders_from_blocks(
+            FilterHeader::from_genesis_block(&network.genesis),
+            chain.tail.iter(),
+        );
+
+        let src = tempfile::tempdir().unwrap();
+        let src = Stores::new(src.path(), &network);
+        {
+            let mut headers = src.open_headers(&network).unwrap();
+            let mut filters = src.open_filters(&network).unwrap();
+
+            headers.put(chain.tail.iter().map(|b| b.header)).unwrap();
+            filters
+                .put(cfheaders.iter().map(|(hash, header)| StoredHeader {
+                    hash: *hash,
+                    header: *header,
+                }))
+                .unwrap();
+        }
+
+        let dst = tempfile::tempdir().unwrap();
+        let dst = Stores::new(dst.path(), &network);
+
+        // Import the chain in two overlapping ranges.
+        for range in [1..=10, 6..=16].iter() {
+            let (mut headers, mut filters) = (Vec::new(), Vec::new());
+            let count = export(
+                &src,
+                &network,
+                range.clone(),
+                &mut headers,
+                Some(&mut filters),
+            )
+            .unwrap();
+            assert_eq!(count, range.clone().count());
+
+            let height =
+                import(&dst, &network, headers.as_slice(), Some(filters.as_slice())).unwrap();
+            assert_eq!(height, *range.end());
+        }
+
+        let headers = dst.open_headers(&network).unwrap();
+        let filters = dst.open_filters(&network).unwrap();
+
+        assert_eq!(headers.height().unwrap(), 16);
+        assert_eq!(filters.height().unwrap(), 16);
+        assert_eq!(headers.get(16).unwrap(), chain.last().header);
+
+        // Ranges outside of the store can't be exported.
+        assert!(matches!(
+            export(&src, &network, 0..=8, Vec::new(), None),
+            Err(Error::InvalidRange(_, 16))
+        ));
+        assert!(matches!(
+            export(&src, &network, 8..=17, Vec::new(), None),
+            Err(Error::InvalidRange(_, 16))
+        ));
+
+        // Headers that don't connect are rejected.
+        let mut headers = Vec::new();
+        export(&src, &network, 8..=8, &mut headers, None).unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let other = Stores::new(other.path(), &network);
+        assert!(matches!(
+            import(&other, &network, headers.as_slice(), None),
+            Err(Error::Disconnected)
+        ));
+    }
+}
@@ -5,10 +5,12 @@ use std::net;
 use std::path::PathBuf;
 use std::time;
 
-pub use nakamoto_client::client::{self, Client, Config, Network};
+pub use nakamoto_client::client::{self, Client, Config, Network, NetworkParams, Signet};
 pub use nakamoto_client::error::Error;
 pub use nakamoto_client::Domain;
 
+pub mod db;
+pub mod headers;
 pub mod logger;
 
 /// The network reactor we're going to use.
@@ -21,7 +23,7 @@ pub fn run(
     listen: &[net::SocketAddr],
     root: Option<PathBuf>,
     domains: &[Domain],
-    network: Network,
+    network: NetworkParams,
 ) -> Result<(), Error> {
     let mut cfg = Config {
         network,
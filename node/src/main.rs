@@ -1,10 +1,16 @@
+use std::fs;
 use std::net;
 use std::path::PathBuf;
 
 use argh::FromArgs;
 
-use nakamoto_client::client::Network;
-use nakamoto_node::{logger, Domain};
+use bitcoin::Script;
+use nakamoto_chain::block::store;
+use nakamoto_chain::integrity::Report;
+use nakamoto_client::client::{Network, NetworkParams, Signet};
+use nakamoto_node::db;
+use nakamoto_node::headers::{self, Stores};
+use nakamoto_node::{logger, Config, Domain};
 
 #[derive(FromArgs)]
 /// A Bitcoin light client.
@@ -21,6 +27,18 @@ pub struct Options {
     #[argh(switch)]
     pub testnet: bool,
 
+    /// use the bitcoin signet network (default: false)
+    #[argh(switch)]
+    pub signet: bool,
+
+    /// custom signet block challenge, as a hex-encoded script (implies --signet)
+    #[argh(option)]
+    pub signet_challenge: Option<Script>,
+
+    /// custom signet seed nodes, as `host` or `host:port`
+    #[argh(option)]
+    pub signet_seed: Vec<String>,
+
     /// only connect to IPv4 addresses (default: false)
     #[argh(switch, short = '4')]
     pub ipv4: bool,
@@ -36,6 +54,83 @@ pub struct Options {
     /// root directory for nakamoto files (default: ~)
     #[argh(option)]
     pub root: Option<PathBuf>,
+
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    ExportHeaders(ExportHeaders),
+    ImportHeaders(ImportHeaders),
+    Db(Db),
+}
+
+#[derive(FromArgs)]
+/// Verify, repair or compact the header stores.
+#[argh(subcommand, name = "db")]
+pub struct Db {
+    #[argh(subcommand)]
+    pub command: DbCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum DbCommand {
+    Verify(DbVerify),
+    Repair(DbRepair),
+    Compact(DbCompact),
+}
+
+#[derive(FromArgs)]
+/// Verify the header stores and print store statistics.
+#[argh(subcommand, name = "verify")]
+pub struct DbVerify {}
+
+#[derive(FromArgs)]
+/// Truncate the header stores to their last valid header. Truncated headers are
+/// downloaded again on the next sync.
+#[argh(subcommand, name = "repair")]
+pub struct DbRepair {}
+
+#[derive(FromArgs)]
+/// Rewrite the header stores, keeping only valid headers.
+#[argh(subcommand, name = "compact")]
+pub struct DbCompact {}
+
+#[derive(FromArgs)]
+/// Export block headers from the header store.
+#[argh(subcommand, name = "export-headers")]
+pub struct ExportHeaders {
+    /// file to write block headers to
+    #[argh(positional)]
+    pub output: PathBuf,
+
+    /// file to write the matching filter headers to
+    #[argh(option)]
+    pub filters: Option<PathBuf>,
+
+    /// height of the first header to export (default: 1)
+    #[argh(option, default = "1")]
+    pub from: u64,
+
+    /// height of the last header to export (default: store height)
+    #[argh(option)]
+    pub to: Option<u64>,
+}
+
+#[derive(FromArgs)]
+/// Import and validate block headers into the header store.
+#[argh(subcommand, name = "import-headers")]
+pub struct ImportHeaders {
+    /// file to read block headers from
+    #[argh(positional)]
+    pub input: PathBuf,
+
+    /// file to read the matching filter headers from
+    #[argh(option)]
+    pub filters: Option<PathBuf>,
 }
 
 impl Options {
@@ -50,9 +145,13 @@ fn main() {
     logger::init(opts.log).expect("initializing logger for the first time");
 
     let network = if opts.testnet {
-        Network::Testnet
+        NetworkParams::from(Network::Testnet)
+    } else if let Some(challenge) = opts.signet_challenge {
+        Signet::custom(challenge, opts.signet_seed).network_params()
+    } else if opts.signet {
+        NetworkParams::from(Network::Signet)
     } else {
-        Network::Mainnet
+        NetworkParams::from(Network::Mainnet)
     };
 
     let domains = if opts.ipv4 && opts.ipv6 {
@@ -65,8 +164,105 @@ fn main() {
         vec![Domain::IPV4, Domain::IPV6]
     };
 
-    if let Err(e) = nakamoto_node::run(&opts.connect, &opts.listen, opts.root, &domains, network) {
+    let result: Result<(), Box<dyn std::error::Error>> = match opts.command {
+        Some(command) => {
+            let root = opts.root.unwrap_or_else(|| Config::default().root);
+            let stores = Stores::new(&root, &network);
+
+            run_command(command, &stores, &network).map_err(Box::from)
+        }
+        None => nakamoto_node::run(&opts.connect, &opts.listen, opts.root, &domains, network)
+            .map_err(Box::from),
+    };
+
+    if let Err(e) = result {
         log::error!("Exiting: {}", e);
         std::process::exit(1);
     }
 }
+
+fn run_command(
+    command: Command,
+    stores: &Stores,
+    network: &NetworkParams,
+) -> Result<(), headers::Error> {
+    match command {
+        Command::ExportHeaders(cmd) => {
+            let to = match cmd.to {
+                Some(to) => to,
+                None => headers::height(stores, network)?,
+            };
+            let output = fs::File::create(&cmd.output)?;
+            let filters = match &cmd.filters {
+                Some(path) => Some(fs::File::create(path)?),
+                None => None,
+            };
+            let count = headers::export(stores, network, cmd.from..=to, output, filters)?;
+
+            log::info!("Exported {} header(s) to {:?}", count, cmd.output);
+        }
+        Command::ImportHeaders(cmd) => {
+            let input = fs::File::open(&cmd.input)?;
+            let filters = match &cmd.filters {
+                Some(path) => Some(fs::File::open(path)?),
+                None => None,
+            };
+            let height = headers::import(stores, network, input, filters)?;
+
+            log::info!("Imported headers from {:?}, height = {}", cmd.input, height);
+        }
+        Command::Db(Db {
+            command: DbCommand::Verify(_),
+        }) => {
+            let reports = db::verify(stores, network)?;
+
+            print_report("Header store", &reports.headers);
+            print_report("Filter header store", &reports.filters);
+
+            if !reports.is_ok() {
+                return Err(store::Error::Corruption.into());
+            }
+        }
+        Command::Db(Db {
+            command: DbCommand::Repair(_),
+        }) => {
+            let reports = db::repair(stores, network)?;
+
+            print_report("Header store", &reports.headers);
+            print_report("Filter header store", &reports.filters);
+
+            let reports = db::verify(stores, network)?;
+            log::info!(
+                "Repaired stores, header height = {}, filter header height = {}",
+                reports.headers.height,
+                reports.filters.height
+            );
+        }
+        Command::Db(Db {
+            command: DbCommand::Compact(_),
+        }) => {
+            let reclaimed = db::compact(stores, network)?;
+
+            log::info!("Compacted stores, reclaimed {} byte(s)", reclaimed);
+        }
+    }
+    Ok(())
+}
+
+fn print_report(name: &str, report: &Report) {
+    log::info!(
+        "{}: {} byte(s), height = {}, checkpoints verified = {}",
+        name,
+        report.size,
+        report.height,
+        report.checkpoints
+    );
+    if let Some(corruption) = &report.corruption {
+        log::warn!(
+            "{}: {}; {} header(s) would be lost by repairing",
+            name,
+            corruption,
+            report.invalid()
+        );
+    }
+}
@@ -11,6 +11,7 @@ pub mod fees;
 pub mod invmgr;
 pub mod peermgr;
 pub mod pingmgr;
+pub mod policy;
 pub mod syncmgr;
 
 #[cfg(test)]
@@ -19,7 +20,8 @@ mod tests;
 use addrmgr::AddressManager;
 use cbfmgr::FilterManager;
 use channel::Channel;
-use invmgr::InventoryManager;
+use fees::FeeBump;
+use invmgr::{Broadcast, InventoryManager};
 use peermgr::PeerManager;
 use pingmgr::PingManager;
 use syncmgr::SyncManager;
@@ -33,21 +35,20 @@ use std::ops::{Bound, RangeInclusive};
 use std::sync::Arc;
 
 use bitcoin::blockdata::block::BlockHeader;
-use bitcoin::consensus::params::Params;
 use bitcoin::network::constants::ServiceFlags;
 use bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
 use bitcoin::network::message_blockdata::{GetHeadersMessage, Inventory};
 use bitcoin::network::message_filter::GetCFilters;
 use bitcoin::network::message_network::VersionMessage;
 use bitcoin::network::Address;
-use bitcoin::Script;
+use bitcoin::{OutPoint, Script, Txid};
 
 use nakamoto_common::block::filter::Filters;
 use nakamoto_common::block::time::{AdjustedTime, LocalDuration, LocalTime};
 use nakamoto_common::block::tree::{self, BlockTree, ImportResult};
 use nakamoto_common::block::{BlockHash, Height};
 use nakamoto_common::block::{BlockTime, Transaction};
-use nakamoto_common::network::{self, Network};
+use nakamoto_common::network::NetworkParams;
 use nakamoto_common::nonempty::NonEmpty;
 use nakamoto_common::p2p::peer::AddressSource;
 use nakamoto_common::p2p::{peer, Domain};
@@ -107,10 +108,22 @@ pub enum FeeEstimation {
 pub enum Command {
     /// Get block header at height.
     GetBlockByHeight(Height, chan::Sender<Option<BlockHeader>>),
+    /// Get a block header of the active chain by hash, along with its height.
+    GetHeader(BlockHash, chan::Sender<Option<(Height, BlockHeader)>>),
+    /// Get the block headers of the active chain in the given height range.
+    GetHeaders(RangeInclusive<Height>, chan::Sender<Vec<BlockHeader>>),
+    /// Check whether the first block is an ancestor of the second.
+    IsAncestor(BlockHash, BlockHash, chan::Sender<bool>),
+    /// Find the point at which a block forks off the active chain.
+    FindFork(BlockHash, chan::Sender<Option<(Height, BlockHeader)>>),
+    /// Get the tips of the known stale branches.
+    GetStaleTips(chan::Sender<Vec<(Height, BlockHeader)>>),
     /// Get connected peers.
     GetPeers(ServiceFlags, chan::Sender<Vec<Peer>>),
     /// Get the tip of the active chain.
     GetTip(chan::Sender<(Height, BlockHeader)>),
+    /// Get the synchronization status of headers, filter headers, filters and blocks.
+    GetSyncStatus(chan::Sender<SyncStatus>),
     /// Get a block from the active chain.
     GetBlock(BlockHash),
     /// Get block filters.
@@ -132,6 +145,15 @@ pub enum Command {
         /// Scripts to match on.
         watch: Vec<Script>,
     },
+    /// Watch an outpoint for spends, from the given height.
+    WatchOutpoint {
+        /// The outpoint to watch.
+        outpoint: OutPoint,
+        /// Script of the output being spent, used to match compact filters.
+        script: Script,
+        /// Scan filters from this height.
+        from: Height,
+    },
     /// Broadcast to peers matching the predicate.
     Broadcast(NetworkMessage, fn(Peer) -> bool, chan::Sender<Vec<PeerId>>),
     /// Send a message to a random peer.
@@ -150,18 +172,73 @@ pub enum Command {
     /// Submit a transaction to the network.
     SubmitTransaction(
         Transaction,
+        Broadcast,
+        chan::Sender<Result<NonEmpty<PeerId>, CommandError>>,
+    ),
+    /// Submit a transaction that was submitted in a previous session, eg. before a restart.
+    /// The transaction keeps its original submission time, which determines when it expires.
+    ResubmitTransaction(Transaction, Broadcast, LocalTime),
+    /// Submit a package of dependent transactions to the network.
+    SubmitPackage(
+        Vec<Transaction>,
+        chan::Sender<Result<NonEmpty<PeerId>, CommandError>>,
+    ),
+    /// Get the submitted transactions that are not yet confirmed.
+    GetPendingTransactions(chan::Sender<Vec<Transaction>>),
+    /// Bump the fee of a previously submitted transaction.
+    BumpFee(
+        Txid,
+        FeeBump,
         chan::Sender<Result<NonEmpty<PeerId>, CommandError>>,
     ),
     /// Shutdown the protocol.
     Shutdown,
 }
 
+/// Progress of a synchronization phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Height synced up to.
+    pub height: Height,
+    /// Height we are syncing towards.
+    pub best: Height,
+}
+
+impl Progress {
+    /// Check whether this phase is complete.
+    pub fn is_done(&self) -> bool {
+        self.height >= self.best
+    }
+}
+
+/// Synchronization status, across all sync phases.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncStatus {
+    /// Block header sync, towards the best height known from peers.
+    pub headers: Progress,
+    /// Filter header sync, towards the block header tip.
+    pub filter_headers: Progress,
+    /// Filter scan, towards the end of the current rescan or the block header tip.
+    pub filters: Progress,
+    /// Number of matched blocks waiting to be downloaded or processed.
+    pub blocks: usize,
+}
+
 /// A generic error resulting from processing a [`Command`].
 #[derive(Error, Debug)]
 pub enum CommandError {
     /// Not connected to any peer with the required services.
     #[error("not connected to any peer with the required services")]
     NotConnected,
+    /// The transaction was not found in the mempool.
+    #[error("transaction {0} not found in mempool")]
+    TransactionNotFound(Txid),
+    /// The fee bump is invalid.
+    #[error("invalid fee bump: {0}")]
+    InvalidFeeBump(&'static str),
+    /// The transaction doesn't pass local policy checks.
+    #[error("invalid transaction: {0}")]
+    InvalidTransaction(#[from] policy::Error),
 }
 
 pub use cbfmgr::GetFiltersError;
@@ -239,6 +316,8 @@ pub enum DisconnectReason {
     PeerServices(ServiceFlags),
     /// Peer chain is too far behind.
     PeerHeight(Height),
+    /// Peer's best chain doesn't have the minimum chain work.
+    PeerChainWork,
     /// Peer magic is invalid.
     PeerMagic(u32),
     /// Peer timed out.
@@ -276,6 +355,7 @@ impl fmt::Display for DisconnectReason {
             Self::PeerProtocolVersion(_) => write!(f, "peer protocol version mismatch"),
             Self::PeerServices(_) => write!(f, "peer doesn't have the required services"),
             Self::PeerHeight(_) => write!(f, "peer is too far behind"),
+            Self::PeerChainWork => write!(f, "peer chain doesn't have enough work"),
             Self::PeerMagic(magic) => write!(f, "received message with invalid magic: {}", magic),
             Self::PeerTimeout(s) => write!(f, "peer timed out: {:?}", s),
             Self::SelfConnection => write!(f, "detected self-connection"),
@@ -296,10 +376,8 @@ mod message {
     }
 
     impl Builder {
-        pub fn new(network: Network) -> Self {
-            Builder {
-                magic: network.magic(),
-            }
+        pub fn new(magic: u32) -> Self {
+            Builder { magic }
         }
 
         pub fn message(&self, addr: net::SocketAddr, payload: NetworkMessage) -> Out {
@@ -357,11 +435,9 @@ pub struct Protocol<T, F, P> {
     /// Block tree.
     tree: T,
     /// Bitcoin network we're connecting to.
-    network: network::Network,
+    network: NetworkParams,
     /// Our protocol version.
     protocol_version: u32,
-    /// Consensus parameters.
-    params: Params,
     /// Peer address manager.
     addrmgr: AddressManager<P, Upstream>,
     /// Blockchain synchronization manager.
@@ -392,7 +468,7 @@ pub struct Protocol<T, F, P> {
 #[derive(Debug, Clone)]
 pub struct Config {
     /// Bitcoin network we are connected to.
-    pub network: network::Network,
+    pub network: NetworkParams,
     /// Peers to connect to.
     pub connect: Vec<net::SocketAddr>,
     /// Supported communication domains.
@@ -403,8 +479,6 @@ pub struct Config {
     pub required_services: ServiceFlags,
     /// Peer whitelist. Peers in this list are trusted by default.
     pub whitelist: Whitelist,
-    /// Consensus parameters.
-    pub params: Params,
     /// Our protocol version.
     pub protocol_version: u32,
     /// Our user agent.
@@ -419,13 +493,19 @@ pub struct Config {
     pub target: &'static str,
     /// Protocol event hooks.
     pub hooks: Hooks,
+    /// Mempool monitoring configuration. If set, transactions relayed by peers are requested
+    /// and matched against the watchlist.
+    pub mempool: Option<invmgr::MempoolConfig>,
+    /// Number of reverted blocks from which a re-org is reported as deep.
+    pub deep_reorg_depth: usize,
+    /// Time after which submitted transactions that haven't confirmed are dropped.
+    pub mempool_expiry: LocalDuration,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            network: network::Network::Mainnet,
-            params: Params::new(network::Network::Mainnet.into()),
+            network: NetworkParams::default(),
             connect: Vec::new(),
             domains: Domain::all(),
             services: ServiceFlags::NONE,
@@ -438,6 +518,9 @@ impl Default for Config {
             user_agent: USER_AGENT,
             target: "self",
             hooks: Hooks::default(),
+            mempool: None,
+            deep_reorg_depth: syncmgr::DEEP_REORG_DEPTH,
+            mempool_expiry: invmgr::MEMPOOL_EXPIRY,
         }
     }
 }
@@ -446,23 +529,20 @@ impl Config {
     /// Construct a new configuration.
     pub fn from(
         target: &'static str,
-        network: network::Network,
+        network: impl Into<NetworkParams>,
         connect: Vec<net::SocketAddr>,
     ) -> Self {
-        let params = Params::new(network.into());
-
         Self {
-            network,
+            network: network.into(),
             connect,
             target,
-            params,
             ..Self::default()
         }
     }
 
     /// Get the listen port.
     pub fn port(&self) -> u16 {
-        self.network.port()
+        self.network.port
     }
 }
 
@@ -514,23 +594,30 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
             user_agent,
             required_services,
             target,
-            params,
             hooks,
+            mempool,
+            deep_reorg_depth,
+            mempool_expiry,
         } = config;
 
-        let upstream = Upstream::new(network, protocol_version, target, upstream);
+        let upstream = Upstream::new(network.magic, protocol_version, target, upstream);
         let syncmgr = SyncManager::new(
             syncmgr::Config {
                 max_message_headers: syncmgr::MAX_MESSAGE_HEADERS,
                 request_timeout: syncmgr::REQUEST_TIMEOUT,
-                params: params.clone(),
+                params: network.params.clone(),
+                minimum_chain_work: network.minimum_chain_work,
+                deep_reorg_depth,
             },
             rng.clone(),
             upstream.clone(),
         );
         let pingmgr = PingManager::new(ping_timeout, rng.clone(), upstream.clone());
         let cbfmgr = FilterManager::new(
-            cbfmgr::Config::default(),
+            cbfmgr::Config {
+                minimum_chain_work: network.minimum_chain_work,
+                ..cbfmgr::Config::default()
+            },
             rng.clone(),
             filters,
             upstream.clone(),
@@ -547,6 +634,7 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
                 preferred_services: syncmgr::REQUIRED_SERVICES | cbfmgr::REQUIRED_SERVICES,
                 services,
                 user_agent,
+                relay: mempool.is_some(),
             },
             rng.clone(),
             hooks.clone(),
@@ -561,14 +649,21 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
             peers,
             upstream.clone(),
         );
-        let invmgr = InventoryManager::new(rng.clone(), upstream.clone());
+        let invmgr = InventoryManager::new(
+            invmgr::Config {
+                mempool,
+                mempool_expiry,
+                ..invmgr::Config::default()
+            },
+            rng.clone(),
+            upstream.clone(),
+        );
 
         Self {
             tree,
             network,
             protocol_version,
             target,
-            params,
             clock,
             addrmgr,
             syncmgr,
@@ -702,7 +797,7 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
         let now = self.clock.local_time();
         let cmd = msg.cmd();
 
-        if msg.magic != self.network.magic() {
+        if msg.magic != self.network.magic {
             return self.disconnect(addr, DisconnectReason::PeerMagic(msg.magic));
         }
 
@@ -763,6 +858,10 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
             NetworkMessage::Pong(nonce) => {
                 if self.pingmgr.received_pong(addr, nonce, now) {
                     self.addrmgr.peer_active(addr, now);
+
+                    if let Some(latency) = self.pingmgr.latency(&addr) {
+                        self.invmgr.peer_latency(&addr, latency);
+                    }
                 }
             }
             NetworkMessage::Headers(headers) => {
@@ -779,7 +878,7 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
 
                         for (height, _) in reverted {
                             for tx in self.invmgr.block_reverted(height) {
-                                self.cbfmgr.watch_transaction(&tx);
+                                self.watch_transaction(&tx, height);
                             }
                         }
                     }
@@ -803,17 +902,62 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
                     .received_getheaders(&addr, (locator_hashes, stop_hash), &self.tree);
             }
             NetworkMessage::Block(block) => {
-                for confirmed in self.invmgr.received_block(&addr, block, &self.tree) {
+                if let Err(err) = self.tree.validate_block(&block) {
+                    let hash = block.block_hash();
+
+                    debug!(target: self.target, "{}: Received invalid block: {}", addr, err);
+
+                    // The header may be valid even though the body we received isn't, so we
+                    // only disconnect the peer. Once it's gone, the block is requested again
+                    // from another peer.
+                    if let tree::Error::InvalidBlockBody(_) = err {
+                        return self.disconnect(
+                            addr,
+                            DisconnectReason::PeerMisbehaving("block doesn't match its header"),
+                        );
+                    }
+                    // Drop the block's header, so that we don't build on an invalid chain.
+                    self.invmgr.cancel_block(&hash);
+
+                    match self.syncmgr.block_invalidated(&hash, now, &mut self.tree) {
+                        Err(e) => log::error!("Error invalidating block {}: {}", hash, e),
+                        Ok(reverted) if !reverted.is_empty() => {
+                            // Filter headers may not have caught up with the reverted blocks.
+                            let fork_height = reverted[0].0 - 1;
+                            let stale = self.cbfmgr.filters.height().saturating_sub(fork_height);
+
+                            self.cbfmgr.rollback(stale as usize).unwrap();
+
+                            for (height, _) in reverted {
+                                for tx in self.invmgr.block_reverted(height) {
+                                    self.watch_transaction(&tx, height);
+                                }
+                            }
+                        }
+                        Ok(_) => {}
+                    }
+
+                    return self.disconnect(
+                        addr,
+                        DisconnectReason::PeerMisbehaving("invalid block received"),
+                    );
+                }
+                for confirmed in self.invmgr.received_block(&addr, block, now, &self.tree) {
                     self.cbfmgr.unwatch_transaction(&confirmed);
                 }
             }
             NetworkMessage::Inv(inventory) => {
                 // Receive an `inv` message. This will happen if we are out of sync with a
                 // peer. And blocks are being announced. Otherwise, we expect to receive a
-                // `headers` message.
+                // `headers` message. If mempool monitoring is enabled, transactions are also
+                // announced this way.
+                self.invmgr.received_inv(addr, &inventory, now);
                 self.syncmgr
                     .received_inv(addr, inventory, &self.clock, &self.tree);
             }
+            NetworkMessage::Tx(tx) => {
+                self.invmgr.received_tx(addr, tx, &self.cbfmgr.rescan.watch);
+            }
             NetworkMessage::CFHeaders(msg) => {
                 match self.cbfmgr.received_cfheaders(&addr, msg, &self.tree, now) {
                     Err(cbfmgr::Error::InvalidMessage { reason, .. }) => {
@@ -864,6 +1008,224 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
         }
     }
 
+    /// Get the synchronization status of all sync phases.
+    fn sync_status(&self) -> SyncStatus {
+        let height = self.tree.height();
+        let rescan = &self.cbfmgr.rescan;
+
+        SyncStatus {
+            headers: Progress {
+                height,
+                best: self.syncmgr.best_height().unwrap_or_default().max(height),
+            },
+            filter_headers: Progress {
+                height: self.cbfmgr.filters.height(),
+                best: height,
+            },
+            filters: Progress {
+                height: rescan.current,
+                best: rescan.end.unwrap_or(height),
+            },
+            blocks: self.invmgr.remaining.len() + self.invmgr.received.len(),
+        }
+    }
+
+    /// Submit a transaction to the network, using the given broadcast strategy. Returns the
+    /// peers the transaction was announced to.
+    fn submit_transaction(
+        &mut self,
+        tx: Transaction,
+        broadcast: Broadcast,
+    ) -> Result<NonEmpty<PeerId>, CommandError> {
+        self.check_transaction(&tx)?;
+
+        // Update local watchlist to track submitted transactions.
+        //
+        // Nb. This is currently non-optimal, as the cfilter matching is based on the
+        // output scripts. This may trigger false-positives, since the same
+        // invoice (address) can be re-used by multiple transactions, ie. outputs
+        // can figure in more than one block.
+        self.watch_transaction(&tx, self.tree.height() + 1);
+
+        // TODO: For BIP 339 support, we can send a `WTx` inventory here.
+        let peers = match broadcast {
+            Broadcast::All => self.invmgr.announce(tx),
+            Broadcast::Single | Broadcast::Ephemeral => self
+                .announce_privately(tx, broadcast, &[])
+                .into_iter()
+                .collect(),
+        };
+        NonEmpty::from_vec(peers).ok_or(CommandError::NotConnected)
+    }
+
+    /// Submit a package of dependent transactions to the network. Parents are announced before
+    /// their children, and children are only announced to peers that have their parents.
+    /// Returns the peers the parents were announced to.
+    fn submit_package(&mut self, txs: Vec<Transaction>) -> Result<NonEmpty<PeerId>, CommandError> {
+        let package = policy::sort_package(txs);
+        let invmgr = &self.invmgr;
+
+        policy::check_package(
+            &package,
+            &self.tree,
+            |outpoint| invmgr.prevout(outpoint),
+            self.min_fee_rate(),
+        )?;
+
+        // TODO: Use BIP 331 package relay with peers that support it. This requires BIP 339
+        // support first.
+        let txids = package.iter().map(|tx| tx.txid()).collect::<Vec<_>>();
+        let mut peers = Vec::new();
+
+        for tx in package {
+            // Children are held back until the peers have their parents, so they aren't
+            // announced to anyone yet.
+            let is_child = tx
+                .input
+                .iter()
+                .any(|input| txids.contains(&input.previous_output.txid));
+
+            self.watch_transaction(&tx, self.tree.height() + 1);
+
+            for addr in self.invmgr.announce(tx) {
+                if !is_child && !peers.contains(&addr) {
+                    peers.push(addr);
+                }
+            }
+        }
+        NonEmpty::from_vec(peers).ok_or(CommandError::NotConnected)
+    }
+
+    /// Announce a transaction to a single peer, other than the given ones. Depending on the
+    /// broadcast strategy, this is either an outbound peer we're connected to, or a random
+    /// known address we connect to for this purpose. Returns the chosen peer, if any.
+    fn announce_privately(
+        &mut self,
+        tx: Transaction,
+        broadcast: Broadcast,
+        exclude: &[PeerId],
+    ) -> Option<PeerId> {
+        let now = self.clock.local_time();
+        let addr = if broadcast == Broadcast::Ephemeral {
+            self.connect_ephemeral(exclude, now)?
+        } else {
+            let peers = self
+                .peermgr
+                .negotiated(Link::Outbound)
+                .filter(|p| p.relay && !exclude.contains(&p.address()))
+                .map(|p| p.address())
+                .collect::<Vec<_>>();
+
+            if peers.is_empty() {
+                return None;
+            }
+            peers[self.rng.usize(..peers.len())]
+        };
+        self.invmgr.announce_privately(tx, broadcast, addr, now);
+
+        Some(addr)
+    }
+
+    /// Connect to a random known address we aren't connected to, other than the given ones.
+    fn connect_ephemeral(&mut self, exclude: &[PeerId], now: LocalTime) -> Option<PeerId> {
+        let services = self.peermgr.config.required_services;
+
+        // Nb. Sampled addresses aren't sampled again for some time, so this terminates.
+        while let Some((addr, _)) = self.addrmgr.sample(services) {
+            if let Ok(addr) = addr.socket_addr() {
+                if !exclude.contains(&addr) && self.peermgr.connect(&addr, now) {
+                    return Some(addr);
+                }
+            }
+        }
+        None
+    }
+
+    /// Bump the fee of a transaction in our mempool. Returns the peers the fee-bumping
+    /// transaction was announced to.
+    fn bump_fee(&mut self, txid: Txid, bump: FeeBump) -> Result<NonEmpty<PeerId>, CommandError> {
+        let original = self
+            .invmgr
+            .mempool
+            .get(&txid)
+            .ok_or(CommandError::TransactionNotFound(txid))?;
+
+        self.check_transaction(bump.transaction())?;
+
+        let peers = match bump {
+            FeeBump::Replace(tx) => {
+                if !fees::is_replaceable(original) {
+                    return Err(CommandError::InvalidFeeBump(
+                        "original transaction does not signal replaceability",
+                    ));
+                }
+                if !tx.input.iter().any(|i| {
+                    original
+                        .input
+                        .iter()
+                        .any(|o| o.previous_output == i.previous_output)
+                }) {
+                    return Err(CommandError::InvalidFeeBump(
+                        "replacement does not conflict with the original transaction",
+                    ));
+                }
+                // BIP 125 rules #3 and #4: the replacement must pay for the original, and for
+                // its own relay at the incremental relay fee rate. If some of the outputs spent
+                // are unknown to us, this is left to our peers to check.
+                let invmgr = &self.invmgr;
+                let increase = fees::fee_increase(original, &tx, |o| invmgr.prevout(o));
+
+                if let Some(increase) = increase {
+                    if increase < (fees::INCREMENTAL_RELAY_FEE * fees::vsize(&tx)) as i64 {
+                        return Err(CommandError::InvalidFeeBump(
+                            "replacement fee increase is below the incremental relay fee",
+                        ));
+                    }
+                }
+                self.cbfmgr.unwatch_transaction(&txid);
+                self.watch_transaction(&tx, self.tree.height() + 1);
+                self.invmgr.replace(&txid, tx)
+            }
+            FeeBump::Child(tx) => {
+                if !tx.input.iter().any(|i| i.previous_output.txid == txid) {
+                    return Err(CommandError::InvalidFeeBump(
+                        "child does not spend an output of the original transaction",
+                    ));
+                }
+                self.watch_transaction(&tx, self.tree.height() + 1);
+                self.invmgr.announce(tx)
+            }
+        };
+        NonEmpty::from_vec(peers).ok_or(CommandError::NotConnected)
+    }
+
+    /// Check a transaction against local policy, before announcing it.
+    fn check_transaction(&self, tx: &Transaction) -> Result<(), policy::Error> {
+        let prevouts = self.invmgr.prevouts(tx);
+
+        policy::check(tx, &self.tree, prevouts.as_deref(), self.min_fee_rate())
+    }
+
+    /// Watch one of our unconfirmed transactions from the given height. Besides its outputs,
+    /// which let us find the block confirming it, the outputs it spends are watched, so that
+    /// a block with a conflicting spend is also matched.
+    fn watch_transaction(&mut self, tx: &Transaction, from: Height) {
+        self.cbfmgr.watch_transaction(tx);
+
+        for script in self.invmgr.spent_scripts(tx) {
+            self.cbfmgr.watch_from(script, from, &self.tree);
+        }
+    }
+
+    /// Minimum fee rate of the transactions we announce.
+    fn min_fee_rate(&self) -> fees::FeeRate {
+        // Transactions paying less than the cheapest transaction of the last block are unlikely
+        // to confirm any time soon.
+        self.invmgr
+            .fee_estimate()
+            .map_or(fees::MIN_RELAY_FEE, |e| e.low.max(fees::MIN_RELAY_FEE))
+    }
+
     fn disconnect(&mut self, addr: PeerId, reason: DisconnectReason) {
         // TODO: Trigger disconnection everywhere, as if peer disconnected. This
         // avoids being in a state where we know a peer is about to get disconnected,
@@ -930,6 +1292,39 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
 
                     reply.send(header).ok();
                 }
+                Command::GetHeader(hash, reply) => {
+                    debug!(target: self.target, "Received command: GetHeader({})", hash);
+
+                    reply.send(self.tree.get_block(&hash)).ok();
+                }
+                Command::GetHeaders(range, reply) => {
+                    debug!(target: self.target, "Received command: GetHeaders({:?})", range);
+
+                    let end = Height::min(*range.end(), self.tree.height());
+                    let headers = (*range.start()..=end)
+                        .filter_map(|height| self.tree.get_block_by_height(height))
+                        .collect();
+
+                    reply.send(headers).ok();
+                }
+                Command::IsAncestor(ancestor, hash, reply) => {
+                    debug!(
+                        target: self.target,
+                        "Received command: IsAncestor({}, {})", ancestor, hash
+                    );
+
+                    reply.send(self.tree.is_ancestor(&ancestor, &hash)).ok();
+                }
+                Command::FindFork(hash, reply) => {
+                    debug!(target: self.target, "Received command: FindFork({})", hash);
+
+                    reply.send(self.tree.find_fork(&hash)).ok();
+                }
+                Command::GetStaleTips(reply) => {
+                    debug!(target: self.target, "Received command: GetStaleTips");
+
+                    reply.send(self.tree.stale_tips()).ok();
+                }
                 Command::GetPeers(services, reply) => {
                     debug!(target: self.target, "Received command: GetPeers");
 
@@ -1003,6 +1398,9 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
 
                     reply.send((height, header)).ok();
                 }
+                Command::GetSyncStatus(reply) => {
+                    reply.send(self.sync_status()).ok();
+                }
                 Command::GetFilters(range, reply) => {
                     debug!(target: self.target,
                         "Received command: GetFilters({}...{})", range.start(), range.end());
@@ -1013,30 +1411,63 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
                 Command::GetBlock(hash) => {
                     self.invmgr.get_block(hash);
                 }
-                Command::SubmitTransaction(tx, reply) => {
+                Command::SubmitTransaction(tx, broadcast, reply) => {
                     debug!(target: self.target, "Received command: SubmitTransaction(..)");
 
-                    // Update local watchlist to track submitted transactions.
-                    //
-                    // Nb. This is currently non-optimal, as the cfilter matching is based on the
-                    // output scripts. This may trigger false-positives, since the same
-                    // invoice (address) can be re-used by multiple transactions, ie. outputs
-                    // can figure in more than one block.
-                    self.cbfmgr.watch_transaction(&tx);
-
-                    // TODO: For BIP 339 support, we can send a `WTx` inventory here.
-                    let peers = self.invmgr.announce(tx);
-
-                    if let Some(peers) = NonEmpty::from_vec(peers) {
-                        reply.send(Ok(peers)).ok();
-                    } else {
-                        reply.send(Err(CommandError::NotConnected)).ok();
+                    reply.send(self.submit_transaction(tx, broadcast)).ok();
+                }
+                Command::ResubmitTransaction(tx, broadcast, submitted) => {
+                    let txid = tx.txid();
+
+                    debug!(target: self.target, "Received command: ResubmitTransaction({})", txid);
+
+                    match self.submit_transaction(tx.clone(), broadcast) {
+                        Ok(_) => {}
+                        // There is no peer to broadcast the transaction privately to yet.
+                        Err(CommandError::NotConnected) if broadcast != Broadcast::All => {
+                            let now = self.clock.local_time();
+                            self.invmgr.defer_private(tx, broadcast, now);
+                        }
+                        Err(err) => {
+                            debug!(target: self.target, "Transaction {} not resubmitted: {}", txid, err);
+                        }
                     }
+                    self.invmgr.submitted(txid, submitted);
+                }
+                Command::SubmitPackage(txs, reply) => {
+                    debug!(target: self.target, "Received command: SubmitPackage(..)");
+
+                    reply.send(self.submit_package(txs)).ok();
+                }
+                Command::GetPendingTransactions(reply) => {
+                    debug!(target: self.target, "Received command: GetPendingTransactions");
+
+                    // Parents are returned before their children, so that the transactions can
+                    // be submitted again in this order.
+                    let pending = self.invmgr.mempool.values().cloned().collect();
+                    reply.send(policy::sort_package(pending)).ok();
+                }
+                Command::BumpFee(txid, bump, reply) => {
+                    debug!(target: self.target, "Received command: BumpFee({})", txid);
+
+                    reply.send(self.bump_fee(txid, bump)).ok();
                 }
                 Command::Rescan { from, to, watch } => {
                     debug!(target: self.target, "Received command: Rescan({:?}, {:?})", from, to);
                     self.cbfmgr.rescan(from, to, watch, &self.tree);
                 }
+                Command::WatchOutpoint {
+                    outpoint,
+                    script,
+                    from,
+                } => {
+                    debug!(
+                        target: self.target,
+                        "Received command: WatchOutpoint({}, {})", outpoint, from
+                    );
+                    self.invmgr.watch_outpoint(outpoint);
+                    self.cbfmgr.watch_from(script, from, &self.tree);
+                }
                 Command::Shutdown => {
                     self.upstream.push(Out::Shutdown);
                 }
@@ -1044,8 +1475,19 @@ impl<T: BlockTree, F: Filters, P: peer::Store> Protocol<T, F, P> {
             Input::Tick => {
                 trace!(target: self.target, "Received tick");
 
+                // Send privately broadcast transactions that didn't propagate to other peers.
+                for (tx, broadcast, peers) in self.invmgr.private_timeouts(local_time) {
+                    let txid = tx.txid();
+
+                    if self.announce_privately(tx, broadcast, &peers).is_none() {
+                        debug!(
+                            target: self.target,
+                            "No peer available to privately broadcast {} to", txid
+                        );
+                    }
+                }
                 self.invmgr.received_tick(local_time, &self.tree);
-                self.syncmgr.received_tick(local_time, &self.tree);
+                self.syncmgr.received_tick(local_time, &mut self.tree);
                 self.pingmgr.received_tick(local_time);
                 self.addrmgr.received_tick(local_time);
                 self.peermgr.received_tick(local_time, &mut self.addrmgr);
@@ -977,7 +977,7 @@ mod tests {
 
         let mut addrmgr = {
             let upstream =
-                crate::protocol::channel::Channel::new(Network::Mainnet, 0, "test", sender);
+                crate::protocol::channel::Channel::new(Network::Mainnet.magic(), 0, "test", sender);
 
             AddressManager::new(
                 Config::default(),
@@ -15,7 +15,7 @@ use bitcoin::{Script, Transaction, Txid};
 use nakamoto_common::block::filter::{self, BlockFilter, Filters};
 use nakamoto_common::block::time::{Clock, LocalDuration, LocalTime};
 use nakamoto_common::block::tree::BlockTree;
-use nakamoto_common::block::{BlockHash, Height};
+use nakamoto_common::block::{BlockHash, Height, Work};
 use nakamoto_common::collections::{AddressBook, HashMap, HashSet};
 use nakamoto_common::source;
 
@@ -225,12 +225,16 @@ pub enum GetFiltersError {
 pub struct Config {
     /// How long to wait for a response from a peer.
     pub request_timeout: Timeout,
+    /// Minimum total work of the block header chain, before filters are synced
+    /// and rescans are run.
+    pub minimum_chain_work: Work,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             request_timeout: Timeout::from_secs(30),
+            minimum_chain_work: Work::default(),
         }
     }
 }
@@ -400,6 +404,41 @@ impl<F: Filters, U: SyncFilters + Events + SetTimeout> FilterManager<F, U> {
         self.rescan.watch.insert(script)
     }
 
+    /// Add a script to the list of scripts to watch, and make sure filters are scanned for it
+    /// from the given height. Starts a rescan if none is active, or rewinds the active rescan
+    /// if it is already past the given height. If the active rescan has an end height, the
+    /// script is only matched up to that height.
+    pub fn watch_from<T: BlockTree>(&mut self, script: Script, from: Height, tree: &T) {
+        if !self.rescan.active {
+            // Keep watching the transactions we were watching before the rescan.
+            let transactions = std::mem::replace(
+                &mut self.rescan.transactions,
+                HashMap::with_hasher(self.rng.clone().into()),
+            );
+            self.rescan(Bound::Included(from), Bound::Unbounded, vec![script], tree);
+            self.rescan.transactions = transactions;
+
+            return;
+        }
+        self.rescan.watch.insert(script);
+
+        if from < self.rescan.current {
+            let current = self.rescan.current;
+
+            self.rescan.start = Height::min(from, self.rescan.start);
+            self.rescan.current = from;
+
+            log::debug!("Rewind rescan from {} to {}", current, from);
+
+            if tree.chain_work() < self.config.minimum_chain_work {
+                return;
+            }
+            // Nb. Heights that are already requested are not requested again.
+            self.get_cfilters(self.rescan.current..=self.filters.height(), tree)
+                .ok();
+        }
+    }
+
     /// Add transaction outputs to list of transactions to watch.
     pub fn watch_transaction(&mut self, tx: &Transaction) {
         self.rescan.transactions.insert(
@@ -442,6 +481,11 @@ impl<F: Filters, U: SyncFilters + Events + SetTimeout> FilterManager<F, U> {
         self.rescan.transactions = HashMap::with_hasher(self.rng.clone().into());
         self.rescan.requested = BTreeSet::new();
 
+        // Until our header chain has the minimum work, it can't be trusted, and we don't fetch
+        // any filters. The rescan is resumed once the chain is synced.
+        if tree.chain_work() < self.config.minimum_chain_work {
+            return;
+        }
         // Nb. If our filter header chain isn't caught up with our block header chain,
         // this range will be empty, and this will effectively do nothing.
         self.get_cfilters(self.rescan.current..=self.filters.height(), tree)
@@ -765,12 +809,15 @@ impl<F: Filters, U: SyncFilters + Events + SetTimeout> FilterManager<F, U> {
         assert!(filter_height <= block_height);
 
         // Don't start syncing filter headers until block headers are synced passed the last
-        // checkpoint.
+        // checkpoint, and have the minimum chain work.
         if let Some(checkpoint) = tree.checkpoints().keys().next_back() {
             if &block_height < checkpoint {
                 return;
             }
         }
+        if tree.chain_work() < self.config.minimum_chain_work {
+            return;
+        }
 
         if filter_height < block_height {
             // We need to sync the filter header chain.
@@ -1027,9 +1074,9 @@ mod tests {
 
             let mut cache = FilterCache::from(store::memory::Memory::genesis(network)).unwrap();
             cache.import_headers(cfheaders).unwrap();
-            cache.verify(network).unwrap();
+            cache.verify(&network.into()).unwrap();
 
-            let upstream = Channel::new(network, PROTOCOL_VERSION, "channel", sender);
+            let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "channel", sender);
 
             (
                 FilterManager::new(Config::default(), rng, cache, upstream),
@@ -1138,7 +1185,7 @@ mod tests {
         let mut cbfmgr = {
             let rng = fastrand::Rng::new();
             let cache = FilterCache::from(store::memory::Memory::genesis(network)).unwrap();
-            let upstream = Channel::new(network, PROTOCOL_VERSION, "test", sender);
+            let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
 
             FilterManager::new(Config::default(), rng, cache, upstream)
         };
@@ -1165,7 +1212,7 @@ mod tests {
         }
 
         assert_eq!(cbfmgr.filters.height(), 15);
-        cbfmgr.filters.verify(network).unwrap();
+        cbfmgr.filters.verify(&network.into()).unwrap();
 
         let cfilters = FILTERS
             .iter()
@@ -1319,6 +1366,65 @@ mod tests {
             .expect("Rescanning should trigger filters to be fetched");
     }
 
+    /// Test that watching a script from a given height starts or rewinds the rescan.
+    #[test]
+    fn test_watch_from() {
+        let best = 42;
+        let mut rng = fastrand::Rng::new();
+        let time = LocalTime::now();
+        let network = Network::Regtest;
+        let (mut cbfmgr, tree, chain, outputs) = util::setup(network, best);
+        let remote: PeerId = ([88, 88, 88, 88], 8333).into();
+        let tx = gen::transaction(&mut rng);
+
+        cbfmgr.peer_negotiated(
+            remote,
+            best,
+            REQUIRED_SERVICES,
+            Link::Outbound,
+            &time,
+            &tree,
+        );
+        cbfmgr.watch_transaction(&tx);
+
+        // Without an active rescan, one is started from the given height.
+        cbfmgr.watch_from(gen::script(&mut rng), 30, &tree);
+        assert!(cbfmgr.rescan.active);
+        assert_eq!(cbfmgr.rescan.start, 30);
+        assert_eq!(cbfmgr.rescan.current, 30);
+        assert!(
+            cbfmgr.rescan.transactions.contains_key(&tx.txid()),
+            "Watched transactions are kept"
+        );
+
+        for msg in util::cfilters(chain.iter().skip(30).take(10)) {
+            cbfmgr.received_cfilter(&remote, msg, &tree).unwrap();
+        }
+        assert_eq!(cbfmgr.rescan.current, 40);
+        protocol::test::messages(&outputs).for_each(drop);
+
+        // Watching from a height the rescan is already past rewinds it.
+        let script = gen::script(&mut rng);
+        cbfmgr.watch_from(script.clone(), 20, &tree);
+        assert!(cbfmgr.rescan.watch.contains(&script));
+        assert_eq!(cbfmgr.rescan.start, 20);
+        assert_eq!(cbfmgr.rescan.current, 20);
+
+        let expected = GetCFilters {
+            filter_type: 0x0,
+            start_height: 20,
+            stop_hash: tree.get_block_by_height(39).unwrap().block_hash(),
+        };
+        protocol::test::messages(&outputs)
+            .find(|(_, m)| matches!(m, NetworkMessage::GetCFilters(msg) if msg == &expected))
+            .expect("Filters are fetched again from the given height");
+
+        // Watching from a height the rescan hasn't reached doesn't rewind it.
+        cbfmgr.watch_from(gen::script(&mut rng), 35, &tree);
+        assert_eq!(cbfmgr.rescan.start, 20);
+        assert_eq!(cbfmgr.rescan.current, 20);
+    }
+
     /// Test that if we start with our cfheader chain behind our header
     /// chain, we immediately try to catch up.
     #[test]
@@ -1414,7 +1520,7 @@ mod tests {
 
             let parent = fork_height;
             let fork = gen::fork(
-                tree.get_block_by_height(parent).unwrap(),
+                &tree.get_block_by_height(parent).unwrap(),
                 fork_len,
                 &mut rng,
             );
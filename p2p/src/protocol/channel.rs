@@ -21,7 +21,6 @@ use nakamoto_common::block::{BlockHash, BlockHeader, BlockTime, Height};
 
 use crate::protocol::{DisconnectReason, Event, Out, PeerId};
 
-use super::network::Network;
 use super::{addrmgr, cbfmgr, invmgr, message, peermgr, pingmgr, syncmgr, Locators};
 
 /// Used to construct a protocol output.
@@ -40,7 +39,7 @@ pub struct Channel {
 impl Channel {
     /// Create a new channel.
     pub fn new(
-        network: Network,
+        magic: u32,
         version: u32,
         target: &'static str,
         outbound: chan::Sender<Out>,
@@ -48,7 +47,7 @@ impl Channel {
         Self {
             version,
             outbound,
-            builder: message::Builder::new(network),
+            builder: message::Builder::new(magic),
             target,
         }
     }
@@ -1,12 +1,48 @@
 //! Types and utilities related to transaction fees and fee rates.
+//!
+//! ## Fee bumping
+//!
+//! Unconfirmed transactions can have their fee bumped in one of two ways:
+//!
+//! 1. By *replacing* the transaction with a conflicting one that pays a higher fee, as specified
+//!    in BIP 125. See [`build_replacement`].
+//! 2. By spending one of its outputs with a *child* transaction that pays a high enough fee for
+//!    the parent and child to be mined together (CPFP). See [`build_child`].
+//!
+//! In both cases, the resulting transaction is *unsigned*. Once signed, it can be submitted
+//! via [`super::Command::BumpFee`].
+//!
 use bitcoin::blockdata::constants::WITNESS_SCALE_FACTOR;
-use bitcoin::{OutPoint, Transaction, TxOut};
+use bitcoin::consensus::encode;
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut};
+use thiserror::Error;
 
 use nakamoto_common::collections::HashMap;
 use nakamoto_common::nonempty::NonEmpty;
 
 use super::Height;
 
+/// Minimum fee rate for a transaction to be relayed, in satoshis/vByte.
+pub const MIN_RELAY_FEE: FeeRate = 1;
+
+/// Minimum fee rate increment of a replacement transaction, in satoshis/vByte.
+pub const INCREMENTAL_RELAY_FEE: FeeRate = 1;
+
+/// Dust threshold of P2PKH outputs, the highest of the standard output types.
+/// See [`dust_threshold`].
+pub const DUST_THRESHOLD: u64 = 546;
+
+/// Fee rate used to compute the dust threshold of outputs, in satoshis/vByte.
+pub const DUST_RELAY_FEE: FeeRate = 3;
+
+/// Input sequence number used to signal replaceability, as per BIP 125.
+/// Any sequence number below `0xfffffffe` signals replaceability.
+pub const SEQUENCE_RBF: u32 = 0xffff_fffd;
+
+/// Estimated weight of a signed P2WPKH input witness. Used to estimate the size of
+/// unsigned child transactions.
+pub const INPUT_WITNESS_WEIGHT: usize = 108;
+
 // TODO: Handle rollbacks in a more graceful way.
 // TODO: Prune UTXO set so that it doesn't grow indefinitely.
 
@@ -77,6 +113,217 @@ impl FeeEstimate {
     }
 }
 
+/// A fee bump for an unconfirmed transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeeBump {
+    /// Replace the transaction with a conflicting one, paying a higher fee (BIP 125).
+    Replace(Transaction),
+    /// Spend one of the transaction outputs with a child paying a higher fee (CPFP).
+    Child(Transaction),
+}
+
+impl FeeBump {
+    /// Get the fee-bumping transaction.
+    pub fn transaction(&self) -> &Transaction {
+        match self {
+            Self::Replace(tx) | Self::Child(tx) => tx,
+        }
+    }
+}
+
+/// An error building a fee bump.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FeeBumpError {
+    /// The previous outputs don't match the transaction inputs.
+    #[error("expected {expected} previous output(s), got {actual}")]
+    Prevouts {
+        /// Number of transaction inputs.
+        expected: usize,
+        /// Number of previous outputs supplied.
+        actual: usize,
+    },
+    /// The transaction outputs are worth more than its inputs.
+    #[error("transaction outputs exceed inputs")]
+    NegativeFee,
+    /// The given output doesn't exist.
+    #[error("output #{0} does not exist")]
+    InvalidOutput(usize),
+    /// The given output isn't worth enough to pay for the fee bump.
+    #[error("output #{0} has insufficient funds to cover the fee bump")]
+    InsufficientFunds(usize),
+}
+
+/// Get the value below which an output is considered dust and is not relayed. This is the fee
+/// paid at [`DUST_RELAY_FEE`] for the output, and for an input spending it. Unspendable outputs
+/// have no dust threshold.
+///
+/// For example, the threshold is 294 satoshis for P2WPKH outputs, 330 satoshis for P2WSH and
+/// P2TR outputs, and 546 satoshis for P2PKH outputs.
+pub fn dust_threshold(output: &TxOut) -> u64 {
+    if output.script_pubkey.is_provably_unspendable() {
+        return 0;
+    }
+    let size = encode::serialize(output).len() as u64;
+    // Outpoint, script length, sequence and signature data of a typical input spending the
+    // output. Signature data is discounted for witness programs.
+    let spend = if output.script_pubkey.is_witness_program() {
+        32 + 4 + 1 + 107 / WITNESS_SCALE_FACTOR as u64 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    DUST_RELAY_FEE * (size + spend)
+}
+
+/// Get the virtual size of a transaction, in vBytes.
+pub fn vsize(tx: &Transaction) -> u64 {
+    let scale = WITNESS_SCALE_FACTOR as u64;
+
+    (tx.get_weight() as u64 + scale - 1) / scale
+}
+
+/// Get the fee paid by a transaction, given the outputs spent by its inputs, in order.
+pub fn fee(tx: &Transaction, prevouts: &[TxOut]) -> Result<u64, FeeBumpError> {
+    if tx.input.len() != prevouts.len() {
+        return Err(FeeBumpError::Prevouts {
+            expected: tx.input.len(),
+            actual: prevouts.len(),
+        });
+    }
+    let received = prevouts.iter().map(|o| o.value).sum::<u64>();
+    let sent = tx.output.iter().map(|o| o.value).sum::<u64>();
+
+    received.checked_sub(sent).ok_or(FeeBumpError::NegativeFee)
+}
+
+/// Get how much more fee a replacement pays than the transaction it replaces, which is negative
+/// if it pays less. Inputs shared by both transactions cancel out, so only the outputs spent by
+/// one of them and not the other need to be known. Returns `None` if one of these is unknown.
+pub fn fee_increase<F>(original: &Transaction, replacement: &Transaction, prevout: F) -> Option<i64>
+where
+    F: Fn(&OutPoint) -> Option<TxOut>,
+{
+    let spent = |tx: &Transaction, other: &Transaction| -> Option<i64> {
+        let mut value = 0;
+
+        for input in &tx.input {
+            if !other
+                .input
+                .iter()
+                .any(|i| i.previous_output == input.previous_output)
+            {
+                value += prevout(&input.previous_output)?.value as i64;
+            }
+        }
+        Some(value)
+    };
+    let sent = |tx: &Transaction| tx.output.iter().map(|o| o.value as i64).sum::<i64>();
+
+    Some(
+        spent(replacement, original)? - spent(original, replacement)? - sent(replacement)
+            + sent(original),
+    )
+}
+
+/// Check whether a transaction signals replaceability, as per BIP 125.
+pub fn is_replaceable(tx: &Transaction) -> bool {
+    tx.input.iter().any(|i| i.sequence <= SEQUENCE_RBF)
+}
+
+/// Build an unsigned BIP 125 replacement of a transaction, paying the given fee rate.
+///
+/// The additional fee is deducted from the `change` output, which must be owned by the
+/// caller. The replacement pays at least the fee of the original transaction plus the
+/// [`INCREMENTAL_RELAY_FEE`], and all its inputs signal replaceability.
+///
+/// Since the replacement has the same inputs and outputs as the original, the size of the
+/// signed original is used to compute the fee.
+pub fn build_replacement(
+    tx: &Transaction,
+    prevouts: &[TxOut],
+    change: usize,
+    fee_rate: FeeRate,
+) -> Result<Transaction, FeeBumpError> {
+    let original_fee = fee(tx, prevouts)?;
+    let vsize = vsize(tx);
+    let fee = FeeRate::max(
+        fee_rate * vsize,
+        original_fee + INCREMENTAL_RELAY_FEE * vsize,
+    );
+    let mut replacement = tx.clone();
+    let output = replacement
+        .output
+        .get_mut(change)
+        .ok_or(FeeBumpError::InvalidOutput(change))?;
+
+    let dust = dust_threshold(output);
+
+    output.value = output
+        .value
+        .checked_sub(fee - original_fee)
+        .filter(|v| *v >= dust)
+        .ok_or(FeeBumpError::InsufficientFunds(change))?;
+
+    for input in replacement.input.iter_mut() {
+        input.sequence = u32::min(input.sequence, SEQUENCE_RBF);
+        // Signatures are invalidated by the change in outputs.
+        input.script_sig = Script::new();
+        input.witness.clear();
+    }
+    Ok(replacement)
+}
+
+/// Build an unsigned CPFP child of a transaction, spending the given output to the given script.
+///
+/// The child pays enough fee for the parent and child together to reach the given fee rate. The
+/// spent output must be owned by the caller, eg. the parent's change output. The size of the child
+/// is estimated assuming the output is spent with a P2WPKH witness.
+pub fn build_child(
+    parent: &Transaction,
+    prevouts: &[TxOut],
+    output: usize,
+    script_pubkey: Script,
+    fee_rate: FeeRate,
+) -> Result<Transaction, FeeBumpError> {
+    let parent_fee = fee(parent, prevouts)?;
+    let value = parent
+        .output
+        .get(output)
+        .ok_or(FeeBumpError::InvalidOutput(output))?
+        .value;
+    let mut child = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: parent.txid(),
+                vout: output as u32,
+            },
+            script_sig: Script::new(),
+            sequence: SEQUENCE_RBF,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey,
+        }],
+    };
+    let scale = WITNESS_SCALE_FACTOR as u64;
+    let child_vsize = (child.get_weight() + INPUT_WITNESS_WEIGHT) as u64 / scale + 1;
+    let fee = FeeRate::max(
+        (fee_rate * (vsize(parent) + child_vsize)).saturating_sub(parent_fee),
+        MIN_RELAY_FEE * child_vsize,
+    );
+
+    let dust = dust_threshold(&child.output[0]);
+
+    child.output[0].value = value
+        .checked_sub(fee)
+        .filter(|v| *v >= dust)
+        .ok_or(FeeBumpError::InsufficientFunds(output))?;
+
+    Ok(child)
+}
+
 /// Transaction fee rate estimator.
 #[derive(Debug, Default)]
 pub struct FeeEstimator {
@@ -97,6 +344,11 @@ impl FeeEstimator {
         FeeEstimate::from(fees)
     }
 
+    /// Get an unspent output seen in a block.
+    pub fn utxo(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+        self.utxos.get(outpoint)
+    }
+
     /// Rollback to a certain height.
     pub fn rollback(&mut self, _height: Height) {
         self.utxos.clear();
@@ -142,3 +394,135 @@ impl FeeEstimator {
         Some(rate.round() as FeeRate)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> (Transaction, Vec<TxOut>) {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let mut tx = nakamoto_test::block::gen::transaction(&mut rng);
+
+        tx.input.truncate(1);
+        tx.input[0].sequence = SEQUENCE_RBF;
+        tx.output.truncate(1);
+        tx.output[0].value = 90_000;
+
+        let prevouts = vec![TxOut {
+            value: 100_000,
+            script_pubkey: Script::new(),
+        }];
+        (tx, prevouts)
+    }
+
+    #[test]
+    fn test_dust_threshold() {
+        use bitcoin::blockdata::opcodes::all::{OP_PUSHNUM_1, OP_RETURN};
+        use bitcoin::blockdata::script::Builder;
+        use bitcoin::hashes::Hash;
+
+        let output = |script_pubkey| TxOut {
+            value: 0,
+            script_pubkey,
+        };
+        let p2tr = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_slice(&[0; 32])
+            .into_script();
+        let op_return = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(&[0; 32])
+            .into_script();
+
+        assert_eq!(
+            dust_threshold(&output(Script::new_v0_wpkh(&Hash::hash(&[])))),
+            294
+        );
+        assert_eq!(
+            dust_threshold(&output(Script::new_v0_wsh(&Hash::hash(&[])))),
+            330
+        );
+        assert_eq!(dust_threshold(&output(p2tr)), 330);
+        assert_eq!(
+            dust_threshold(&output(Script::new_p2pkh(&Hash::hash(&[])))),
+            DUST_THRESHOLD
+        );
+        assert_eq!(dust_threshold(&output(op_return)), 0);
+    }
+
+    #[test]
+    fn test_build_replacement() {
+        let (tx, prevouts) = fixture();
+        let original_fee = fee(&tx, &prevouts).unwrap();
+        let replacement = build_replacement(&tx, &prevouts, 0, 100).unwrap();
+        let replacement_fee = fee(&replacement, &prevouts).unwrap();
+
+        assert!(is_replaceable(&replacement));
+        assert_ne!(replacement.txid(), tx.txid());
+        assert_eq!(
+            replacement.input[0].previous_output,
+            tx.input[0].previous_output
+        );
+        assert!(replacement_fee >= original_fee + INCREMENTAL_RELAY_FEE * vsize(&tx));
+        assert!(replacement_fee >= 100 * vsize(&replacement));
+
+        assert_eq!(
+            build_replacement(&tx, &prevouts, 1, 100),
+            Err(FeeBumpError::InvalidOutput(1))
+        );
+        assert_eq!(
+            build_replacement(&tx, &prevouts, 0, 100_000),
+            Err(FeeBumpError::InsufficientFunds(0))
+        );
+        assert!(matches!(
+            build_replacement(&tx, &[], 0, 100),
+            Err(FeeBumpError::Prevouts { .. })
+        ));
+    }
+
+    #[test]
+    fn test_fee_increase() {
+        let (tx, prevouts) = fixture();
+        let replacement = build_replacement(&tx, &prevouts, 0, 100).unwrap();
+        let increase = fee(&replacement, &prevouts).unwrap() - fee(&tx, &prevouts).unwrap();
+
+        // The outputs spent by both transactions don't need to be known.
+        assert_eq!(
+            fee_increase(&tx, &replacement, |_| None),
+            Some(increase as i64)
+        );
+        assert_eq!(
+            fee_increase(&replacement, &tx, |_| None),
+            Some(-(increase as i64))
+        );
+
+        // Outputs spent by only one of them do.
+        let mut rng = fastrand::Rng::with_seed(2);
+        let mut other = replacement.clone();
+        other
+            .input
+            .push(nakamoto_test::block::gen::transaction(&mut rng).input[0].clone());
+
+        assert_eq!(fee_increase(&tx, &other, |_| None), None);
+        assert_eq!(
+            fee_increase(&tx, &other, |_| Some(prevouts[0].clone())),
+            Some(increase as i64 + prevouts[0].value as i64)
+        );
+    }
+
+    #[test]
+    fn test_build_child() {
+        let (parent, prevouts) = fixture();
+        let parent_fee = fee(&parent, &prevouts).unwrap();
+        let child = build_child(&parent, &prevouts, 0, Script::new(), 100).unwrap();
+        let child_fee = parent.output[0].value - child.output[0].value;
+
+        assert_eq!(child.input[0].previous_output.txid, parent.txid());
+        assert!(parent_fee + child_fee >= 100 * (vsize(&parent) + vsize(&child)));
+
+        assert_eq!(
+            build_child(&parent, &prevouts, 1, Script::new(), 100),
+            Err(FeeBumpError::InvalidOutput(1))
+        );
+    }
+}
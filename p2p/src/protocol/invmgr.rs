@@ -19,10 +19,19 @@
 //! the [`InventoryManager::received_tick`] function is called. Confirmed transactions are removed
 //! after they are burried at a certain depth.
 //!
+//! ## Mempool monitoring
+//!
+//! When configured with a [`MempoolConfig`], transactions announced by relay peers are requested
+//! and matched against the watchlist, to learn about incoming payments before they are confirmed.
+//! Matching transactions are reported via an [`Event::TxReceived`] event. Since this can use a lot
+//! of bandwidth, transaction requests are rate-limited: only a certain number of transactions are
+//! requested per minute, and only a certain number of requests may be in-flight per peer.
+//!
 use std::collections::BTreeMap;
 
+use bitcoin::blockdata::script::Instruction;
 use bitcoin::network::{constants::ServiceFlags, message_blockdata::Inventory};
-use bitcoin::{Block, BlockHash, Transaction, Txid};
+use bitcoin::{Block, BlockHash, OutPoint, PublicKey, Script, Transaction, TxIn, TxOut, Txid};
 
 // TODO: Timeout should be configurable
 // TODO: Add exponential back-off
@@ -41,7 +50,7 @@ pub const REBROADCAST_TIMEOUT: LocalDuration = LocalDuration::from_mins(1);
 /// Time between request retries.
 pub const REQUEST_TIMEOUT: LocalDuration = LocalDuration::from_secs(30);
 
-/// Maximum number of attempts to send inventories to a peer.
+/// Maximum number of attempts to send inventories to a peer, or to request a block.
 pub const MAX_ATTEMPTS: usize = 3;
 
 /// Time between idles.
@@ -50,6 +59,106 @@ pub const IDLE_TIMEOUT: LocalDuration = LocalDuration::from_secs(30);
 /// Block depth at which confirmed transactions are pruned and no longer reverted after a re-org.
 pub const TRANSACTION_PRUNE_DEPTH: Height = 12;
 
+/// Time after which a transaction we've seen announced may be requested again.
+pub const SEEN_TRANSACTION_EXPIRY: LocalDuration = LocalDuration::from_mins(60);
+
+/// Maximum number of blocks requested from peers at any given time.
+pub const MAX_BLOCKS_IN_FLIGHT: usize = 16;
+
+/// Maximum number of blocks requested from a single peer at any given time.
+pub const MAX_BLOCKS_IN_FLIGHT_PER_PEER: usize = 4;
+
+/// Number of consecutive block request timeouts after which a peer is disconnected.
+pub const MAX_BLOCK_REQUEST_FAILURES: usize = 3;
+
+/// Maximum number of heights past the lowest block still being downloaded from which blocks
+/// are requested. This bounds the number of blocks received out of order waiting to be
+/// processed.
+pub const BLOCK_DOWNLOAD_WINDOW: Height = 1024;
+
+/// Time after which a privately broadcast transaction that wasn't relayed back to us by another
+/// peer is sent to a different peer.
+pub const PRIVATE_BROADCAST_TIMEOUT: LocalDuration = LocalDuration::from_mins(2);
+
+/// Time after which a transaction that didn't propagate is re-announced.
+pub const PROPAGATION_TIMEOUT: LocalDuration = LocalDuration::from_mins(30);
+
+/// Number of peers that must have one of our transactions for it to be considered propagated.
+/// Peers that requested the transaction from us count, as well as peers that announced it
+/// to us without having requested it.
+pub const MIN_PROPAGATION_PEERS: usize = 2;
+
+/// Time after which a transaction held back until a peer requests its parents is announced
+/// anyway, since peers that already have the parents never request them from us.
+pub const HOLD_TIMEOUT: LocalDuration = LocalDuration::from_mins(1);
+
+/// Time after which a submitted transaction that hasn't confirmed is dropped from the mempool.
+pub const MEMPOOL_EXPIRY: LocalDuration = LocalDuration::from_mins(60 * 24 * 14);
+
+/// How a submitted transaction is broadcast to the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Broadcast {
+    /// Announce the transaction to all relay peers.
+    All,
+    /// Announce the transaction to a single, randomly chosen outbound peer.
+    Single,
+    /// Announce the transaction through a short-lived connection to a random known address.
+    Ephemeral,
+}
+
+impl Default for Broadcast {
+    fn default() -> Self {
+        Self::All
+    }
+}
+
+/// Mempool monitoring configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolConfig {
+    /// Maximum number of transactions requested per minute, across all peers.
+    pub max_requests_per_minute: usize,
+    /// Maximum number of unanswered transaction requests per peer.
+    pub max_requests_in_flight: usize,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            max_requests_per_minute: 60,
+            max_requests_in_flight: 16,
+        }
+    }
+}
+
+/// Inventory manager configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Mempool monitoring configuration. If `None`, transactions relayed by peers are ignored.
+    pub mempool: Option<MempoolConfig>,
+    /// Maximum number of blocks requested from peers at any given time.
+    pub max_blocks_in_flight: usize,
+    /// Maximum number of blocks requested from a single peer at any given time.
+    pub max_blocks_in_flight_per_peer: usize,
+    /// Maximum number of heights past the lowest block still being downloaded from which
+    /// blocks are requested.
+    pub block_download_window: Height,
+    /// Time after which submitted transactions that haven't confirmed are dropped from the
+    /// mempool.
+    pub mempool_expiry: LocalDuration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mempool: None,
+            max_blocks_in_flight: MAX_BLOCKS_IN_FLIGHT,
+            max_blocks_in_flight_per_peer: MAX_BLOCKS_IN_FLIGHT_PER_PEER,
+            block_download_window: BLOCK_DOWNLOAD_WINDOW,
+            mempool_expiry: MEMPOOL_EXPIRY,
+        }
+    }
+}
+
 /// The ability to send and receive inventory data.
 pub trait Inventories {
     /// Sends an `inv` message to a peer.
@@ -79,6 +188,60 @@ pub enum Event {
         /// Block height.
         height: Height,
     },
+    /// A block could not be fetched after [`MAX_ATTEMPTS`] requests. The block is still
+    /// requested from peers until it is received.
+    BlockFailed {
+        /// Block hash.
+        hash: BlockHash,
+        /// Number of requests that timed out.
+        attempts: usize,
+    },
+    /// A transaction paying to a watched script was received from a peer, before being
+    /// confirmed. Only emitted when mempool monitoring is enabled.
+    TxReceived {
+        /// The received transaction.
+        transaction: Transaction,
+        /// Peer we received the transaction from.
+        from: PeerId,
+        /// Time at which the transaction was first announced to us.
+        first_seen: LocalTime,
+    },
+    /// A transaction was added to the mempool and announced to peers.
+    Announced {
+        /// The announced transaction.
+        transaction: Transaction,
+        /// How the transaction is broadcast.
+        broadcast: Broadcast,
+    },
+    /// A transaction was replaced by another one and removed from the mempool.
+    Replaced {
+        /// The replaced transaction ID.
+        txid: Txid,
+        /// The replacing transaction ID.
+        replaced_by: Txid,
+    },
+    /// A transaction didn't confirm within the mempool expiry, and was removed from the mempool.
+    /// It is no longer announced.
+    Expired {
+        /// The expired transaction ID.
+        txid: Txid,
+    },
+    /// One of our transactions was announced to us by a peer that didn't get it from us,
+    /// which means that it propagated through the network. Emitted once per peer.
+    Relayed {
+        /// The relayed transaction ID.
+        txid: Txid,
+        /// The peer announcing the transaction.
+        peer: PeerId,
+    },
+    /// One of our transactions propagated, ie. at least [`MIN_PROPAGATION_PEERS`] peers have it.
+    /// Emitted every time another peer is known to have it.
+    Propagated {
+        /// The propagated transaction ID.
+        txid: Txid,
+        /// The peers known to have the transaction.
+        peers: Vec<PeerId>,
+    },
     /// A peer acknowledged one of our transaction inventories.
     Acknowledged {
         /// The acknowledged transaction ID.
@@ -100,6 +263,23 @@ pub enum Event {
         /// The reverted transaction.
         transaction: Transaction, // TODO: Just the txid?
     },
+    /// A watched outpoint was spent by a transaction in a block of the active chain.
+    OutpointSpent {
+        /// The spent outpoint.
+        outpoint: OutPoint,
+        /// The transaction spending the outpoint.
+        spending_tx: Transaction,
+        /// The height of the block in which the outpoint was spent.
+        height: Height,
+    },
+    /// The block spending a watched outpoint was reverted. The outpoint is unspent again,
+    /// until it is spent in a block of the new active chain.
+    OutpointReverted {
+        /// The outpoint, which is unspent again.
+        outpoint: OutPoint,
+        /// The reverted transaction which was spending the outpoint.
+        spending_tx: Transaction,
+    },
     /// Transaction fee rate estimated for a block.
     FeeEstimated {
         /// Block hash of the estimate.
@@ -120,6 +300,43 @@ impl std::fmt::Display for Event {
             Event::BlockProcessed { height, .. } => {
                 write!(fmt, "Processed block at height {}", height)
             }
+            Event::BlockFailed { hash, attempts } => {
+                write!(
+                    fmt,
+                    "Failed to fetch block {} after {} attempt(s)",
+                    hash, attempts
+                )
+            }
+            Event::TxReceived {
+                transaction, from, ..
+            } => {
+                write!(
+                    fmt,
+                    "{}: Received unconfirmed transaction {}",
+                    from,
+                    transaction.txid()
+                )
+            }
+            Event::Announced { transaction, .. } => {
+                write!(fmt, "Transaction {} was announced", transaction.txid())
+            }
+            Event::Replaced { txid, replaced_by } => {
+                write!(fmt, "Transaction {} was replaced by {}", txid, replaced_by)
+            }
+            Event::Expired { txid } => {
+                write!(fmt, "Transaction {} expired", txid)
+            }
+            Event::Relayed { txid, peer } => {
+                write!(fmt, "Transaction {} was relayed by peer {}", txid, peer)
+            }
+            Event::Propagated { txid, peers } => {
+                write!(
+                    fmt,
+                    "Transaction {} propagated to {} peer(s)",
+                    txid,
+                    peers.len()
+                )
+            }
             Event::Acknowledged { txid, peer } => {
                 write!(
                     fmt,
@@ -141,6 +358,26 @@ impl std::fmt::Display for Event {
             Event::Reverted { transaction, .. } => {
                 write!(fmt, "Transaction {} was reverted", transaction.txid(),)
             }
+            Event::OutpointSpent {
+                outpoint,
+                spending_tx,
+                height,
+            } => write!(
+                fmt,
+                "Outpoint {} was spent by transaction {} at height {}",
+                outpoint,
+                spending_tx.txid(),
+                height
+            ),
+            Event::OutpointReverted {
+                outpoint,
+                spending_tx,
+            } => write!(
+                fmt,
+                "Spend of outpoint {} by transaction {} was reverted",
+                outpoint,
+                spending_tx.txid()
+            ),
             Event::FeeEstimated { fees, height, .. } => {
                 write!(
                     fmt,
@@ -162,6 +399,8 @@ pub struct Peer {
 
     /// Inventories we are attempting to send to this peer.
     outbox: HashSet<Txid>,
+    /// Inventories waiting for this peer to have their parents, before being sent.
+    held: HashSet<Txid>,
     /// Number of times we attempted to send inventories to this peer.
     attempts: usize,
     /// Last time we attempted to send inventories to this peer.
@@ -170,9 +409,25 @@ pub struct Peer {
     /// Number of times a certain block was requested.
     #[allow(dead_code)]
     requests: HashMap<BlockHash, usize>,
+    /// Average round-trip latency, as measured by pings.
+    latency: Option<LocalDuration>,
+    /// Average time taken to deliver a requested block.
+    block_time: Option<LocalDuration>,
+    /// Number of consecutive scheduling rounds in which a block request to this peer timed out.
+    failures: usize,
 }
 
 impl Peer {
+    /// Queue a transaction inventory to be sent to this peer. If the peer is missing some
+    /// of the transaction's parents, it is held back until the peer has them.
+    fn queue(&mut self, txid: Txid, ready: bool) {
+        if ready {
+            self.outbox.insert(txid);
+        } else {
+            self.held.insert(txid);
+        }
+    }
+
     fn attempted(&mut self, time: LocalTime) {
         self.last_attempt = Some(time);
         self.attempts += 1;
@@ -187,6 +442,165 @@ impl Peer {
         self.last_attempt = None;
         self.attempts = 0;
     }
+
+    /// Record the delivery of a requested block.
+    fn delivered(&mut self, elapsed: LocalDuration) {
+        // Exponential moving average, giving more weight to recent deliveries.
+        self.block_time = Some(match self.block_time {
+            Some(average) => (average * 3 + elapsed) / 4,
+            None => elapsed,
+        });
+        self.failures = 0;
+    }
+
+    /// Expected time for this peer to deliver a block. Based on past deliveries if any,
+    /// otherwise on the peer latency, and penalized by recent failures.
+    fn cost(&self) -> LocalDuration {
+        let base = self
+            .block_time
+            .or(self.latency)
+            .unwrap_or_else(|| LocalDuration::from_secs(1));
+
+        base * (self.failures as u64 + 1)
+    }
+}
+
+/// Propagation state of a transaction in our mempool.
+#[derive(Debug)]
+struct Propagation {
+    /// Time at which the transaction was last announced, if it was.
+    announced: Option<LocalTime>,
+    /// Peers that requested the transaction from us.
+    acknowledged: HashSet<PeerId>,
+    /// Peers that announced the transaction to us without having requested it from us.
+    relayed: HashSet<PeerId>,
+}
+
+impl Propagation {
+    fn new(rng: fastrand::Rng) -> Self {
+        Self {
+            announced: None,
+            acknowledged: HashSet::with_hasher(rng.clone().into()),
+            relayed: HashSet::with_hasher(rng.into()),
+        }
+    }
+
+    /// Get the peers known to have the transaction.
+    fn peers(&self) -> Vec<PeerId> {
+        let mut peers = self
+            .acknowledged
+            .union(&self.relayed)
+            .copied()
+            .collect::<Vec<_>>();
+        peers.sort();
+        peers
+    }
+
+    /// Check whether the transaction propagated.
+    fn is_propagated(&self) -> bool {
+        self.acknowledged.union(&self.relayed).count() >= MIN_PROPAGATION_PEERS
+    }
+}
+
+/// A transaction broadcast privately, which wasn't yet relayed back to us by another peer.
+#[derive(Debug)]
+struct Private {
+    /// How the transaction is broadcast.
+    broadcast: Broadcast,
+    /// Peers the transaction was sent to.
+    peers: Vec<PeerId>,
+    /// Time of the last broadcast attempt.
+    since: LocalTime,
+}
+
+/// Check whether a transaction in the mempool can be announced to the given peer, ie. whether
+/// the peer has all of its parents that are also in the mempool. Otherwise, the peer would
+/// treat the transaction as an orphan.
+fn is_ready(
+    txid: &Txid,
+    addr: &PeerId,
+    mempool: &BTreeMap<Txid, Transaction>,
+    propagation: &HashMap<Txid, Propagation>,
+) -> bool {
+    let tx = if let Some(tx) = mempool.get(txid) {
+        tx
+    } else {
+        return true;
+    };
+    tx.input.iter().all(|input| {
+        let parent = &input.previous_output.txid;
+
+        !mempool.contains_key(parent)
+            || propagation.get(parent).map_or(false, |p| {
+                p.acknowledged.contains(addr) || p.relayed.contains(addr)
+            })
+    })
+}
+
+/// Derive the script of the output spent by an input, for P2WPKH and P2PKH spends.
+fn spent_script(input: &TxIn) -> Option<Script> {
+    if input.script_sig.is_empty() {
+        if let [_, key] = input.witness.as_slice() {
+            let key = PublicKey::from_slice(key).ok()?;
+
+            return Some(Script::new_v0_wpkh(&key.wpubkey_hash()?));
+        }
+        return None;
+    }
+    let pushes = input
+        .script_sig
+        .instructions()
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+
+    if let [Instruction::PushBytes(_), Instruction::PushBytes(key)] = pushes.as_slice() {
+        let key = PublicKey::from_slice(key).ok()?;
+
+        return Some(Script::new_p2pkh(&key.pubkey_hash()));
+    }
+    None
+}
+
+/// Mempool monitoring state.
+#[derive(Debug)]
+struct Monitor {
+    /// Monitoring configuration.
+    config: MempoolConfig,
+    /// Transactions requested, with the peer they were requested from and the time
+    /// at which they were first announced.
+    requested: HashMap<Txid, (PeerId, LocalTime)>,
+    /// Transactions already requested or received, and when they were first seen.
+    seen: HashMap<Txid, LocalTime>,
+    /// Start of the current rate-limiting window.
+    window: LocalTime,
+    /// Number of transactions requested in the current window.
+    window_requests: usize,
+}
+
+impl Monitor {
+    fn new(config: MempoolConfig, rng: fastrand::Rng) -> Self {
+        Self {
+            config,
+            requested: HashMap::with_hasher(rng.clone().into()),
+            seen: HashMap::with_hasher(rng.into()),
+            window: LocalTime::default(),
+            window_requests: 0,
+        }
+    }
+
+    /// Whether the given peer may be sent another transaction request.
+    fn is_allowed(&mut self, addr: &PeerId, now: LocalTime) -> bool {
+        if now - self.window >= LocalDuration::from_mins(1) {
+            self.window = now;
+            self.window_requests = 0;
+        }
+        if self.window_requests >= self.config.max_requests_per_minute {
+            return false;
+        }
+        let in_flight = self.requested.values().filter(|(a, _)| a == addr).count();
+
+        in_flight < self.config.max_requests_in_flight
+    }
 }
 
 /// Inventory manager state.
@@ -196,19 +610,43 @@ pub struct InventoryManager<U> {
     peers: AddressBook<PeerId, Peer>,
     /// Timeout used for retrying broadcasts.
     timeout: LocalDuration,
+    /// Mempool monitoring state, if enabled.
+    monitor: Option<Monitor>,
     /// Confirmed transactions by block height.
     /// Pruned after a certain depth.
     confirmed: HashMap<Height, Vec<Transaction>>,
+    /// Watched outpoints, and the height and transaction spending them, if spent.
+    /// Spent outpoints are pruned after a certain depth.
+    outpoints: HashMap<OutPoint, Option<(Height, Transaction)>>,
 
     /// Transaction fee estimator.
     estimator: FeeEstimator,
+    /// Fee estimate of the last block processed.
+    fee_estimate: Option<FeeEstimate>,
 
     /// Transaction mempool. Stores unconfirmed transactions sent to the network.
     pub mempool: BTreeMap<Txid, Transaction>,
-    /// Blocks requested and the time at which they were last requested.
-    pub remaining: HashMap<BlockHash, Option<LocalTime>>,
+    /// Transactions in the mempool that are broadcast privately, until they propagate.
+    private: HashMap<Txid, Private>,
+    /// Propagation state of the transactions in the mempool.
+    propagation: HashMap<Txid, Propagation>,
+    /// Time at which the transactions in the mempool were submitted.
+    submitted: HashMap<Txid, LocalTime>,
+    /// Time after which transactions in the mempool expire.
+    mempool_expiry: LocalDuration,
+    /// Blocks to download, and the peer and time they were last requested from.
+    pub remaining: HashMap<BlockHash, Option<(PeerId, LocalTime)>>,
+    /// Number of timed out requests for each remaining block.
+    attempts: HashMap<BlockHash, usize>,
     /// Blocks received, waiting to be processed.
     pub received: HashMap<Height, Block>,
+    /// Maximum number of blocks requested at any given time.
+    max_blocks_in_flight: usize,
+    /// Maximum number of blocks requested from a single peer at any given time.
+    max_blocks_in_flight_per_peer: usize,
+    /// Maximum number of heights past the lowest block still being downloaded from which
+    /// blocks are requested.
+    block_download_window: Height,
 
     last_tick: Option<LocalTime>,
     rng: fastrand::Rng,
@@ -217,14 +655,25 @@ pub struct InventoryManager<U> {
 
 impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
     /// Create a new inventory manager.
-    pub fn new(rng: fastrand::Rng, upstream: U) -> Self {
+    pub fn new(config: Config, rng: fastrand::Rng, upstream: U) -> Self {
         Self {
             peers: AddressBook::new(rng.clone()),
+            monitor: config.mempool.map(|c| Monitor::new(c, rng.clone())),
             mempool: BTreeMap::new(),
+            private: HashMap::with_hasher(rng.clone().into()),
+            propagation: HashMap::with_hasher(rng.clone().into()),
+            submitted: HashMap::with_hasher(rng.clone().into()),
+            mempool_expiry: config.mempool_expiry,
             estimator: FeeEstimator::default(),
+            fee_estimate: None,
             confirmed: HashMap::with_hasher(rng.clone().into()),
+            outpoints: HashMap::with_hasher(rng.clone().into()),
             remaining: HashMap::with_hasher(rng.clone().into()),
+            attempts: HashMap::with_hasher(rng.clone().into()),
             received: HashMap::with_hasher(rng.clone().into()),
+            max_blocks_in_flight: config.max_blocks_in_flight,
+            max_blocks_in_flight_per_peer: config.max_blocks_in_flight_per_peer,
+            block_download_window: config.block_download_window,
             timeout: REBROADCAST_TIMEOUT,
             last_tick: None,
             rng,
@@ -242,12 +691,74 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
         self.mempool.contains_key(txid)
     }
 
+    /// Get the fee estimate of the last block processed, if any.
+    pub fn fee_estimate(&self) -> Option<&FeeEstimate> {
+        self.fee_estimate.as_ref()
+    }
+
+    /// Get the outputs spent by a transaction, if they are all known. Outputs are looked up
+    /// in the blocks processed, and in the transactions we announced.
+    pub fn prevouts(&self, tx: &Transaction) -> Option<Vec<TxOut>> {
+        tx.input
+            .iter()
+            .map(|input| self.prevout(&input.previous_output))
+            .collect()
+    }
+
+    /// Get an output, if it is known. See [`InventoryManager::prevouts`].
+    pub fn prevout(&self, outpoint: &OutPoint) -> Option<TxOut> {
+        self.estimator.utxo(outpoint).cloned().or_else(|| {
+            self.mempool
+                .get(&outpoint.txid)
+                .and_then(|tx| tx.output.get(outpoint.vout as usize))
+                .cloned()
+        })
+    }
+
+    /// Get the scripts of the outputs spent by a transaction, as far as they are known or can
+    /// be derived from the transaction inputs.
+    pub fn spent_scripts(&self, tx: &Transaction) -> Vec<Script> {
+        tx.input
+            .iter()
+            .filter_map(|input| {
+                self.prevout(&input.previous_output)
+                    .map(|output| output.script_pubkey)
+                    .or_else(|| spent_script(input))
+            })
+            .collect()
+    }
+
     /// Called when a peer is negotiated.
     pub fn peer_negotiated(&mut self, addr: PeerId, services: ServiceFlags, relay: bool) {
         // Add existing inventories to this peer's outbox so that they are announced.
         let mut outbox = HashSet::with_hasher(self.rng.clone().into());
-        for txid in self.mempool.keys() {
-            outbox.insert(*txid);
+        let mut held = HashSet::with_hasher(self.rng.clone().into());
+
+        // Peers a transaction is broadcast privately to only ever receive that transaction,
+        // so that it can't be linked to our other transactions. Privately broadcast
+        // transactions are not announced to other peers.
+        let private = self
+            .private
+            .iter()
+            .filter(|(_, p)| p.peers.contains(&addr))
+            .map(|(txid, _)| *txid)
+            .collect::<Vec<_>>();
+        let txids = if private.is_empty() {
+            self.mempool
+                .keys()
+                .filter(|txid| !self.private.contains_key(*txid))
+                .copied()
+                .collect()
+        } else {
+            private
+        };
+
+        for txid in &txids {
+            if is_ready(txid, &addr, &self.mempool, &self.propagation) {
+                outbox.insert(*txid);
+            } else {
+                held.insert(*txid);
+            }
         }
         self.schedule_tick();
         self.peers.insert(
@@ -257,8 +768,12 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
                 attempts: 0,
                 relay,
                 outbox,
+                held,
                 last_attempt: None,
                 requests: HashMap::with_hasher(self.rng.clone().into()),
+                latency: None,
+                block_time: None,
+                failures: 0,
             },
         );
     }
@@ -266,12 +781,58 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
     /// Called when a peer disconnected.
     pub fn peer_disconnected(&mut self, id: &PeerId) {
         self.peers.remove(id);
+
+        // Blocks requested from this peer will have to be requested from other peers.
+        let mut reassign = false;
+        for request in self.remaining.values_mut() {
+            if matches!(request, Some((addr, _)) if addr == id) {
+                *request = None;
+                reassign = true;
+            }
+        }
+        if reassign {
+            self.schedule_tick();
+        }
+
+        if let Some(monitor) = &mut self.monitor {
+            // Allow transactions requested from this peer to be requested from other peers.
+            let Monitor {
+                requested, seen, ..
+            } = monitor;
+
+            requested.retain(|txid, (addr, _)| {
+                if addr == id {
+                    seen.remove(txid);
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+
+    /// Called when a peer's latency was measured.
+    pub fn peer_latency(&mut self, addr: &PeerId, latency: LocalDuration) {
+        if let Some(peer) = self.peers.get_mut(addr) {
+            peer.latency = Some(latency);
+        }
     }
 
     /// Called when a block is reverted.
     pub fn block_reverted(&mut self, height: Height) -> Vec<Transaction> {
         self.estimator.rollback(height - 1);
 
+        for (outpoint, spend) in self.outpoints.iter_mut() {
+            if matches!(spend, Some((h, _)) if *h == height) {
+                if let Some((_, spending_tx)) = spend.take() {
+                    self.upstream.event(Event::OutpointReverted {
+                        outpoint: *outpoint,
+                        spending_tx,
+                    });
+                }
+            }
+        }
+
         if let Some(transactions) = self.confirmed.remove(&height) {
             for tx in transactions.iter().cloned() {
                 self.announce(tx);
@@ -299,27 +860,122 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
             let height = tree.height();
             self.confirmed
                 .retain(|h, _| height - h <= TRANSACTION_PRUNE_DEPTH);
+            self.outpoints.retain(|_, spend| {
+                spend
+                    .as_ref()
+                    .map_or(true, |(h, _)| height - h <= TRANSACTION_PRUNE_DEPTH)
+            });
         }
 
-        // Handle retries annd disconnects.
-        let mut requests = Vec::new();
-        let mut disconnect = Vec::new();
+        {
+            // Drop the transactions that didn't confirm in time. Transactions are considered
+            // submitted on the first tick after they were added to the mempool, unless their
+            // submission time is already known.
+            let mempool = &self.mempool;
+            self.submitted.retain(|txid, _| mempool.contains_key(txid));
+
+            for txid in self.mempool.keys() {
+                self.submitted.entry(*txid).or_insert(now);
+            }
+            let expiry = self.mempool_expiry;
+            let expired = self
+                .submitted
+                .iter()
+                .filter(|(_, submitted)| now - **submitted >= expiry)
+                .map(|(txid, _)| *txid)
+                .collect::<Vec<_>>();
+
+            for txid in expired {
+                self.expire(&txid);
+            }
+        }
 
-        for (addr, peer) in &mut *self.peers {
-            // TODO: Disconnect peers from which we requested blocks many times, and who haven't
-            // responded, or at least don't retry the same peer too many times.
+        {
+            // Announce the transactions held back for too long. The peer most likely got
+            // their parents from another peer, and won't request them from us.
+            let (mempool, submitted) = (&self.mempool, &self.submitted);
+
+            for peer in self.peers.values_mut() {
+                let ready = peer
+                    .held
+                    .iter()
+                    .filter(|txid| {
+                        mempool.get(*txid).map_or(true, |tx| {
+                            tx.input.iter().all(|input| {
+                                submitted
+                                    .get(&input.previous_output.txid)
+                                    .map_or(true, |time| now - *time >= HOLD_TIMEOUT)
+                            })
+                        })
+                    })
+                    .copied()
+                    .collect::<Vec<_>>();
+
+                for txid in ready {
+                    peer.held.remove(&txid);
+                    peer.outbox.insert(txid);
+                }
+            }
+        }
 
-            // Schedule inventory requests.
-            let queue = self
-                .remaining
-                .iter_mut()
-                .filter(|(_, t)| now - t.unwrap_or_default() >= REQUEST_TIMEOUT);
+        if let Some(monitor) = &mut self.monitor {
+            // Expire unanswered transaction requests, and transactions seen long ago.
+            let Monitor {
+                requested, seen, ..
+            } = monitor;
+
+            requested.retain(|txid, (_, first_seen)| {
+                if now - *first_seen >= REQUEST_TIMEOUT {
+                    seen.remove(txid);
+                    false
+                } else {
+                    true
+                }
+            });
+            seen.retain(|_, first_seen| now - *first_seen < SEEN_TRANSACTION_EXPIRY);
+        }
 
-            for (block_hash, last_request) in queue {
-                *last_request = Some(now);
-                requests.push(*block_hash);
+        // Re-announce the transactions that didn't propagate. Since these peers may have
+        // already received the transaction, we don't retry.
+        let mut reannounce = Vec::new();
+        for (txid, propagation) in self.propagation.iter_mut() {
+            // Privately broadcast transactions have their own timeout.
+            if self.private.contains_key(txid) || propagation.is_propagated() {
+                continue;
+            }
+            match propagation.announced {
+                Some(time) if now - time < PROPAGATION_TIMEOUT => {}
+                Some(_) => {
+                    propagation.announced = Some(now);
+                    reannounce.push(*txid);
+                }
+                None => {
+                    propagation.announced = Some(now);
+                }
+            }
+        }
+        if !reannounce.is_empty() {
+            log::debug!(
+                "Re-announcing {} transaction(s) that didn't propagate",
+                reannounce.len()
+            );
+            for (addr, peer) in self.peers.iter().filter(|(_, p)| p.relay) {
+                let invs = reannounce
+                    .iter()
+                    .filter(|txid| !peer.held.contains(*txid))
+                    .map(|txid| Inventory::Transaction(*txid))
+                    .collect::<Vec<_>>();
+
+                if !invs.is_empty() {
+                    self.upstream.inv(*addr, invs);
+                }
             }
+        }
+
+        // Handle retries annd disconnects.
+        let mut disconnect = Vec::new();
 
+        for (addr, peer) in &mut *self.peers {
             // Peer inventory announce timeout.
             if !peer.outbox.is_empty() {
                 let elapsed = now - peer.last_attempt.unwrap_or_default();
@@ -353,15 +1009,13 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
             self.upstream
                 .disconnect(addr, DisconnectReason::PeerTimeout("inv"));
         }
-        for block_hash in requests {
-            if let Some(_peer) = self.request(block_hash) {
-                // TODO: Fire event.
-            }
-        }
+        self.request_blocks(now, tree);
     }
 
     /// Called when a `getdata` is received from a peer.
     pub fn received_getdata(&mut self, addr: PeerId, invs: &[Inventory]) {
+        let mut acknowledged = false;
+
         for inv in invs {
             match inv {
                 // NOTE: Normally, we would handle non-witness inventory requests differently
@@ -373,6 +1027,11 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
 
                         self.upstream.tx(addr, tx.clone());
                     }
+                    let first = self
+                        .propagation
+                        .get_mut(txid)
+                        .map_or(false, |p| p.acknowledged.insert(addr));
+
                     // Since we received a `getdata` from the peer, it means it received our
                     // inventory broadcast and we no longer need to send it.
                     if let Some(peer) = self.peers.get_mut(&addr) {
@@ -387,6 +1046,10 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
                             txid: *txid,
                         });
                     }
+                    if first {
+                        acknowledged = true;
+                        self.propagated(txid);
+                    }
                 }
                 Inventory::WTx(_wtxid) => {
                     // TODO: This should be filled in as part of BIP 339 support.
@@ -394,6 +1057,82 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
                 _ => {}
             }
         }
+        // The peer may now have the parents of transactions we held back.
+        if acknowledged {
+            self.release();
+        }
+    }
+
+    /// Called when an `inv` is received from a peer. If mempool monitoring is enabled, requests
+    /// the announced transactions we haven't seen yet, subject to rate limits.
+    pub fn received_inv(&mut self, addr: PeerId, invs: &[Inventory], now: LocalTime) {
+        for inv in invs {
+            if let Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) = inv {
+                self.relayed(addr, txid);
+            }
+        }
+
+        let monitor = if let Some(monitor) = &mut self.monitor {
+            monitor
+        } else {
+            return;
+        };
+        if !self.peers.get(&addr).map_or(false, |p| p.relay) {
+            return;
+        }
+        let mut requests = Vec::new();
+
+        for inv in invs {
+            let txid = match inv {
+                Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => txid,
+                _ => continue,
+            };
+            if self.mempool.contains_key(txid) || monitor.seen.contains_key(txid) {
+                continue;
+            }
+            if !monitor.is_allowed(&addr, now) {
+                log::debug!("{}: Transaction request rate limit reached", addr);
+                break;
+            }
+            monitor.window_requests += 1;
+            monitor.seen.insert(*txid, now);
+            monitor.requested.insert(*txid, (addr, now));
+
+            requests.push(Inventory::WitnessTransaction(*txid));
+        }
+
+        if !requests.is_empty() {
+            self.upstream.getdata(addr, requests);
+        }
+    }
+
+    /// Called when a transaction is received from a peer. If the transaction was requested
+    /// and pays to one of the watched scripts, an [`Event::TxReceived`] event is emitted.
+    pub fn received_tx(&mut self, addr: PeerId, tx: Transaction, watch: &HashSet<Script>) {
+        let monitor = if let Some(monitor) = &mut self.monitor {
+            monitor
+        } else {
+            return;
+        };
+        let txid = tx.txid();
+
+        match monitor.requested.get(&txid) {
+            Some((from, first_seen)) if *from == addr => {
+                let first_seen = *first_seen;
+                monitor.requested.remove(&txid);
+
+                if tx.output.iter().any(|o| watch.contains(&o.script_pubkey)) {
+                    self.upstream.event(Event::TxReceived {
+                        transaction: tx,
+                        from: addr,
+                        first_seen,
+                    });
+                }
+            }
+            _ => {
+                log::debug!("{}: Ignoring unsolicited transaction {}", addr, txid);
+            }
+        }
     }
 
     /// Called when a block is received from a peer.
@@ -404,16 +1143,29 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
         &mut self,
         from: &PeerId,
         block: Block,
+        now: LocalTime,
         tree: &T,
     ) -> Vec<Txid> {
         let hash = block.block_hash();
         let from = *from;
 
-        if self.remaining.remove(&hash).is_none() {
+        let request = if let Some(request) = self.remaining.remove(&hash) {
+            self.attempts.remove(&hash);
+            request
+        } else {
             // Nb. The remote isn't necessarily sending an unsolicited block here.
             // We often have to ask multiple peers to get a response, so we may
             // have already received this block once.
             return vec![];
+        };
+
+        // Keep track of how long the peer took to deliver the block.
+        if let Some((addr, time)) = request {
+            if addr == from {
+                if let Some(peer) = self.peers.get_mut(&from) {
+                    peer.delivered(now - time);
+                }
+            }
         }
 
         // We're done requesting this block.
@@ -434,14 +1186,19 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
         self.received.insert(height, block);
         self.upstream.event(Event::BlockReceived { from, height });
 
-        // If there are still blocks remaining to download, don't process any of the
-        // received queue yet.
-        if !self.remaining.is_empty() {
-            return vec![];
+        // A download slot was freed, request the next block.
+        if self.remaining.values().any(Option::is_none) {
+            self.schedule_tick();
         }
 
-        // Now that all blocks to be processed are downloaded, we can start
-        // processing them in order.
+        // Process the received blocks below the lowest block still being downloaded,
+        // in order. This way, a slow download only holds back the blocks after it.
+        let next = self
+            .remaining
+            .keys()
+            .filter_map(|hash| tree.get_block(hash))
+            .map(|(height, _)| height)
+            .min();
         let mut confirmed = Vec::new();
 
         while let Some((height, block)) = self
@@ -449,6 +1206,7 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
             .keys()
             .min()
             .cloned()
+            .filter(|h| next.map_or(true, |n| *h < n))
             .and_then(|h| self.received.remove(&h).map(|b| (h, b)))
         {
             let hash = block.block_hash();
@@ -456,6 +1214,20 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
             for tx in &block.txdata {
                 let txid = tx.txid();
 
+                for input in &tx.input {
+                    let outpoint = input.previous_output;
+
+                    if let Some(spend @ None) = self.outpoints.get_mut(&outpoint) {
+                        *spend = Some((height, tx.clone()));
+
+                        self.upstream.event(Event::OutpointSpent {
+                            outpoint,
+                            spending_tx: tx.clone(),
+                            height,
+                        });
+                    }
+                }
+
                 // Attempt to remove confirmed transaction from mempool.
                 if let Some(transaction) = self.mempool.remove(&txid) {
                     confirmed.push(txid);
@@ -463,7 +1235,10 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
                     // Transactions that have been confirmed no longer need to be announced.
                     for peer in self.peers.values_mut() {
                         peer.outbox.remove(&txid);
+                        peer.held.remove(&txid);
                     }
+                    self.end_private(&txid);
+                    self.propagation.remove(&txid);
 
                     self.confirmed
                         .entry(height)
@@ -479,6 +1254,7 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
             }
             // Process block through fee estimator.
             if let Some(fees) = self.estimator.get_estimate(&block.txdata) {
+                self.fee_estimate = Some(fees.clone());
                 self.upstream.event(Event::FeeEstimated {
                     block: hash,
                     height,
@@ -487,6 +1263,10 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
             }
             self.upstream.event(Event::BlockProcessed { block, height });
         }
+        // Children of confirmed transactions no longer need to be held back.
+        if !confirmed.is_empty() {
+            self.release();
+        }
         confirmed
     }
 
@@ -497,53 +1277,436 @@ impl<U: Inventories + SetTimeout + Disconnect> InventoryManager<U> {
 
         // Insert transaction into the peer outboxes and keep a local copy for re-broadcasting later.
         let txid = tx.txid();
-        self.mempool.insert(txid, tx);
-
-        for (addr, peer) in self.peers.iter_mut().filter(|(_, p)| p.relay) {
-            peer.outbox.insert(txid);
+        self.mempool.insert(txid, tx.clone());
+        self.end_private(&txid);
+        let rng = self.rng.clone();
+        self.propagation
+            .entry(txid)
+            .or_insert_with(|| Propagation::new(rng));
+
+        let private = &self.private;
+        for (addr, peer) in self
+            .peers
+            .iter_mut()
+            .filter(|(_, p)| p.relay)
+            .filter(|(addr, _)| !private.values().any(|p| p.peers.contains(addr)))
+        {
+            peer.queue(
+                txid,
+                is_ready(&txid, addr, &self.mempool, &self.propagation),
+            );
             addrs.push(*addr);
         }
         self.schedule_tick();
+        self.upstream.event(Event::Announced {
+            transaction: tx,
+            broadcast: Broadcast::All,
+        });
 
         addrs
     }
 
-    /// Attempt to get a block from the network. Retries if necessary.
-    pub fn get_block(&mut self, hash: BlockHash) {
-        self.remaining.entry(hash).or_insert(None);
-        self.schedule_tick();
+    /// Replace a transaction in the mempool with a conflicting one, eg. to bump its fee.
+    /// The replaced transaction is no longer announced. Returns the peers the replacement
+    /// is announced to.
+    pub fn replace(&mut self, txid: &Txid, tx: Transaction) -> Vec<PeerId> {
+        if self.mempool.remove(txid).is_some() {
+            for peer in self.peers.values_mut() {
+                peer.outbox.remove(txid);
+                peer.held.remove(txid);
+            }
+            self.end_private(txid);
+            self.propagation.remove(txid);
+            self.upstream.event(Event::Replaced {
+                txid: *txid,
+                replaced_by: tx.txid(),
+            });
+        }
+        self.announce(tx)
     }
 
-    ////////////////////////////////////////////////////////////////////////////
+    /// Set the time at which a transaction in the mempool was submitted, eg. in a previous
+    /// session. Transactions expire [`Config::mempool_expiry`] after being submitted.
+    pub fn submitted(&mut self, txid: Txid, time: LocalTime) {
+        if self.mempool.contains_key(&txid) {
+            self.submitted.insert(txid, time);
+        }
+    }
 
-    fn schedule_tick(&mut self) {
-        self.last_tick = None; // Disable rate-limiting for the next tick.
-        self.upstream.set_timeout(LocalDuration::from_secs(1));
+    /// Drop a transaction from the mempool, without announcing it any further.
+    fn expire(&mut self, txid: &Txid) {
+        if self.mempool.remove(txid).is_some() {
+            for peer in self.peers.values_mut() {
+                peer.outbox.remove(txid);
+                peer.held.remove(txid);
+            }
+            self.end_private(txid);
+            self.propagation.remove(txid);
+            self.submitted.remove(txid);
+            self.upstream.event(Event::Expired { txid: *txid });
+        }
     }
 
-    /// Request a block from a random peer.
-    fn request(&self, block: BlockHash) -> Option<PeerId> {
-        self.peers
-            .sample_with(|_, p| p.services.has(ServiceFlags::NETWORK))
-            .map(|(addr, _)| {
-                log::debug!("Requesting block {} from {}", block, addr);
+    /// Announce a transaction to a single peer, and keep it from other peers until one of them
+    /// announces it back to us. If the peer isn't negotiated yet, the transaction is announced
+    /// once it is. Can be called again with a different peer, if the transaction didn't
+    /// propagate.
+    pub fn announce_privately(
+        &mut self,
+        tx: Transaction,
+        broadcast: Broadcast,
+        addr: PeerId,
+        now: LocalTime,
+    ) {
+        let txid = tx.txid();
+        let private = self.private.entry(txid).or_insert_with(|| Private {
+            broadcast,
+            peers: Vec::new(),
+            since: now,
+        });
+        private.peers.push(addr);
+        private.since = now;
+
+        let rng = self.rng.clone();
+        self.propagation
+            .entry(txid)
+            .or_insert_with(|| Propagation::new(rng));
+
+        if self.mempool.insert(txid, tx.clone()).is_none() {
+            self.upstream.event(Event::Announced {
+                transaction: tx,
+                broadcast,
+            });
+        }
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.queue(
+                txid,
+                is_ready(&txid, &addr, &self.mempool, &self.propagation),
+            );
+        }
+        self.schedule_tick();
+        self.upstream.set_timeout(PRIVATE_BROADCAST_TIMEOUT);
+    }
 
-                self.upstream.getdata(*addr, vec![Inventory::Block(block)]);
-                *addr
-            })
+    /// Keep a transaction to broadcast privately when there is no peer to send it to yet. It is
+    /// returned by [`InventoryManager::private_timeouts`] on the next tick, to be sent to a peer.
+    pub fn defer_private(&mut self, tx: Transaction, broadcast: Broadcast, now: LocalTime) {
+        let txid = tx.txid();
+        self.private.entry(txid).or_insert_with(|| Private {
+            broadcast,
+            peers: Vec::new(),
+            since: now - PRIVATE_BROADCAST_TIMEOUT,
+        });
+
+        let rng = self.rng.clone();
+        self.propagation
+            .entry(txid)
+            .or_insert_with(|| Propagation::new(rng));
+
+        if self.mempool.insert(txid, tx.clone()).is_none() {
+            self.upstream.event(Event::Announced {
+                transaction: tx,
+                broadcast,
+            });
+        }
+        self.schedule_tick();
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Get the privately broadcast transactions that weren't relayed back to us within
+    /// [`PRIVATE_BROADCAST_TIMEOUT`], along with how they are broadcast and the peers they were
+    /// already sent to. These should be sent to a different peer. Short-lived connections
+    /// opened for these transactions are closed.
+    pub fn private_timeouts(
+        &mut self,
+        now: LocalTime,
+    ) -> Vec<(Transaction, Broadcast, Vec<PeerId>)> {
+        let mut timeouts = Vec::new();
+        let mut disconnect = Vec::new();
+
+        for (txid, private) in self.private.iter_mut() {
+            if now - private.since < PRIVATE_BROADCAST_TIMEOUT {
+                continue;
+            }
+            private.since = now;
+
+            if private.broadcast == Broadcast::Ephemeral {
+                disconnect.extend(private.peers.iter().copied());
+            }
+            if let Some(tx) = self.mempool.get(txid) {
+                timeouts.push((tx.clone(), private.broadcast, private.peers.clone()));
+            }
+        }
+        for addr in disconnect {
+            if self.peers.remove(&addr).is_some() {
+                self.upstream
+                    .disconnect(addr, DisconnectReason::Other("ephemeral connection closed"));
+            }
+        }
+        timeouts
+    }
+
+    /// Watch an outpoint, and emit [`Event::OutpointSpent`] when it is spent in a processed
+    /// block. Note that only blocks that are fetched are checked.
+    pub fn watch_outpoint(&mut self, outpoint: OutPoint) {
+        self.outpoints.entry(outpoint).or_insert(None);
+    }
+
+    /// Attempt to get a block from the network. Retries if necessary, and emits
+    /// [`Event::BlockFailed`] if the block wasn't received after [`MAX_ATTEMPTS`] requests.
+    pub fn get_block(&mut self, hash: BlockHash) {
+        self.remaining.entry(hash).or_insert(None);
+        self.attempts.remove(&hash);
+        self.schedule_tick();
+    }
+
+    /// Stop requesting a block, eg. because it turned out to be invalid.
+    pub fn cancel_block(&mut self, hash: &BlockHash) {
+        self.remaining.remove(hash);
+        self.attempts.remove(hash);
+
+        for peer in self.peers.values_mut() {
+            peer.requests.remove(hash);
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+
+    /// Called when a peer announces one of our transactions. If the peer didn't get it from us,
+    /// the transaction has propagated, and the peer no longer needs to be told about it.
+    fn relayed(&mut self, addr: PeerId, txid: &Txid) {
+        let propagation = if let Some(propagation) = self.propagation.get_mut(txid) {
+            propagation
+        } else {
+            return;
+        };
+        if propagation.acknowledged.contains(&addr)
+            || self
+                .private
+                .get(txid)
+                .map_or(false, |p| p.peers.contains(&addr))
+        {
+            return;
+        }
+        if !propagation.relayed.insert(addr) {
+            return;
+        }
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.outbox.remove(txid);
+
+            if peer.outbox.is_empty() {
+                peer.reset();
+            }
+        }
+        self.end_private(txid);
+        self.upstream.event(Event::Relayed {
+            txid: *txid,
+            peer: addr,
+        });
+        self.propagated(txid);
+        // The peer has this transaction, so its children may no longer need to be held back.
+        self.release();
+    }
+
+    /// Called when another peer is known to have one of our transactions.
+    fn propagated(&mut self, txid: &Txid) {
+        if let Some(propagation) = self.propagation.get(txid) {
+            if propagation.is_propagated() {
+                self.upstream.event(Event::Propagated {
+                    txid: *txid,
+                    peers: propagation.peers(),
+                });
+            }
+        }
+    }
+
+    /// Send the held back transactions whose parents are now known to the peer.
+    fn release(&mut self) {
+        let (mempool, propagation) = (&self.mempool, &self.propagation);
+        let mut released = false;
+
+        for (addr, peer) in self.peers.iter_mut() {
+            peer.held.retain(|txid| mempool.contains_key(txid));
+
+            let ready = peer
+                .held
+                .iter()
+                .filter(|txid| is_ready(txid, addr, mempool, propagation))
+                .copied()
+                .collect::<Vec<_>>();
+
+            for txid in ready {
+                peer.held.remove(&txid);
+                peer.outbox.insert(txid);
+                released = true;
+            }
+        }
+        if released {
+            self.schedule_tick();
+        }
+    }
+
+    /// Stop broadcasting a transaction privately. Short-lived connections opened for it
+    /// are closed.
+    fn end_private(&mut self, txid: &Txid) {
+        if let Some(private) = self.private.remove(txid) {
+            if private.broadcast != Broadcast::Ephemeral {
+                return;
+            }
+            for addr in private.peers {
+                if self.peers.remove(&addr).is_some() {
+                    self.upstream
+                        .disconnect(addr, DisconnectReason::Other("ephemeral connection closed"));
+                }
+            }
+        }
+    }
+
+    fn schedule_tick(&mut self) {
+        self.last_tick = None; // Disable rate-limiting for the next tick.
+        self.upstream.set_timeout(LocalDuration::from_secs(1));
+    }
+
+    /// Schedule block requests across peers. The remaining blocks with the lowest heights are
+    /// requested first, each from the peer expected to deliver it soonest, while respecting the
+    /// global and per-peer in-flight limits, and the download window. Requests that timed out count as a failure against
+    /// the peer and are re-assigned to a different peer, and peers that fail repeatedly are
+    /// disconnected.
+    fn request_blocks<T: BlockTree>(&mut self, now: LocalTime, tree: &T) {
+        // Expire the requests that timed out.
+        let mut timed_out = HashMap::with_hasher(self.rng.clone().into());
+        for (hash, request) in self.remaining.iter_mut() {
+            if let Some((addr, time)) = *request {
+                if now - time >= REQUEST_TIMEOUT {
+                    timed_out.insert(*hash, addr);
+                    *request = None;
+                }
+            }
+        }
+
+        for hash in timed_out.keys() {
+            let attempts = self.attempts.entry(*hash).or_default();
+            *attempts += 1;
+
+            if *attempts == MAX_ATTEMPTS {
+                self.upstream.event(Event::BlockFailed {
+                    hash: *hash,
+                    attempts: *attempts,
+                });
+            }
+        }
+
+        // Peers failing to deliver are only counted once per round, no matter the number of
+        // blocks they failed to deliver.
+        let failed = timed_out.values().copied().collect::<HashSet<_>>();
+        for addr in failed {
+            let failures = if let Some(peer) = self.peers.get_mut(&addr) {
+                peer.failures += 1;
+                peer.failures
+            } else {
+                continue;
+            };
+            if failures >= MAX_BLOCK_REQUEST_FAILURES {
+                self.peer_disconnected(&addr);
+                self.upstream
+                    .disconnect(addr, DisconnectReason::PeerTimeout("getdata"));
+            }
+        }
+
+        let mut in_flight: HashMap<PeerId, usize> = HashMap::with_hasher(self.rng.clone().into());
+        for (addr, _) in self.remaining.values().flatten() {
+            *in_flight.entry(*addr).or_default() += 1;
+        }
+        let mut queue = self
+            .remaining
+            .iter()
+            .filter(|(_, r)| r.is_none())
+            .map(|(hash, _)| {
+                // Blocks not on the active chain, if any, are requested last.
+                let height = tree
+                    .get_block(hash)
+                    .map_or(Height::MAX, |(height, _)| height);
+
+                (height, *hash)
+            })
+            .collect::<Vec<_>>();
+
+        queue.sort_unstable();
+
+        // Blocks are processed in order, so blocks received past the lowest block still being
+        // downloaded are held until it arrives. Only request blocks within the download window
+        // of that block, so that a slow download can't cause an unbounded number of blocks
+        // to be held.
+        let lowest = self
+            .remaining
+            .keys()
+            .filter_map(|hash| tree.get_block(hash))
+            .map(|(height, _)| height)
+            .min();
+        if let Some(lowest) = lowest {
+            let end = lowest.saturating_add(self.block_download_window);
+            queue.retain(|(height, _)| *height < end);
+        }
+
+        let total = in_flight.values().sum::<usize>();
+        let mut requests: BTreeMap<PeerId, Vec<Inventory>> = BTreeMap::new();
+
+        for (_, hash) in queue
+            .into_iter()
+            .take(self.max_blocks_in_flight.saturating_sub(total))
+        {
+            let avoid = timed_out.get(&hash);
+            let candidates = self.peers.iter().filter(|(addr, peer)| {
+                peer.services.has(ServiceFlags::NETWORK)
+                    && in_flight.get(*addr).copied().unwrap_or_default()
+                        < self.max_blocks_in_flight_per_peer
+            });
+            // Weigh each peer by how long it would take to deliver all of its blocks, including
+            // this one.
+            let score = |(addr, peer): &(&PeerId, &Peer)| {
+                let load = in_flight.get(*addr).copied().unwrap_or_default() as u64 + 1;
+                (peer.cost() * load).as_millis()
+            };
+            let best = candidates
+                .clone()
+                .filter(|(addr, _)| Some(*addr) != avoid)
+                .min_by_key(score)
+                .or_else(|| candidates.min_by_key(score))
+                .map(|(addr, _)| *addr);
+
+            let addr = match best {
+                Some(addr) => addr,
+                // All peers are busy.
+                None => break,
+            };
+            *in_flight.entry(addr).or_default() += 1;
+
+            self.remaining.insert(hash, Some((addr, now)));
+            requests
+                .entry(addr)
+                .or_default()
+                .push(Inventory::Block(hash));
+        }
+
+        for (addr, invs) in requests {
+            log::debug!("Requesting {} block(s) from {}", invs.len(), addr);
+
+            self.upstream.getdata(addr, invs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
 
     use crate::bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
     use crate::protocol;
     use crate::protocol::channel::{chan, Channel};
-    use crate::protocol::{Network, Out, PROTOCOL_VERSION};
+    use crate::protocol::{Out, PROTOCOL_VERSION};
+    use nakamoto_common::network::Network;
 
     use nakamoto_common::nonempty::NonEmpty;
+    use nakamoto_test::assert_matches;
     use nakamoto_test::block::cache::model;
     use nakamoto_test::block::gen;
     use nakamoto_test::logger;
@@ -570,7 +1733,7 @@ mod tests {
 
         let network = Network::Regtest;
         let (sender, receiver) = chan::unbounded::<Out>();
-        let upstream = Channel::new(network, PROTOCOL_VERSION, "test", sender);
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
 
         let mut rng = fastrand::Rng::new();
         let mut time = LocalTime::now();
@@ -584,7 +1747,7 @@ mod tests {
         let inv = vec![Inventory::Block(hash)];
         let block = chain.iter().find(|b| b.block_hash() == hash).unwrap();
 
-        let mut invmgr = InventoryManager::new(rng.clone(), upstream);
+        let mut invmgr = InventoryManager::new(Config::default(), rng.clone(), upstream);
 
         invmgr.peer_negotiated(([66, 66, 66, 66], 8333).into(), ServiceFlags::NETWORK, true);
         invmgr.peer_negotiated(([77, 77, 77, 77], 8333).into(), ServiceFlags::NETWORK, true);
@@ -615,7 +1778,7 @@ mod tests {
                     // We're not done until we've requested all peers.
                     continue;
                 }
-                invmgr.received_block(&addr, block.clone(), &tree);
+                invmgr.received_block(&addr, block.clone(), time, &tree);
 
                 assert!(invmgr.remaining.is_empty(), "No more blocks to remaining");
                 events(&receiver)
@@ -629,11 +1792,311 @@ mod tests {
         assert_eq!(messages(&receiver).count(), 0, "No more requests are sent");
     }
 
+    #[test]
+    fn test_get_block_failed() {
+        let network = Network::Regtest;
+        let (sender, receiver) = chan::unbounded::<Out>();
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
+
+        let mut rng = fastrand::Rng::new();
+        let mut time = LocalTime::now();
+
+        let chain = gen::blockchain(network.genesis_block(), 16, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let tree = model::Cache::from(headers);
+        let block = &chain[6];
+        let hash = block.block_hash();
+
+        let mut invmgr = InventoryManager::new(Config::default(), rng, upstream);
+        let remote: PeerId = ([66, 66, 66, 66], 8333).into();
+
+        invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
+        invmgr.peer_negotiated(([77, 77, 77, 77], 8333).into(), ServiceFlags::NETWORK, true);
+        invmgr.get_block(hash);
+        invmgr.received_tick(time, &tree);
+
+        for _ in 1..MAX_ATTEMPTS {
+            time.elapse(REQUEST_TIMEOUT);
+            invmgr.received_tick(time, &tree);
+
+            assert!(
+                !events(&receiver).any(|e| matches!(e, Event::BlockFailed { .. })),
+                "No failure is reported before the maximum number of attempts"
+            );
+        }
+        time.elapse(REQUEST_TIMEOUT);
+        invmgr.received_tick(time, &tree);
+
+        assert_matches!(
+            events(&receiver).find(|e| matches!(e, Event::BlockFailed { .. })),
+            Some(Event::BlockFailed { hash: h, attempts }) if h == hash && attempts == MAX_ATTEMPTS
+        );
+        assert!(
+            invmgr.remaining.contains_key(&hash),
+            "The block is still requested after a failure"
+        );
+
+        time.elapse(REQUEST_TIMEOUT);
+        invmgr.received_tick(time, &tree);
+        assert!(
+            !events(&receiver).any(|e| matches!(e, Event::BlockFailed { .. })),
+            "Failures are only reported once"
+        );
+
+        invmgr.received_block(&remote, block.clone(), time, &tree);
+        assert!(invmgr.remaining.is_empty());
+        assert!(invmgr.attempts.is_empty());
+    }
+
+    #[test]
+    fn test_watch_outpoint() {
+        let network = Network::Regtest;
+        let (sender, receiver) = chan::unbounded::<Out>();
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
+
+        let mut rng = fastrand::Rng::new();
+        let time = LocalTime::now();
+
+        let spending = gen::transaction(&mut rng);
+        let outpoint = spending.input[0].previous_output;
+        let mut chain = vec![network.genesis_block()];
+
+        for height in 1..=4 {
+            let mut txdata = vec![gen::coinbase(&mut rng)];
+            if height == 3 {
+                txdata.push(spending.clone());
+            }
+            let block = gen::block_with(&chain.last().unwrap().header, txdata, &mut rng);
+            chain.push(block);
+        }
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let tree = model::Cache::from(headers);
+        let remote: PeerId = ([66, 66, 66, 66], 8333).into();
+
+        let mut invmgr = InventoryManager::new(Config::default(), rng, upstream);
+        invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
+        invmgr.watch_outpoint(outpoint);
+
+        for block in chain.iter().skip(1) {
+            invmgr.get_block(block.block_hash());
+            invmgr.received_block(&remote, block.clone(), time, &tree);
+        }
+        assert_matches!(
+            events(&receiver).find(|e| matches!(e, Event::OutpointSpent { .. })),
+            Some(Event::OutpointSpent { outpoint: o, spending_tx, height: 3 })
+            if o == outpoint && spending_tx == spending
+        );
+
+        // Processing the same block again doesn't report the spend twice.
+        invmgr.get_block(chain[3].block_hash());
+        invmgr.received_block(&remote, chain[3].clone(), time, &tree);
+        assert!(!events(&receiver).any(|e| matches!(e, Event::OutpointSpent { .. })));
+
+        // Reverting the spending block makes the outpoint unspent again.
+        invmgr.block_reverted(4);
+        assert!(!events(&receiver).any(|e| matches!(e, Event::OutpointReverted { .. })));
+        invmgr.block_reverted(3);
+        assert_matches!(
+            events(&receiver).find(|e| matches!(e, Event::OutpointReverted { .. })),
+            Some(Event::OutpointReverted { outpoint: o, spending_tx })
+            if o == outpoint && spending_tx == spending
+        );
+
+        // The outpoint is spent again in the new chain.
+        invmgr.get_block(chain[3].block_hash());
+        invmgr.received_block(&remote, chain[3].clone(), time, &tree);
+        assert_matches!(
+            events(&receiver).find(|e| matches!(e, Event::OutpointSpent { .. })),
+            Some(Event::OutpointSpent { outpoint: o, height: 3, .. }) if o == outpoint
+        );
+    }
+
+    #[test]
+    fn test_block_download_window() {
+        let network = Network::Regtest;
+        let (sender, receiver) = chan::unbounded::<Out>();
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
+
+        let mut rng = fastrand::Rng::new();
+        let mut time = LocalTime::now();
+
+        let chain = gen::blockchain(network.genesis_block(), 16, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let tree = model::Cache::from(headers);
+        let config = Config {
+            max_blocks_in_flight: 4,
+            block_download_window: 4,
+            ..Config::default()
+        };
+        let mut invmgr = InventoryManager::new(config, rng.clone(), upstream);
+
+        invmgr.peer_negotiated(([66, 66, 66, 66], 8333).into(), ServiceFlags::NETWORK, true);
+        invmgr.peer_negotiated(([77, 77, 77, 77], 8333).into(), ServiceFlags::NETWORK, true);
+
+        for block in chain.iter().skip(1).take(10) {
+            invmgr.get_block(block.block_hash());
+        }
+        invmgr.received_tick(time, &tree);
+
+        let requested = |receiver: &chan::Receiver<Out>| {
+            let tree = &tree;
+
+            messages(receiver)
+                .filter_map(|(addr, msg)| match msg {
+                    NetworkMessage::GetData(invs) => Some((addr, invs)),
+                    _ => None,
+                })
+                .flat_map(|(addr, invs)| {
+                    invs.into_iter().filter_map(move |inv| match inv {
+                        Inventory::Block(hash) => Some((tree.get_block(&hash).unwrap().0, addr)),
+                        _ => None,
+                    })
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        // Only the lowest blocks are requested, up to the limit.
+        let first = requested(&receiver);
+        assert_eq!(first.keys().copied().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+
+        // Blocks received out of order are held back until the blocks before them arrive.
+        invmgr.received_block(&first[&2], chain[2].clone(), time, &tree);
+        assert!(!events(&receiver).any(|e| matches!(e, Event::BlockProcessed { .. })));
+
+        invmgr.received_block(&first[&1], chain[1].clone(), time, &tree);
+        assert_eq!(
+            events(&receiver)
+                .filter_map(|e| match e {
+                    Event::BlockProcessed { height, .. } => Some(height),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            vec![1, 2],
+            "The contiguous blocks are processed without waiting for the others"
+        );
+
+        // The freed slots are used for the next blocks.
+        time.elapse(LocalDuration::from_secs(1));
+        invmgr.received_tick(time, &tree);
+        assert_eq!(
+            requested(&receiver).keys().copied().collect::<Vec<_>>(),
+            vec![5, 6]
+        );
+
+        // Requests that time out are re-assigned to the other peer.
+        time.elapse(REQUEST_TIMEOUT);
+        invmgr.received_tick(time, &tree);
+
+        let retried = requested(&receiver);
+        assert_eq!(
+            retried.keys().copied().collect::<Vec<_>>(),
+            vec![3, 4, 5, 6]
+        );
+        assert_ne!(retried[&3], first[&3]);
+        assert_ne!(retried[&4], first[&4]);
+
+        // While the lowest block is outstanding, blocks past the download window aren't
+        // requested, even if there are free slots.
+        for height in 4..=6 {
+            invmgr.received_block(
+                &retried[&height],
+                chain[height as usize].clone(),
+                time,
+                &tree,
+            );
+        }
+        assert_eq!(invmgr.received.len(), 3);
+
+        time.elapse(LocalDuration::from_secs(1));
+        invmgr.received_tick(time, &tree);
+        assert!(requested(&receiver).is_empty());
+
+        // Once it arrives, the window moves forward.
+        invmgr.received_block(&retried[&3], chain[3].clone(), time, &tree);
+        assert!(invmgr.received.is_empty());
+
+        time.elapse(LocalDuration::from_secs(1));
+        invmgr.received_tick(time, &tree);
+        assert_eq!(
+            requested(&receiver).keys().copied().collect::<Vec<_>>(),
+            vec![7, 8, 9, 10]
+        );
+    }
+
+    #[test]
+    fn test_block_scheduler() {
+        let network = Network::Regtest;
+        let (sender, receiver) = chan::unbounded::<Out>();
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
+
+        let mut rng = fastrand::Rng::new();
+        let mut time = LocalTime::now();
+
+        let chain = gen::blockchain(network.genesis_block(), 16, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let tree = model::Cache::from(headers);
+        let config = Config {
+            max_blocks_in_flight: 6,
+            max_blocks_in_flight_per_peer: 4,
+            ..Config::default()
+        };
+        let mut invmgr = InventoryManager::new(config, rng.clone(), upstream);
+
+        let fast: PeerId = ([66, 66, 66, 66], 8333).into();
+        let slow: PeerId = ([77, 77, 77, 77], 8333).into();
+        let limited: PeerId = ([88, 88, 88, 88], 8333).into();
+
+        invmgr.peer_negotiated(fast, ServiceFlags::NETWORK, true);
+        invmgr.peer_negotiated(slow, ServiceFlags::NETWORK, true);
+        invmgr.peer_negotiated(limited, ServiceFlags::NONE, true);
+        invmgr.peer_latency(&fast, LocalDuration::from_millis(10));
+        invmgr.peer_latency(&slow, LocalDuration::from_millis(500));
+
+        for block in chain.iter().skip(1) {
+            invmgr.get_block(block.block_hash());
+        }
+        invmgr.received_tick(time, &tree);
+
+        let requested = |receiver: &chan::Receiver<Out>| {
+            let mut requested = HashMap::with_hasher(rng.clone().into());
+            for (addr, msg) in messages(receiver) {
+                if let NetworkMessage::GetData(invs) = msg {
+                    *requested.entry(addr).or_insert(0) += invs.len();
+                }
+            }
+            requested
+        };
+
+        // The faster peer is sent as many requests as it can take, the slower peer gets the
+        // rest, and peers without the block service aren't used.
+        let first = requested(&receiver);
+        assert_eq!(first.get(&fast), Some(&4));
+        assert_eq!(first.get(&slow), Some(&2));
+        assert_eq!(first.get(&limited), None);
+
+        // Peers that repeatedly fail to deliver blocks are disconnected.
+        for _ in 0..MAX_BLOCK_REQUEST_FAILURES {
+            time.elapse(REQUEST_TIMEOUT);
+            invmgr.received_tick(time, &tree);
+        }
+        let disconnected = receiver
+            .try_iter()
+            .filter_map(|o| match o {
+                Out::Disconnect(addr, _) => Some(addr),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        assert!(disconnected.contains(&fast));
+        assert!(disconnected.contains(&slow));
+        assert!(invmgr.remaining.values().all(Option::is_none));
+    }
+
     #[test]
     fn test_rebroadcast_timeout() {
         let network = Network::Mainnet;
         let (sender, receiver) = chan::unbounded::<Out>();
-        let upstream = Channel::new(network, PROTOCOL_VERSION, "test", sender);
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
         let tree = model::Cache::from(NonEmpty::new(network.genesis()));
         let remote = ([88, 88, 88, 88], 8333).into();
         let mut rng = fastrand::Rng::with_seed(1);
@@ -641,7 +2104,7 @@ mod tests {
         let time = LocalTime::now();
         let tx = gen::transaction(&mut rng);
 
-        let mut invmgr = InventoryManager::new(rng, upstream);
+        let mut invmgr = InventoryManager::new(Config::default(), rng, upstream);
 
         invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
         invmgr.announce(tx);
@@ -670,7 +2133,7 @@ mod tests {
     fn test_max_attemps() {
         let network = Network::Mainnet;
         let (sender, receiver) = chan::unbounded::<Out>();
-        let upstream = Channel::new(network, PROTOCOL_VERSION, "test", sender);
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
         let tree = model::Cache::from(NonEmpty::new(network.genesis()));
 
         let mut rng = fastrand::Rng::with_seed(1);
@@ -679,7 +2142,7 @@ mod tests {
         let remote = ([88, 88, 88, 88], 8333).into();
         let tx = gen::transaction(&mut rng);
 
-        let mut invmgr = InventoryManager::new(rng, upstream);
+        let mut invmgr = InventoryManager::new(Config::default(), rng, upstream);
 
         invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
         invmgr.announce(tx.clone());
@@ -717,6 +2180,250 @@ mod tests {
         assert!(invmgr.peers.is_empty());
     }
 
+    #[test]
+    fn test_replace() {
+        let network = Network::Mainnet;
+        let (sender, receiver) = chan::unbounded::<Out>();
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
+        let tree = model::Cache::from(NonEmpty::new(network.genesis()));
+        let remote = ([88, 88, 88, 88], 8333).into();
+        let mut rng = fastrand::Rng::with_seed(1);
+
+        let time = LocalTime::now();
+        let tx = gen::transaction(&mut rng);
+        let replacement = gen::transaction(&mut rng);
+
+        let mut invmgr = InventoryManager::new(Config::default(), rng, upstream);
+
+        invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
+        invmgr.announce(tx.clone());
+
+        assert_eq!(
+            invmgr.replace(&tx.txid(), replacement.clone()),
+            vec![remote]
+        );
+        assert!(!invmgr.contains(&tx.txid()));
+        assert!(invmgr.contains(&replacement.txid()));
+
+        events(&receiver)
+            .find(|e| {
+                matches! {
+                    e, Event::Replaced { txid, replaced_by }
+                    if txid == &tx.txid() && replaced_by == &replacement.txid()
+                }
+            })
+            .expect("An event is emitted when a transaction is replaced");
+
+        invmgr.received_tick(time, &tree);
+        assert_eq!(
+            messages(&receiver)
+                .filter_map(|(_, m)| match m {
+                    NetworkMessage::Inv(invs) => Some(invs),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            vec![vec![Inventory::Transaction(replacement.txid())]],
+            "Only the replacement is announced"
+        );
+    }
+
+    #[test]
+    fn test_private_broadcast_peer() {
+        let network = Network::Mainnet;
+        let (sender, receiver) = chan::unbounded::<Out>();
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
+        let tree = model::Cache::from(NonEmpty::new(network.genesis()));
+        let remote = ([88, 88, 88, 88], 8333).into();
+        let ephemeral = ([99, 99, 99, 99], 8333).into();
+        let mut rng = fastrand::Rng::with_seed(1);
+
+        let time = LocalTime::now();
+        let tx = gen::transaction(&mut rng);
+        let private = gen::transaction(&mut rng);
+        let other = gen::transaction(&mut rng);
+
+        let mut invmgr = InventoryManager::new(Config::default(), rng, upstream);
+
+        invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
+        invmgr.announce(tx.clone());
+        invmgr.announce_privately(private.clone(), Broadcast::Ephemeral, ephemeral, time);
+        invmgr.peer_negotiated(ephemeral, ServiceFlags::NETWORK, true);
+        invmgr.announce(other.clone());
+        invmgr.received_tick(time, &tree);
+
+        let mut invs = messages(&receiver)
+            .filter_map(|(addr, m)| match m {
+                NetworkMessage::Inv(invs) => Some((addr, invs)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        invs.sort_by_key(|(addr, _)| *addr == ephemeral);
+
+        assert_eq!(invs.len(), 2);
+        assert_eq!(invs[0].0, remote);
+        assert!(!invs[0].1.contains(&Inventory::Transaction(private.txid())));
+        assert_eq!(
+            invs[1],
+            (ephemeral, vec![Inventory::Transaction(private.txid())]),
+            "The peer opened for the private broadcast only receives that transaction"
+        );
+    }
+
+    #[test]
+    fn test_defer_private() {
+        let network = Network::Mainnet;
+        let (sender, _receiver) = chan::unbounded::<Out>();
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
+        let remote = ([88, 88, 88, 88], 8333).into();
+        let mut rng = fastrand::Rng::with_seed(1);
+
+        let time = LocalTime::now();
+        let tx = gen::transaction(&mut rng);
+
+        let mut invmgr = InventoryManager::new(Config::default(), rng, upstream);
+
+        invmgr.defer_private(tx.clone(), Broadcast::Single, time);
+        invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
+
+        assert!(invmgr.contains(&tx.txid()));
+        assert!(
+            invmgr.peers[&remote].outbox.is_empty(),
+            "The transaction isn't announced to all peers"
+        );
+        assert_eq!(
+            invmgr.private_timeouts(time),
+            vec![(tx, Broadcast::Single, vec![])],
+            "The transaction is due to be sent to a peer"
+        );
+    }
+
+    #[test]
+    fn test_mempool_expiry() {
+        let network = Network::Mainnet;
+        let (sender, receiver) = chan::unbounded::<Out>();
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
+        let tree = model::Cache::from(NonEmpty::new(network.genesis()));
+        let remote = ([88, 88, 88, 88], 8333).into();
+        let mut rng = fastrand::Rng::with_seed(1);
+
+        let time = LocalTime::now();
+        let tx = gen::transaction(&mut rng);
+        let resubmitted = gen::transaction(&mut rng);
+
+        let mut invmgr = InventoryManager::new(Config::default(), rng, upstream);
+
+        invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
+        invmgr.announce(tx.clone());
+        invmgr.received_tick(time, &tree);
+
+        // A transaction submitted in a previous session keeps its submission time.
+        invmgr.announce(resubmitted.clone());
+        invmgr.submitted(resubmitted.txid(), time - LocalDuration::from_mins(60));
+        invmgr.received_tick(time + MEMPOOL_EXPIRY - LocalDuration::from_mins(30), &tree);
+
+        assert!(invmgr.contains(&tx.txid()));
+        assert!(!invmgr.contains(&resubmitted.txid()));
+
+        invmgr.received_tick(time + MEMPOOL_EXPIRY, &tree);
+        assert!(!invmgr.contains(&tx.txid()));
+        assert!(invmgr.peers.values().all(|p| p.outbox.is_empty()));
+
+        assert_eq!(
+            events(&receiver)
+                .filter_map(|e| match e {
+                    Event::Expired { txid } => Some(txid),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            vec![resubmitted.txid(), tx.txid()]
+        );
+    }
+
+    #[test]
+    fn test_mempool_monitoring() {
+        let network = Network::Mainnet;
+        let (sender, receiver) = chan::unbounded::<Out>();
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
+        let relay = ([88, 88, 88, 88], 8333).into();
+        let non_relay = ([99, 99, 99, 99], 8333).into();
+        let mut rng = fastrand::Rng::with_seed(1);
+        let mut time = LocalTime::now();
+
+        let config = Config {
+            mempool: Some(MempoolConfig {
+                max_requests_per_minute: 2,
+                max_requests_in_flight: 2,
+            }),
+            ..Config::default()
+        };
+        let payment = gen::transaction(&mut rng);
+        let other = gen::transaction(&mut rng);
+        let limited = gen::transaction(&mut rng);
+        let mut watch = HashSet::with_hasher(rng.clone().into());
+        watch.insert(payment.output[0].script_pubkey.clone());
+
+        let mut invmgr = InventoryManager::new(config, rng, upstream);
+
+        invmgr.peer_negotiated(relay, ServiceFlags::NETWORK, true);
+        invmgr.peer_negotiated(non_relay, ServiceFlags::NETWORK, false);
+
+        let invs = vec![
+            Inventory::Transaction(payment.txid()),
+            Inventory::Transaction(other.txid()),
+            Inventory::Transaction(limited.txid()),
+        ];
+
+        invmgr.received_inv(non_relay, &invs, time);
+        assert_eq!(
+            messages(&receiver).count(),
+            0,
+            "Non-relay peers are ignored"
+        );
+
+        invmgr.received_inv(relay, &invs, time);
+        assert_eq!(
+            messages(&receiver).collect::<Vec<_>>(),
+            vec![(
+                relay,
+                NetworkMessage::GetData(vec![
+                    Inventory::WitnessTransaction(payment.txid()),
+                    Inventory::WitnessTransaction(other.txid()),
+                ])
+            )],
+            "Transaction requests are rate-limited"
+        );
+
+        // Unsolicited transactions are ignored.
+        invmgr.received_tx(non_relay, payment.clone(), &watch);
+        assert_eq!(events(&receiver).count(), 0);
+
+        invmgr.received_tx(relay, other, &watch);
+        assert_eq!(
+            events(&receiver).count(),
+            0,
+            "Only watched transactions are reported"
+        );
+
+        time.elapse(LocalDuration::from_secs(10));
+        invmgr.received_tx(relay, payment.clone(), &watch);
+        assert_matches!(
+            events(&receiver).next(),
+            Some(Event::TxReceived { transaction, from, first_seen })
+            if transaction == payment && from == relay && first_seen == time - LocalDuration::from_secs(10)
+        );
+
+        // Transactions are not requested twice.
+        time.elapse(LocalDuration::from_mins(1));
+        invmgr.received_inv(relay, &invs, time);
+        assert_eq!(
+            messages(&receiver).collect::<Vec<_>>(),
+            vec![(
+                relay,
+                NetworkMessage::GetData(vec![Inventory::WitnessTransaction(limited.txid())])
+            )],
+        );
+    }
+
     #[test]
     fn test_block_reverted() {
         let network = Network::Regtest;
@@ -737,16 +2444,16 @@ mod tests {
         let fork_block1 = gen::block_with(&tip, vec![tx.clone()], &mut rng);
         let fork_block2 = gen::block(&fork_block1.header, &mut rng);
 
-        let upstream = Channel::new(network, PROTOCOL_VERSION, "test", sender);
+        let upstream = Channel::new(network.magic(), PROTOCOL_VERSION, "test", sender);
         let time = LocalTime::now();
 
         let mut tree = model::Cache::from(headers);
-        let mut invmgr = InventoryManager::new(rng, upstream);
+        let mut invmgr = InventoryManager::new(Config::default(), rng, upstream);
 
         invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
         invmgr.announce(tx.clone());
         invmgr.get_block(main_block1.block_hash());
-        invmgr.received_block(&remote, main_block1, &tree);
+        invmgr.received_block(&remote, main_block1, time, &tree);
 
         assert!(!invmgr.contains(&tx.txid()));
 
@@ -780,7 +2487,7 @@ mod tests {
             .unwrap();
 
         invmgr.get_block(fork_block1.block_hash());
-        invmgr.received_block(&remote, fork_block1.clone(), &tree);
+        invmgr.received_block(&remote, fork_block1.clone(), time, &tree);
 
         events
             .find(|e| {
@@ -148,6 +148,8 @@ pub struct Config {
     pub max_inbound_peers: usize,
     /// Our user agent.
     pub user_agent: &'static str,
+    /// Whether we want peers to relay transactions to us.
+    pub relay: bool,
 
     /// Supported communication domains.
     pub domains: Vec<Domain>,
@@ -591,7 +593,7 @@ impl<U: Handshake + SetTimeout + Connect + Disconnect + Events> PeerManager<U> {
             // Our best height.
             start_height,
             // Whether we want to receive transaction `inv` messages.
-            relay: false,
+            relay: self.config.relay,
         }
     }
 }
@@ -797,6 +799,7 @@ mod tests {
                 max_inbound_peers: MAX_INBOUND_PEERS,
                 domains: Domain::all(),
                 user_agent: crate::protocol::USER_AGENT,
+                relay: false,
                 retry: vec![],
                 services: ServiceFlags::NONE,
                 preferred_services: ServiceFlags::COMPACT_FILTERS | ServiceFlags::NETWORK,
@@ -48,12 +48,14 @@ struct Peer {
 }
 
 impl Peer {
-    /// Calculate the average latency of this peer.
-    #[allow(dead_code)]
-    fn latency(&self) -> LocalDuration {
+    /// Calculate the average latency of this peer, if any latency was recorded.
+    fn latency(&self) -> Option<LocalDuration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
         let sum: LocalDuration = self.latencies.iter().sum();
 
-        sum / self.latencies.len() as u32
+        Some(sum / self.latencies.len() as u32)
     }
 
     fn record_latency(&mut self, sample: LocalDuration) {
@@ -140,6 +142,11 @@ impl<U: Ping + SetTimeout + Disconnect> PingManager<U> {
         }
     }
 
+    /// Get the average round-trip latency of a peer, if known.
+    pub fn latency(&self, addr: &PeerId) -> Option<LocalDuration> {
+        self.peers.get(addr).and_then(|peer| peer.latency())
+    }
+
     /// Called when a `ping` is received.
     pub fn received_ping(&mut self, addr: PeerId, nonce: u64) {
         self.upstream.pong(addr, nonce);
@@ -0,0 +1,496 @@
+//! Local transaction policy.
+//!
+//! Transactions submitted to the network are checked against these rules before being announced,
+//! since peers silently drop transactions that don't follow their relay policy. The rules are a
+//! subset of Bitcoin Core's standardness rules.
+//!
+use std::collections::{HashMap, HashSet};
+
+use bitcoin::blockdata::constants::WITNESS_SCALE_FACTOR;
+use bitcoin::consensus::encode;
+use bitcoin::{OutPoint, Transaction, TxOut, Txid};
+use thiserror::Error;
+
+use nakamoto_common::block::time::MEDIAN_TIME_SPAN;
+use nakamoto_common::block::tree::BlockTree;
+use nakamoto_common::block::BlockTime;
+
+use super::fees::{self, FeeRate};
+use super::Height;
+
+/// Maximum weight of a transaction that is relayed.
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+
+/// Minimum size of a transaction that is relayed, excluding witness data.
+pub const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+
+/// Lock times below this value are interpreted as block heights, and above as timestamps.
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Sequence number of an input that doesn't enable its transaction's lock time.
+pub const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+
+/// Maximum number of transactions in a package.
+pub const MAX_PACKAGE_COUNT: usize = 25;
+
+/// A transaction policy violation.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The transaction has no inputs.
+    #[error("transaction has no inputs")]
+    NoInputs,
+    /// The transaction has no outputs.
+    #[error("transaction has no outputs")]
+    NoOutputs,
+    /// The transaction weight is above the standard limit.
+    #[error("transaction weight of {0} is above the maximum of {max}", max = MAX_STANDARD_TX_WEIGHT)]
+    TooLarge(usize),
+    /// The transaction size, without witness data, is below the standard limit.
+    #[error(
+        "transaction size of {0} byte(s) is below the minimum of {min}",
+        min = MIN_STANDARD_TX_NONWITNESS_SIZE
+    )]
+    TooSmall(usize),
+    /// An output's value is below the dust threshold.
+    #[error("output #{0} value of {1} is below the dust threshold")]
+    Dust(usize, u64),
+    /// The same output is spent by more than one input.
+    #[error("output {0} is spent more than once")]
+    DuplicateInput(OutPoint),
+    /// The transaction can't be included in the next block, due to its lock time.
+    #[error("transaction lock time {0} is not final")]
+    NonFinal(u32),
+    /// The package has no transactions.
+    #[error("package is empty")]
+    EmptyPackage,
+    /// The package has too many transactions.
+    #[error(
+        "package of {0} transaction(s) is above the maximum of {max}",
+        max = MAX_PACKAGE_COUNT
+    )]
+    PackageTooLarge(usize),
+    /// The same transaction is included more than once in a package.
+    #[error("transaction {0} is included more than once")]
+    DuplicateTransaction(Txid),
+    /// The transaction pays too little fees.
+    #[error("fee rate of {rate} sat/vB is below the minimum of {minimum} sat/vB")]
+    FeeTooLow {
+        /// Fee rate of the transaction.
+        rate: FeeRate,
+        /// Minimum fee rate expected.
+        minimum: FeeRate,
+    },
+}
+
+/// Check a transaction against local policy, before announcing it.
+///
+/// The fee rate can only be checked if the outputs spent by the transaction are known. If
+/// `prevouts` is `None`, the fee rate check is skipped.
+pub fn check<T: BlockTree>(
+    tx: &Transaction,
+    tree: &T,
+    prevouts: Option<&[TxOut]>,
+    min_fee_rate: FeeRate,
+) -> Result<(), Error> {
+    if tx.input.is_empty() {
+        return Err(Error::NoInputs);
+    }
+    if tx.output.is_empty() {
+        return Err(Error::NoOutputs);
+    }
+
+    let weight = tx.get_weight();
+    if weight > MAX_STANDARD_TX_WEIGHT {
+        return Err(Error::TooLarge(weight));
+    }
+    let size = nonwitness_size(tx);
+    if size < MIN_STANDARD_TX_NONWITNESS_SIZE {
+        return Err(Error::TooSmall(size));
+    }
+
+    for (i, output) in tx.output.iter().enumerate() {
+        // Data carrier outputs are unspendable, and are expected to carry no value.
+        if output.script_pubkey.is_op_return() {
+            continue;
+        }
+        if output.value < fees::dust_threshold(output) {
+            return Err(Error::Dust(i, output.value));
+        }
+    }
+
+    let mut spent = HashSet::with_capacity(tx.input.len());
+    for input in &tx.input {
+        if !spent.insert(input.previous_output) {
+            return Err(Error::DuplicateInput(input.previous_output));
+        }
+    }
+
+    // The transaction must be valid in the next block.
+    let height = tree.height();
+    if !is_final(tx, height + 1, median_time_past(tree, height)) {
+        return Err(Error::NonFinal(tx.lock_time));
+    }
+
+    if let Some(prevouts) = prevouts {
+        // Nb. An invalid fee is left for peers to reject.
+        if let Ok(fee) = fees::fee(tx, prevouts) {
+            let rate = fee / fees::vsize(tx);
+            if rate < min_fee_rate {
+                return Err(Error::FeeTooLow {
+                    rate,
+                    minimum: min_fee_rate,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check a package of dependent transactions against local policy. The package must be sorted
+/// with [`sort_package`]. Each transaction is checked with [`check`], except for the fee rate,
+/// which is checked for the package as a whole, so that children can pay for their parents.
+///
+/// The outputs spent by the package are looked up in the package itself, and otherwise with
+/// `prevout`. If any of them is unknown, the fee rate check is skipped.
+pub fn check_package<T: BlockTree>(
+    package: &[Transaction],
+    tree: &T,
+    prevout: impl Fn(&OutPoint) -> Option<TxOut>,
+    min_fee_rate: FeeRate,
+) -> Result<(), Error> {
+    if package.is_empty() {
+        return Err(Error::EmptyPackage);
+    }
+    if package.len() > MAX_PACKAGE_COUNT {
+        return Err(Error::PackageTooLarge(package.len()));
+    }
+
+    let mut txs = HashMap::with_capacity(package.len());
+    let mut spent = HashSet::new();
+    for tx in package {
+        let txid = tx.txid();
+
+        if txs.insert(txid, tx).is_some() {
+            return Err(Error::DuplicateTransaction(txid));
+        }
+        // Transactions in the package may not conflict with each other.
+        for input in &tx.input {
+            if !spent.insert(input.previous_output) {
+                return Err(Error::DuplicateInput(input.previous_output));
+            }
+        }
+        check(tx, tree, None, min_fee_rate)?;
+    }
+
+    let received = spent
+        .iter()
+        .map(|outpoint| {
+            txs.get(&outpoint.txid)
+                .and_then(|tx| tx.output.get(outpoint.vout as usize))
+                .cloned()
+                .or_else(|| prevout(outpoint))
+                .map(|output| output.value)
+        })
+        .sum::<Option<u64>>();
+
+    if let Some(received) = received {
+        let sent = package
+            .iter()
+            .flat_map(|tx| &tx.output)
+            .map(|output| output.value)
+            .sum::<u64>();
+
+        // Nb. An invalid fee is left for peers to reject.
+        if let Some(fee) = received.checked_sub(sent) {
+            let rate = fee / package.iter().map(fees::vsize).sum::<u64>();
+            if rate < min_fee_rate {
+                return Err(Error::FeeTooLow {
+                    rate,
+                    minimum: min_fee_rate,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sort transactions so that parents come before the children spending their outputs.
+/// Otherwise, the order of the transactions is preserved.
+pub fn sort_package(txs: Vec<Transaction>) -> Vec<Transaction> {
+    fn visit(
+        ix: usize,
+        txs: &[Transaction],
+        index: &HashMap<Txid, usize>,
+        visited: &mut [bool],
+        order: &mut Vec<usize>,
+    ) {
+        if visited[ix] {
+            return;
+        }
+        visited[ix] = true;
+
+        for input in &txs[ix].input {
+            if let Some(parent) = index.get(&input.previous_output.txid) {
+                visit(*parent, txs, index, visited, order);
+            }
+        }
+        order.push(ix);
+    }
+
+    let index = txs
+        .iter()
+        .enumerate()
+        .map(|(ix, tx)| (tx.txid(), ix))
+        .collect::<HashMap<_, _>>();
+    let mut visited = vec![false; txs.len()];
+    let mut order = Vec::with_capacity(txs.len());
+
+    for ix in 0..txs.len() {
+        visit(ix, &txs, &index, &mut visited, &mut order);
+    }
+    let mut txs = txs.into_iter().map(Some).collect::<Vec<_>>();
+
+    order.into_iter().filter_map(|ix| txs[ix].take()).collect()
+}
+
+/// Check whether a transaction's lock time allows it to be included in a block at the given
+/// height, given the median time past of the previous block.
+pub fn is_final(tx: &Transaction, height: Height, median_time_past: BlockTime) -> bool {
+    if tx.lock_time == 0 {
+        return true;
+    }
+    // The lock time is disabled if all inputs have a final sequence number.
+    if tx.input.iter().all(|i| i.sequence == SEQUENCE_FINAL) {
+        return true;
+    }
+    if tx.lock_time < LOCKTIME_THRESHOLD {
+        (tx.lock_time as Height) < height
+    } else {
+        tx.lock_time < median_time_past
+    }
+}
+
+/// Size of a transaction, without its witness data.
+fn nonwitness_size(tx: &Transaction) -> usize {
+    let scale = WITNESS_SCALE_FACTOR;
+    let total = encode::serialize(tx).len();
+
+    // The weight counts non-witness bytes four times, and witness bytes once.
+    (tx.get_weight() - total) / (scale - 1)
+}
+
+/// Median time of the block at the given height and the blocks preceding it.
+fn median_time_past<T: BlockTree>(tree: &T, height: Height) -> BlockTime {
+    let start = (height + 1).saturating_sub(MEDIAN_TIME_SPAN);
+    let mut times = (start..=height)
+        .filter_map(|h| tree.get_block_by_height(h))
+        .map(|h| h.time)
+        .collect::<Vec<_>>();
+
+    times.sort_unstable();
+    times.get(times.len() / 2).copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin::blockdata::opcodes::all::OP_RETURN;
+    use bitcoin::blockdata::script::Builder;
+
+    use nakamoto_common::network::Network;
+    use nakamoto_test::assert_matches;
+    use nakamoto_test::block::cache::model;
+    use nakamoto_test::block::gen;
+
+    #[test]
+    fn test_check() {
+        let mut rng = fastrand::Rng::new();
+        let network = Network::Regtest;
+        let headers = gen::headers(network.genesis(), 16, &mut rng);
+        let tree = model::Cache::from(headers);
+
+        let mut tx = gen::transaction(&mut rng);
+        tx.lock_time = 0;
+        for output in tx.output.iter_mut() {
+            output.value = fees::DUST_THRESHOLD;
+        }
+        assert_eq!(check(&tx, &tree, None, 1), Ok(()));
+
+        // Dust.
+        {
+            let mut tx = tx.clone();
+            tx.output[0].value = fees::DUST_THRESHOLD - 1;
+            assert_eq!(
+                check(&tx, &tree, None, 1),
+                Err(Error::Dust(0, fees::DUST_THRESHOLD - 1))
+            );
+
+            tx.output[0].script_pubkey = Builder::new()
+                .push_opcode(OP_RETURN)
+                .push_slice(&[0; 32])
+                .into_script();
+            tx.output[0].value = 0;
+            assert_eq!(
+                check(&tx, &tree, None, 1),
+                Ok(()),
+                "Data carriers are allowed"
+            );
+        }
+
+        // Duplicate inputs.
+        {
+            let mut tx = tx.clone();
+            tx.input.push(tx.input[0].clone());
+            assert_eq!(
+                check(&tx, &tree, None, 1),
+                Err(Error::DuplicateInput(tx.input[0].previous_output))
+            );
+        }
+
+        // Lock time.
+        {
+            let mut tx = tx.clone();
+            tx.input[0].sequence = 0;
+
+            tx.lock_time = tree.height() as u32;
+            assert_eq!(check(&tx, &tree, None, 1), Ok(()));
+
+            tx.lock_time = tree.height() as u32 + 1;
+            assert_eq!(
+                check(&tx, &tree, None, 1),
+                Err(Error::NonFinal(tx.lock_time))
+            );
+
+            for input in tx.input.iter_mut() {
+                input.sequence = SEQUENCE_FINAL;
+            }
+            assert_eq!(check(&tx, &tree, None, 1), Ok(()));
+        }
+
+        // Fee rate.
+        {
+            let vsize = fees::vsize(&tx);
+            let sent = tx.output.iter().map(|o| o.value).sum::<u64>();
+            let mut prevouts = tx
+                .input
+                .iter()
+                .map(|_| TxOut {
+                    value: 0,
+                    script_pubkey: Default::default(),
+                })
+                .collect::<Vec<_>>();
+
+            prevouts[0].value = sent + vsize * 4;
+            assert_eq!(check(&tx, &tree, Some(&prevouts), 4), Ok(()));
+            assert_eq!(
+                check(&tx, &tree, Some(&prevouts), 5),
+                Err(Error::FeeTooLow {
+                    rate: 4,
+                    minimum: 5
+                })
+            );
+        }
+
+        // Size.
+        {
+            let mut tx = tx.clone();
+            tx.output.truncate(1);
+            tx.output[0].script_pubkey = Default::default();
+            tx.input.truncate(1);
+            tx.input[0].script_sig = Default::default();
+            tx.input[0].witness.clear();
+
+            nakamoto_test::assert_matches!(check(&tx, &tree, None, 1), Err(Error::TooSmall(_)));
+        }
+    }
+
+    #[test]
+    fn test_package() {
+        let mut rng = fastrand::Rng::new();
+        let network = Network::Regtest;
+        let headers = gen::headers(network.genesis(), 16, &mut rng);
+        let tree = model::Cache::from(headers);
+
+        let mut parent = gen::transaction(&mut rng);
+        parent.lock_time = 0;
+        for output in parent.output.iter_mut() {
+            output.value = fees::DUST_THRESHOLD;
+        }
+        parent.output[0].value = 100_000;
+
+        let mut child = parent.clone();
+        child.input.truncate(1);
+        child.input[0].previous_output = OutPoint {
+            txid: parent.txid(),
+            vout: 0,
+        };
+        child.output.truncate(1);
+        child.output[0].value = fees::DUST_THRESHOLD;
+
+        let mut grandchild = child.clone();
+        grandchild.input[0].previous_output = OutPoint {
+            txid: child.txid(),
+            vout: 0,
+        };
+        let unrelated = gen::transaction(&mut rng);
+
+        assert_eq!(
+            sort_package(vec![
+                grandchild.clone(),
+                unrelated.clone(),
+                child.clone(),
+                parent.clone()
+            ]),
+            vec![parent.clone(), child.clone(), grandchild, unrelated]
+        );
+
+        // The parent pays no fee, and the child pays for both.
+        let sent = parent.output.iter().map(|o| o.value).sum::<u64>();
+        let funding = parent.input[0].previous_output;
+        let prevout = |outpoint: &OutPoint| {
+            Some(TxOut {
+                value: if *outpoint == funding { sent } else { 0 },
+                script_pubkey: Default::default(),
+            })
+        };
+        let fee = parent.output[0].value - child.output[0].value;
+        let rate = fee / (fees::vsize(&parent) + fees::vsize(&child));
+        let package = vec![parent.clone(), child.clone()];
+
+        assert_eq!(check_package(&package, &tree, prevout, rate), Ok(()));
+        assert_eq!(
+            check_package(&package, &tree, prevout, rate + 1),
+            Err(Error::FeeTooLow {
+                rate,
+                minimum: rate + 1
+            })
+        );
+        assert_matches!(
+            check_package(&package[..1], &tree, prevout, 1),
+            Err(Error::FeeTooLow { rate: 0, .. })
+        );
+        assert_eq!(
+            check_package(&package, &tree, |_| None, rate + 1),
+            Ok(()),
+            "The fee rate isn't checked with unknown prevouts"
+        );
+
+        assert_eq!(
+            check_package(&[], &tree, prevout, 1),
+            Err(Error::EmptyPackage)
+        );
+        assert_eq!(
+            check_package(&[parent.clone(), parent.clone()], &tree, prevout, 1),
+            Err(Error::DuplicateTransaction(parent.txid()))
+        );
+
+        let mut conflict = child.clone();
+        conflict.output[0].value += 1;
+        assert_eq!(
+            check_package(&[parent, child.clone(), conflict], &tree, prevout, 1),
+            Err(Error::DuplicateInput(child.input[0].previous_output)),
+            "Transactions in a package may not conflict"
+        );
+    }
+}
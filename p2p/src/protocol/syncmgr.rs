@@ -1,7 +1,16 @@
 //!
 //! Manages header synchronization with peers.
 //!
+//! ## Parallel sync
+//!
+//! Headers up to the last checkpoint are downloaded in checkpoint-delimited ranges, from
+//! multiple peers at once. Since checkpoints are known in advance, each range can be requested
+//! independently with a `getheaders` message from the preceding checkpoint, stopping at the
+//! next one. Ranges are buffered until all preceding ranges are imported, and are then imported
+//! into the block tree in order. Past the last checkpoint, headers are synced from a single peer.
+//!
 #![warn(missing_docs)]
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::SystemTime;
 
@@ -12,7 +21,7 @@ use bitcoin::network::message_blockdata::Inventory;
 use nakamoto_common::block::store;
 use nakamoto_common::block::time::{Clock, LocalDuration, LocalTime};
 use nakamoto_common::block::tree::{BlockTree, Error, ImportResult};
-use nakamoto_common::block::{BlockHash, BlockHeader, Height};
+use nakamoto_common::block::{BlockHash, BlockHeader, Height, Work};
 use nakamoto_common::collections::{AddressBook, HashMap};
 use nakamoto_common::nonempty::NonEmpty;
 
@@ -31,6 +40,13 @@ pub const IDLE_TIMEOUT: LocalDuration = LocalDuration::BLOCK_INTERVAL;
 /// Services required from peers for header sync.
 pub const REQUIRED_SERVICES: ServiceFlags = ServiceFlags::NETWORK;
 
+/// Maximum number of checkpoint-delimited header ranges downloaded in parallel.
+pub const MAX_PARALLEL_RANGES: usize = 8;
+/// Number of reverted blocks from which a re-org is considered deep.
+pub const DEEP_REORG_DEPTH: usize = 6;
+/// Misbehavior score from which a peer is disconnected.
+pub const MAX_MISBEHAVIOR_SCORE: u32 = 100;
+
 /// Maximum headers announced in a `headers` message, when unsolicited.
 const MAX_HEADERS_ANNOUNCED: usize = 8;
 /// How long to wait between checks for longer chains from peers.
@@ -65,6 +81,43 @@ struct PeerState {
     link: Link,
     last_active: Option<LocalTime>,
     last_asked: Option<Locators>,
+    /// Accumulated misbehavior score. See [`MAX_MISBEHAVIOR_SCORE`].
+    misbehavior: u32,
+}
+
+/// A range of headers ending at a checkpoint, downloaded from a single peer at a time.
+#[derive(Debug)]
+struct HeaderRange {
+    /// Hash of the header preceding the headers in this range.
+    start: BlockHash,
+    /// Height of the header preceding the headers in this range.
+    start_height: Height,
+    /// Checkpoint hash ending the range.
+    end: BlockHash,
+    /// Headers downloaded and not yet imported.
+    headers: Vec<BlockHeader>,
+    /// Peer currently downloading this range.
+    peer: Option<PeerId>,
+}
+
+impl HeaderRange {
+    /// Hash of the last header known in this range.
+    fn tip(&self) -> BlockHash {
+        self.headers
+            .last()
+            .map(|h| h.block_hash())
+            .unwrap_or(self.start)
+    }
+
+    /// Height of the last header known in this range.
+    fn height(&self) -> Height {
+        self.start_height + self.headers.len() as Height
+    }
+
+    /// Whether all headers in this range were downloaded.
+    fn is_complete(&self) -> bool {
+        self.tip() == self.end
+    }
 }
 
 /// Sync manager configuration.
@@ -76,6 +129,10 @@ pub struct Config {
     pub request_timeout: LocalDuration,
     /// Consensus parameters.
     pub params: Params,
+    /// Minimum total work of the active chain, before we consider ourselves synced.
+    pub minimum_chain_work: Work,
+    /// Number of reverted blocks from which a re-org triggers an [`Event::DeepReorg`].
+    pub deep_reorg_depth: usize,
 }
 
 /// The sync manager state.
@@ -95,6 +152,11 @@ pub struct SyncManager<U> {
     rng: fastrand::Rng,
     /// In-flight requests to peers.
     inflight: HashMap<PeerId, GetHeaders>,
+    /// Time and height at which the current header sync started, if we're syncing.
+    /// Used to report sync progress.
+    started: Option<(LocalTime, Height)>,
+    /// Checkpoint-delimited header ranges being downloaded, keyed by checkpoint height.
+    ranges: BTreeMap<Height, HeaderRange>,
     /// Upstream protocol channel.
     upstream: U,
 }
@@ -122,8 +184,27 @@ pub enum Event {
         /// Block hash.
         hash: BlockHash,
     },
+    /// The active chain was re-organized. Emitted after the [`Event::BlockDisconnected`] and
+    /// [`Event::BlockConnected`] events of the re-org.
+    Reorg {
+        /// Height of the last block shared by the old and new active chains.
+        fork_height: Height,
+        /// Blocks removed from the active chain, in ascending height order.
+        reverted: Vec<(Height, BlockHash)>,
+        /// Blocks added to the active chain, in ascending height order.
+        connected: Vec<(Height, BlockHeader)>,
+    },
+    /// A re-org at least as deep as the configured threshold happened.
+    DeepReorg {
+        /// Height of the last block shared by the old and new active chains.
+        fork_height: Height,
+        /// Number of blocks reverted.
+        depth: usize,
+    },
     /// A new block was discovered via a peer.
     BlockDiscovered(PeerId, BlockHash),
+    /// Orphan blocks received from a peer expired without connecting to our chain.
+    OrphansExpired(PeerId, usize),
     /// Headers were imported successfully.
     HeadersImported(ImportResult),
     /// Started syncing with a peer.
@@ -134,6 +215,17 @@ pub enum Event {
     TimedOut(PeerId),
     /// Potential stale tip detected on the active chain.
     StaleTipDetected(LocalTime),
+    /// Header sync progressed.
+    HeaderSyncProgress {
+        /// Height of our header chain.
+        height: Height,
+        /// Best height known from our peers.
+        best: Height,
+        /// Average number of headers imported per second, since the sync started.
+        headers_per_sec: f64,
+        /// Estimated time remaining until headers are synced, if it can be estimated.
+        eta: Option<LocalDuration>,
+    },
 }
 
 impl std::fmt::Display for Event {
@@ -167,9 +259,44 @@ impl std::fmt::Display for Event {
             Event::BlockDisconnected { height, hash } => {
                 write!(fmt, "Block {} disconnected at height {}", hash, height)
             }
+            Event::Reorg {
+                fork_height,
+                reverted,
+                connected,
+            } => {
+                write!(
+                    fmt,
+                    "Chain re-organized at height {} ({} block(s) reverted, {} connected)",
+                    fork_height,
+                    reverted.len(),
+                    connected.len()
+                )
+            }
+            Event::DeepReorg { fork_height, depth } => {
+                write!(
+                    fmt,
+                    "Deep re-org of {} block(s) at height {}",
+                    depth, fork_height
+                )
+            }
             Event::BlockDiscovered(from, hash) => {
                 write!(fmt, "{}: Discovered new block: {}", from, &hash)
             }
+            Event::OrphansExpired(from, count) => {
+                write!(fmt, "{}: {} orphan block(s) expired", from, count)
+            }
+            Event::HeaderSyncProgress {
+                height,
+                best,
+                headers_per_sec,
+                ..
+            } => {
+                write!(
+                    fmt,
+                    "Synced headers up to height {} of {} ({:.1} headers/s)",
+                    height, best, headers_per_sec
+                )
+            }
             Event::StaleTipDetected(last_update) => {
                 let elapsed = LocalTime::from(SystemTime::now()) - *last_update;
 
@@ -216,6 +343,8 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
         let last_peer_sample = None;
         let last_idle = None;
         let inflight = HashMap::with_hasher(rng.clone().into());
+        let started = None;
+        let ranges = BTreeMap::new();
 
         Self {
             peers,
@@ -225,6 +354,8 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
             last_idle,
             rng,
             inflight,
+            started,
+            ranges,
             upstream,
         }
     }
@@ -298,7 +429,18 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
         context: &C,
         tree: &mut T,
     ) -> Result<ImportResult, Error> {
-        match tree.import_blocks(blocks, context) {
+        let result = tree.import_blocks(blocks, context);
+
+        self.imported(result, tree)
+    }
+
+    /// Called after blocks were imported into our block tree.
+    fn imported<T: BlockTree>(
+        &mut self,
+        result: Result<ImportResult, Error>,
+        tree: &T,
+    ) -> Result<ImportResult, Error> {
+        match result {
             Ok(ImportResult::TipChanged(header, tip, height, reverted, connected)) => {
                 let result = ImportResult::TipChanged(
                     header,
@@ -308,15 +450,30 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
                     connected.clone(),
                 );
 
-                for (height, hash) in reverted {
+                for (height, hash) in reverted.iter().cloned() {
                     self.upstream
                         .event(Event::BlockDisconnected { height, hash });
                 }
-                for (height, header) in connected {
+                for (height, header) in connected.iter().cloned() {
                     self.upstream
                         .event(Event::BlockConnected { height, header });
                 }
 
+                if !reverted.is_empty() {
+                    let fork_height = connected.head.0 - 1;
+                    let depth = reverted.len();
+
+                    self.upstream.event(Event::Reorg {
+                        fork_height,
+                        reverted,
+                        connected: connected.into(),
+                    });
+
+                    if depth >= self.config.deep_reorg_depth {
+                        self.upstream.event(Event::DeepReorg { fork_height, depth });
+                    }
+                }
+
                 self.upstream.event(Event::Synced(tip, height));
                 self.broadcast_tip(&tip, tree);
 
@@ -327,6 +484,30 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
         }
     }
 
+    /// Called when a block of our header chain turned out to be invalid. Rolls back the active
+    /// chain if necessary, and returns the rolled-back blocks.
+    pub fn block_invalidated<T: BlockTree>(
+        &mut self,
+        hash: &BlockHash,
+        now: LocalTime,
+        tree: &mut T,
+    ) -> Result<Vec<(Height, BlockHash)>, Error> {
+        let reverted = tree.invalidate_block(hash, now)?;
+
+        if !reverted.is_empty() {
+            for (height, hash) in reverted.iter().cloned() {
+                self.upstream
+                    .event(Event::BlockDisconnected { height, hash });
+            }
+            let (tip, _) = tree.tip();
+
+            self.upstream.event(Event::Synced(tip, tree.height()));
+            // Look for a valid chain with our other peers.
+            self.sync(now, tree);
+        }
+        Ok(reverted)
+    }
+
     /// Called when we receive headers from a peer.
     pub fn received_headers<T: BlockTree>(
         &mut self,
@@ -352,11 +533,36 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
         self.upstream
             .event(Event::HeadersReceived(*from, headers.len()));
 
+        let range = self
+            .ranges
+            .iter()
+            .find(|(_, r)| r.peer.as_ref() == Some(from))
+            .map(|(h, r)| (*h, r.tip()));
+
+        if let Some((end, tip)) = range {
+            // Headers answering our request for the range start at its tip. Since peers only
+            // announce a few headers at a time, a larger batch that doesn't connect is also an
+            // answer, albeit a bad one. Other headers, eg. new tip announcements, are handled
+            // as usual, and the range stays assigned to the peer.
+            if headers.first().prev_blockhash == tip || length > MAX_HEADERS_ANNOUNCED {
+                self.inflight.remove(from);
+
+                return self.received_range(from, end, headers, clock, tree);
+            }
+        }
+
         if tree.contains(&best) {
             return Ok(ImportResult::TipUnchanged);
         }
 
-        match self.inflight.remove(from) {
+        // The request for a header range is still in flight.
+        let request = if range.is_some() {
+            None
+        } else {
+            self.inflight.remove(from)
+        };
+
+        match request {
             Some(GetHeaders { locators, .. })
                 if headers
                     .iter()
@@ -383,6 +589,7 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
                         // Keep track of when we last updated our tip. This is useful to check
                         // whether our tip is stale.
                         self.last_tip_update = Some(clock.local_time());
+                        self.progress(height, clock.local_time());
 
                         // If we received less than the maximum number of headers, we must be in sync.
                         // Otherwise, ask for the next batch of headers.
@@ -421,21 +628,26 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
             _ if length <= MAX_HEADERS_ANNOUNCED => {
                 let root = headers.first().block_hash();
 
-                match self.import_blocks(headers.into_iter(), clock, tree) {
+                // Orphans are attributed to the peer, in case they never connect.
+                let result = tree.import_blocks_from(headers.into_iter(), clock, *from);
+
+                match self.imported(result, tree) {
                     Ok(import_result @ ImportResult::TipUnchanged) => {
                         // Try to find a common ancestor that leads up to the first header in
-                        // the list we received.
-                        let locators = (tree.locator_hashes(tree.height()), root);
-                        let timeout = self.config.request_timeout;
-
-                        self.request(
-                            *from,
-                            locators,
-                            clock.local_time(),
-                            timeout,
-                            OnTimeout::Ignore,
-                        );
+                        // the list we received. Peers downloading a header range are left to
+                        // it, since we're still catching up.
+                        if range.is_none() {
+                            let locators = (tree.locator_hashes(tree.height()), root);
+                            let timeout = self.config.request_timeout;
 
+                            self.request(
+                                *from,
+                                locators,
+                                clock.local_time(),
+                                timeout,
+                                OnTimeout::Ignore,
+                            );
+                        }
                         Ok(import_result)
                     }
                     Ok(ImportResult::TipChanged(header, tip, height, reverted, connected)) => {
@@ -465,6 +677,205 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
         }
     }
 
+    /// Called when we receive headers belonging to a checkpoint-delimited range.
+    fn received_range<T: BlockTree>(
+        &mut self,
+        from: &PeerId,
+        end: Height,
+        headers: NonEmpty<BlockHeader>,
+        clock: &impl Clock,
+        tree: &mut T,
+    ) -> Result<ImportResult, store::Error> {
+        let length = headers.len();
+        let range = if let Some(range) = self.ranges.get_mut(&end) {
+            range
+        } else {
+            return Ok(ImportResult::TipUnchanged);
+        };
+        range.peer = None;
+
+        // Make sure the headers connect to the range, and don't go past its end.
+        let mut tip = range.tip();
+        let mut height = range.height();
+
+        for header in headers.iter() {
+            if header.prev_blockhash != tip || height >= end {
+                self.upstream
+                    .event(Event::UnsolicitedHeadersReceived(*from, length));
+                // Re-assign the range to another peer right away.
+                self.sync(clock.local_time(), tree);
+
+                return Ok(ImportResult::TipUnchanged);
+            }
+            tip = header.block_hash();
+            height += 1;
+
+            if height == end && tip != range.end {
+                self.upstream.event(Event::InvalidHeadersReceived(
+                    *from,
+                    Arc::new(Error::InvalidBlockHash(tip, height)),
+                ));
+                self.record_misbehavior(
+                    from,
+                    MAX_MISBEHAVIOR_SCORE,
+                    "headers don't match checkpoint",
+                );
+                self.sync(clock.local_time(), tree);
+
+                return Ok(ImportResult::TipUnchanged);
+            }
+        }
+        range.headers.extend(headers);
+
+        // If the peer sent us a full batch, ask it for the rest of the range.
+        if !range.is_complete() && length >= self.config.max_message_headers {
+            let locators = (vec![range.tip()], range.end);
+            let timeout = self.config.request_timeout;
+
+            range.peer = Some(*from);
+            self.request(
+                *from,
+                locators,
+                clock.local_time(),
+                timeout,
+                OnTimeout::Ignore,
+            );
+        }
+        let result = self.import_ranges(from, clock, tree);
+        // Assign idle peers to the remaining ranges.
+        self.sync(clock.local_time(), tree);
+
+        result
+    }
+
+    /// Import downloaded header ranges that connect to our tip, in order.
+    fn import_ranges<T: BlockTree>(
+        &mut self,
+        from: &PeerId,
+        clock: &impl Clock,
+        tree: &mut T,
+    ) -> Result<ImportResult, store::Error> {
+        let mut result = ImportResult::TipUnchanged;
+
+        while let Some(end) = self.ranges.keys().next().copied() {
+            let (tip, _) = tree.tip();
+            let range = self.ranges.get_mut(&end).unwrap();
+
+            if range.start != tip {
+                break;
+            }
+            let headers = if let Some(headers) = NonEmpty::from_vec(range.headers.split_off(0)) {
+                headers
+            } else {
+                break;
+            };
+            range.start = headers.last().block_hash();
+            range.start_height += headers.len() as Height;
+
+            let complete = range.is_complete();
+            if complete {
+                self.ranges.remove(&end);
+            }
+
+            match self.extend_chain(headers, clock, tree) {
+                Ok(ImportResult::TipChanged(header, tip, height, reverted, connected)) => {
+                    self.last_tip_update = Some(clock.local_time());
+                    self.progress(height, clock.local_time());
+
+                    result = ImportResult::TipChanged(header, tip, height, reverted, connected);
+                }
+                Ok(ImportResult::TipUnchanged) => {}
+                Err(err) => {
+                    // The range will be downloaded again, starting from our tip.
+                    self.ranges.remove(&end);
+
+                    return self.handle_error(from, err).map(|()| result);
+                }
+            }
+            if !complete {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Download the headers up to the last checkpoint in parallel, in checkpoint-delimited
+    /// ranges. Returns `true` if there are ranges left to download.
+    fn sync_ranges<T: BlockTree>(&mut self, now: LocalTime, tree: &T) -> bool {
+        let height = tree.height();
+        let (tip, _) = tree.tip();
+
+        self.ranges.retain(|end, _| *end > height);
+
+        // The first range always starts at our tip. If our tip changed in the meantime,
+        // restart the range from there.
+        if let Some(first) = self.ranges.values_mut().next() {
+            if first.start != tip {
+                first.start = tip;
+                first.start_height = height;
+                first.headers.clear();
+            }
+        }
+
+        // Create new ranges between the checkpoints following the last range.
+        let (last, mut start) = self
+            .ranges
+            .iter()
+            .next_back()
+            .map(|(h, r)| (*h, r.end))
+            .unwrap_or((height, tip));
+        let checkpoints = tree.checkpoints();
+        let remaining = MAX_PARALLEL_RANGES.saturating_sub(self.ranges.len());
+        let mut start_height = last;
+
+        for (end, hash) in checkpoints.range(last + 1..).take(remaining) {
+            self.ranges.insert(
+                *end,
+                HeaderRange {
+                    start,
+                    start_height,
+                    end: *hash,
+                    headers: Vec::new(),
+                    peer: None,
+                },
+            );
+            start = *hash;
+            start_height = *end;
+        }
+
+        // Assign idle peers to the ranges that are left to download.
+        let pending = self
+            .ranges
+            .iter()
+            .filter(|(_, r)| r.peer.is_none() && !r.is_complete())
+            .map(|(h, r)| (*h, (vec![r.tip()], r.end)))
+            .collect::<Vec<_>>();
+
+        for (end, locators) in pending {
+            let candidate = self
+                .peers
+                .sample_with(|addr, peer| {
+                    peer.link.is_outbound()
+                        && peer.height >= end
+                        && !self.inflight.contains_key(addr)
+                        && peer.last_asked.as_ref() != Some(&locators)
+                })
+                .map(|(addr, _)| *addr);
+
+            if let Some(addr) = candidate {
+                let timeout = self.config.request_timeout;
+
+                if let Some(range) = self.ranges.get_mut(&end) {
+                    range.peer = Some(addr);
+                }
+                self.started.get_or_insert((now, height));
+                self.request(addr, locators, now, timeout, OnTimeout::Ignore);
+                self.upstream.event(Event::Syncing(addr));
+            }
+        }
+        !self.ranges.is_empty()
+    }
+
     fn request(
         &mut self,
         addr: PeerId,
@@ -572,7 +983,13 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
     }
 
     /// Called when we received a tick.
-    pub fn received_tick<T: BlockTree>(&mut self, local_time: LocalTime, tree: &T) {
+    pub fn received_tick<T: BlockTree>(&mut self, local_time: LocalTime, tree: &mut T) {
+        // Peers that sent us blocks that never connected are considered misbehaving.
+        for (peer, count) in tree.expire_orphans(local_time) {
+            self.upstream.event(Event::OrphansExpired(peer, count));
+            self.record_misbehavior(&peer, 10, "sent blocks that never connected");
+        }
+
         let timeout = self.config.request_timeout;
         let timed_out = self
             .inflight
@@ -588,6 +1005,7 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
 
         for (peer, on_timeout) in &timed_out {
             self.inflight.remove(peer);
+            self.unassign(peer);
 
             match on_timeout {
                 OnTimeout::Disconnect => {
@@ -620,6 +1038,37 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
         !self.inflight.is_empty()
     }
 
+    /// Emit a header sync progress event, given our new height.
+    fn progress(&mut self, height: Height, now: LocalTime) {
+        let (start_time, start_height) = if let Some(started) = self.started {
+            started
+        } else {
+            return;
+        };
+        let best = self.best_height().unwrap_or_default().max(height);
+        let elapsed = now - start_time;
+        let synced = height.saturating_sub(start_height);
+        let headers_per_sec = if elapsed.as_millis() > 0 {
+            synced as f64 / (elapsed.as_millis() as f64 / 1000.)
+        } else {
+            0.
+        };
+        let eta = if headers_per_sec > 0. {
+            let remaining = (best - height) as f64 / headers_per_sec;
+
+            Some(LocalDuration::from_millis((remaining * 1000.) as u128))
+        } else {
+            None
+        };
+
+        self.upstream.event(Event::HeaderSyncProgress {
+            height,
+            best,
+            headers_per_sec,
+            eta,
+        });
+    }
+
     ///////////////////////////////////////////////////////////////////////////
 
     fn handle_error(&mut self, from: &PeerId, err: Error) -> Result<(), store::Error> {
@@ -628,29 +1077,52 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
             // this up, because we can't handle it here.
             Error::Store(e) => Err(e),
 
+            // Blocks too far in the future may become valid later, and our clock could be off.
+            // Like other nodes, we don't consider this misbehavior.
+            Error::InvalidBlockTime(_, std::cmp::Ordering::Greater) => {
+                self.upstream
+                    .event(Event::InvalidHeadersReceived(*from, Arc::new(err)));
+
+                Ok(())
+            }
+
             // If we got a bad block from the peer, we can handle it here.
             Error::InvalidBlockPoW
             | Error::InvalidBlockTarget(_, _)
             | Error::InvalidBlockHash(_, _)
             | Error::InvalidBlockHeight(_)
-            | Error::InvalidBlockTime(_, _) => {
-                self.record_misbehavior(from);
+            | Error::InvalidBlockTime(_, _)
+            | Error::InvalidBlockBody(_)
+            | Error::InvalidBlockSolution(_) => {
                 self.upstream
                     .event(Event::InvalidHeadersReceived(*from, Arc::new(err)));
+                self.record_misbehavior(from, 20, "invalid headers received");
 
                 Ok(())
             }
 
             // Harmless errors can be ignored.
-            Error::DuplicateBlock(_) | Error::BlockMissing(_) => Ok(()),
+            // Orphans rejected because the orphan pool is full may have been sent in good faith.
+            Error::DuplicateBlock(_) | Error::BlockMissing(_) | Error::OrphanPoolFull(_) => Ok(()),
 
             // TODO: This will be removed.
             Error::BlockImportAborted(_, _, _) => Ok(()),
         }
     }
 
-    fn record_misbehavior(&mut self, _peer: &PeerId) {
-        // TODO
+    /// Add to a peer's misbehavior score, and disconnect it once the score reaches
+    /// [`MAX_MISBEHAVIOR_SCORE`]. Misbehavior that could have happened in good faith, eg.
+    /// an orphan block that never connected, should only count for part of the score.
+    fn record_misbehavior(&mut self, peer: &PeerId, score: u32, reason: &'static str) {
+        if let Some(state) = self.peers.get_mut(peer) {
+            state.misbehavior = state.misbehavior.saturating_add(score);
+
+            if state.misbehavior >= MAX_MISBEHAVIOR_SCORE {
+                self.unregister(peer);
+                self.upstream
+                    .disconnect(*peer, DisconnectReason::PeerMisbehaving(reason));
+            }
+        }
     }
 
     /// Check whether our current tip is stale.
@@ -682,6 +1154,7 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
         let last_active = None;
         let last_asked = None;
         let tip = BlockHash::default();
+        let misbehavior = 0;
 
         self.peers.insert(
             id,
@@ -691,6 +1164,7 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
                 link,
                 last_active,
                 last_asked,
+                misbehavior,
             },
         );
     }
@@ -699,6 +1173,16 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
     fn unregister(&mut self, id: &PeerId) {
         self.inflight.remove(id);
         self.peers.remove(id);
+        self.unassign(id);
+    }
+
+    /// Unassign a peer from the header range it is downloading, if any.
+    fn unassign(&mut self, id: &PeerId) {
+        for range in self.ranges.values_mut() {
+            if range.peer.as_ref() == Some(id) {
+                range.peer = None;
+            }
+        }
     }
 
     /// Check whether a peer can be synced with using the given locators.
@@ -722,6 +1206,10 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
 
             return false;
         }
+        // Our chain can't be trusted until it has the minimum work.
+        if tree.chain_work() < self.config.minimum_chain_work {
+            return false;
+        }
         let height = tree.height();
 
         // Find the peer with the longest chain and compare our height to it.
@@ -747,6 +1235,7 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
             let (tip, _) = tree.tip();
             let height = tree.height();
 
+            self.started = None;
             self.upstream.event(Event::Synced(tip, height));
 
             // If we think we're in sync and we haven't asked other peers in a while, then
@@ -763,6 +1252,16 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
         }
         // It looks like we're out of sync...
 
+        // If our chain doesn't have the minimum work, peers whose chain we've caught up
+        // with can't help us.
+        if tree.chain_work() < self.config.minimum_chain_work {
+            self.disconnect_low_work_peers(tree);
+        }
+
+        // If we're behind the last checkpoint, sync the checkpoint ranges in parallel.
+        if self.sync_ranges(now, tree) {
+            return;
+        }
         let locators = (tree.locator_hashes(tree.height()), BlockHash::default());
 
         // If we're already fetching these headers, just wait.
@@ -777,6 +1276,8 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
             let timeout = self.config.request_timeout;
             let addr = *addr;
 
+            // Header sync is resumed from our current tip.
+            self.started.get_or_insert((now, tree.height()));
             self.request(addr, locators, now, timeout, OnTimeout::Ignore);
             self.upstream.event(Event::Syncing(addr));
         } else {
@@ -784,13 +1285,37 @@ impl<U: SetTimeout + SyncHeaders + Disconnect> SyncManager<U> {
         }
     }
 
+    /// Disconnect outbound peers we synced with, whose best chain we've caught up with without
+    /// reaching the minimum chain work. Peers we're still syncing with are spared.
+    fn disconnect_low_work_peers<T: BlockTree>(&mut self, tree: &T) {
+        let height = tree.height();
+        let peers = self
+            .peers
+            .iter()
+            .filter(|(addr, peer)| {
+                peer.link.is_outbound()
+                    && peer.last_asked.is_some()
+                    && peer.height <= height
+                    && !self.inflight.contains_key(addr)
+                    && !self.ranges.values().any(|r| r.peer.as_ref() == Some(addr))
+            })
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+
+        for addr in peers {
+            self.unregister(&addr);
+            self.upstream
+                .disconnect(addr, DisconnectReason::PeerChainWork);
+        }
+    }
+
     /// Broadcast our best block header to connected peers who don't have it.
     fn broadcast_tip<T: BlockTree>(&mut self, hash: &BlockHash, tree: &T) {
         if let Some((height, best)) = tree.get_block(hash) {
             for (addr, peer) in &*self.peers {
                 // TODO: Don't broadcast to peer that is currently syncing?
                 if peer.link == Link::Inbound && height > peer.height {
-                    self.upstream.send_headers(*addr, vec![*best]);
+                    self.upstream.send_headers(*addr, vec![best]);
                 }
             }
         }
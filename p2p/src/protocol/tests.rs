@@ -12,7 +12,7 @@ use log::*;
 use super::{addrmgr, cbfmgr, invmgr, peermgr, pingmgr, syncmgr};
 use super::{
     chan, message, AdjustedTime, BlockHash, BlockHeader, BlockTree as _, Command, Config,
-    DisconnectReason, Event, HashSet, Height, Input, Link, LocalDuration, LocalTime, Network,
+    DisconnectReason, Event, HashSet, Height, Input, Link, LocalDuration, LocalTime,
     NetworkMessage, Out, PeerId, RawNetworkMessage, ServiceFlags, VersionMessage,
 };
 use super::{PROTOCOL_VERSION, USER_AGENT};
@@ -20,6 +20,8 @@ use super::{PROTOCOL_VERSION, USER_AGENT};
 use peer::{Peer, PeerDummy};
 use simulator::{Options, Simulation};
 
+use invmgr::Broadcast;
+
 use bitcoin::network::message_blockdata::Inventory;
 use bitcoin::network::message_filter::CFilter;
 use bitcoin::network::message_filter::{CFHeaders, GetCFHeaders, GetCFilters};
@@ -28,12 +30,14 @@ use bitcoin_hashes::hex::FromHex;
 
 use quickcheck_macros::quickcheck;
 
-use nakamoto_chain::block::cache::BlockCache;
+use nakamoto_chain::block::cache::{self, BlockCache};
 use nakamoto_chain::block::store;
 use nakamoto_chain::store::Genesis;
 
 use nakamoto_common::block::filter::FilterHeader;
+use nakamoto_common::block::Work;
 use nakamoto_common::collections::HashMap;
+use nakamoto_common::network::{Network, NetworkParams};
 use nakamoto_common::nonempty::NonEmpty;
 use nakamoto_common::p2p::peer::KnownAddress;
 use nakamoto_common::p2p::peer::Source;
@@ -126,6 +130,219 @@ fn test_initial_sync() {
     }
 }
 
+#[test]
+fn test_header_sync_progress() {
+    let rng = fastrand::Rng::new();
+    let network = Network::Mainnet;
+    let height = 144;
+    let headers = BITCOIN_HEADERS.tail[0..height].to_vec();
+
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng);
+    let remote = PeerDummy {
+        addr: ([88, 88, 88, 88], 8333).into(),
+        height: height as Height,
+        protocol_version: alice.protocol.protocol_version,
+        services: ServiceFlags::NETWORK,
+        relay: false,
+        time: alice.time,
+    };
+    alice.time = LocalTime::from_block_time(headers.last().unwrap().time);
+    alice.connect(&remote, Link::Outbound);
+    alice
+        .messages()
+        .find(|(_, msg)| matches!(msg, NetworkMessage::GetHeaders(_)))
+        .expect("Alice sends a `getheaders` message");
+
+    alice.time.elapse(LocalDuration::from_secs(2));
+    alice.receive(remote.addr, NetworkMessage::Headers(headers));
+
+    assert_matches!(
+        alice.events().find_map(|e| match e {
+            Event::SyncManager(e @ syncmgr::Event::HeaderSyncProgress { .. }) => Some(e),
+            _ => None,
+        }),
+        Some(syncmgr::Event::HeaderSyncProgress { height: 144, best: 144, headers_per_sec, eta })
+        if headers_per_sec == 72. && eta == Some(LocalDuration::from_secs(0))
+    );
+
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::GetSyncStatus(transmit));
+
+    let status = receive.recv().unwrap();
+    assert!(status.headers.is_done());
+    assert_eq!(status.headers.height, height as Height);
+    assert_eq!(status.filter_headers.best, height as Height);
+    assert_eq!(status.blocks, 0);
+}
+
+#[test]
+fn test_minimum_chain_work() {
+    let network = Network::Mainnet;
+    let height = 144;
+    let headers = BITCOIN_HEADERS.tail[0..height].to_vec();
+    let work = headers
+        .iter()
+        .fold(Work::default(), |work, h| work + h.work());
+
+    for (minimum_chain_work, disconnected) in [(work, false), (work + work, true)] {
+        let mut cfg = Config::from("alice", network, vec![]);
+        cfg.network.minimum_chain_work = minimum_chain_work;
+
+        let mut alice = Peer::config(
+            [48, 48, 48, 48],
+            vec![],
+            vec![],
+            vec![],
+            cfg,
+            fastrand::Rng::new(),
+        );
+        let remote = PeerDummy {
+            addr: ([88, 88, 88, 88], 8333).into(),
+            height: height as Height,
+            protocol_version: alice.protocol.protocol_version,
+            services: ServiceFlags::NETWORK,
+            relay: false,
+            time: alice.time,
+        };
+        alice.time = LocalTime::from_block_time(headers.last().unwrap().time);
+        alice.connect(&remote, Link::Outbound);
+        alice.receive(remote.addr, NetworkMessage::Headers(headers.clone()));
+
+        assert_eq!(alice.protocol.tree.height(), height as Height);
+        assert_eq!(
+            alice.outputs().any(|o| matches!(
+                o,
+                Out::Disconnect(addr, DisconnectReason::PeerChainWork) if addr == remote.addr
+            )),
+            disconnected,
+            "the peer is disconnected if its chain doesn't have the minimum work"
+        );
+    }
+}
+
+#[test]
+fn test_parallel_header_sync() {
+    let rng = fastrand::Rng::new();
+    let network = Network::Mainnet;
+    let headers = BITCOIN_HEADERS.tail[0..200].to_vec();
+    let checkpoints = [50, 100, 150]
+        .iter()
+        .map(|h| (*h as Height, headers[*h - 1].block_hash()))
+        .collect::<Vec<_>>();
+
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+    let store = store::Memory::new(NonEmpty::new(network.genesis()));
+    alice.protocol.tree =
+        BlockCache::from(store, alice.cfg.network.params.clone(), &checkpoints).unwrap();
+    alice.time = LocalTime::from_block_time(headers.last().unwrap().time);
+
+    let remotes: Vec<PeerId> = vec![
+        ([55, 55, 55, 55], 8333).into(),
+        ([66, 66, 66, 66], 8333).into(),
+        ([77, 77, 77, 77], 8333).into(),
+    ];
+    // Each checkpoint range is requested from a different peer.
+    let mut requests = HashMap::with_hasher(rng.clone().into());
+    for addr in &remotes {
+        alice.connect(
+            &PeerDummy {
+                addr: *addr,
+                height: headers.len() as Height,
+                protocol_version: alice.protocol.protocol_version,
+                services: ServiceFlags::NETWORK,
+                relay: false,
+                time: alice.time,
+            },
+            Link::Outbound,
+        );
+        requests.extend(alice.messages().filter_map(|(addr, msg)| match msg {
+            NetworkMessage::GetHeaders(msg) => Some((msg.stop_hash, addr)),
+            _ => None,
+        }));
+    }
+    let peers = checkpoints
+        .iter()
+        .map(|(_, hash)| *requests.get(hash).expect("All ranges are requested"))
+        .collect::<HashSet<_>>();
+    assert_eq!(peers.len(), remotes.len());
+
+    // A new tip announced by a peer downloading a range doesn't affect the range.
+    let peer = requests[&checkpoints[2].1];
+    alice.receive(peer, NetworkMessage::Headers(headers[199..].to_vec()));
+
+    // Ranges received out of order are only imported once they connect to our tip.
+    for (i, range) in [(2, 100..150), (1, 50..100), (0, 0..50)].iter().cloned() {
+        let peer = requests[&checkpoints[i].1];
+
+        assert_eq!(alice.protocol.tree.height(), 0);
+        alice.receive(peer, NetworkMessage::Headers(headers[range].to_vec()));
+    }
+    assert_eq!(alice.protocol.tree.height(), 150);
+
+    // Past the last checkpoint, headers are synced from our tip.
+    alice
+        .messages()
+        .find(|(_, msg)| {
+            matches!(msg, NetworkMessage::GetHeaders(msg) if msg.locator_hashes == vec![headers[149].block_hash()])
+        })
+        .expect("Alice syncs from her tip");
+}
+
+/// Test that a header range is re-assigned right away if its headers don't connect.
+#[test]
+fn test_header_range_reassigned() {
+    let rng = fastrand::Rng::new();
+    let network = Network::Mainnet;
+    let headers = BITCOIN_HEADERS.tail[0..200].to_vec();
+    let checkpoints = [50, 100, 150]
+        .iter()
+        .map(|h| (*h as Height, headers[*h - 1].block_hash()))
+        .collect::<Vec<_>>();
+
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+    let store = store::Memory::new(NonEmpty::new(network.genesis()));
+    alice.protocol.tree =
+        BlockCache::from(store, alice.cfg.network.params.clone(), &checkpoints).unwrap();
+    alice.time = LocalTime::from_block_time(headers.last().unwrap().time);
+
+    // One more peer than there are ranges, so that one of them is idle.
+    let remotes: Vec<PeerId> = vec![
+        ([55, 55, 55, 55], 8333).into(),
+        ([66, 66, 66, 66], 8333).into(),
+        ([77, 77, 77, 77], 8333).into(),
+        ([88, 88, 88, 88], 8333).into(),
+    ];
+    let mut requests = HashMap::with_hasher(rng.clone().into());
+    for addr in &remotes {
+        alice.connect(
+            &PeerDummy {
+                addr: *addr,
+                height: headers.len() as Height,
+                protocol_version: alice.protocol.protocol_version,
+                services: ServiceFlags::NETWORK,
+                relay: false,
+                time: alice.time,
+            },
+            Link::Outbound,
+        );
+        requests.extend(alice.messages().filter_map(|(addr, msg)| match msg {
+            NetworkMessage::GetHeaders(msg) => Some((msg.stop_hash, addr)),
+            _ => None,
+        }));
+    }
+    let (_, end) = checkpoints[2];
+    let peer = requests[&end];
+
+    // The peer sends headers that don't connect to the range.
+    alice.receive(peer, NetworkMessage::Headers(headers[10..20].to_vec()));
+    alice
+        .messages()
+        .find(|(addr, msg)| {
+            matches!(msg, NetworkMessage::GetHeaders(msg) if msg.stop_hash == end) && *addr != peer
+        })
+        .expect("The range is requested from another peer");
+}
+
 /// Test what happens when a peer is idle for too long.
 #[test]
 fn test_idle_disconnect() {
@@ -170,7 +387,7 @@ fn test_idle_disconnect() {
 fn test_inv_getheaders() {
     let rng = fastrand::Rng::new();
     let network = Network::Mainnet;
-    let msg = message::Builder::new(network);
+    let msg = message::Builder::new(network.magic());
     let mut peer = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng);
     let remote: PeerId = ([241, 19, 44, 18], 8333).into();
 
@@ -214,6 +431,92 @@ fn test_bad_magic() {
         .expect("peer should be disconnected");
 }
 
+#[test]
+fn test_custom_signet_magic() {
+    let rng = fastrand::Rng::new();
+    let network = Network::Signet;
+    let signet = nakamoto_common::network::Signet::custom(
+        bitcoin::blockdata::script::Builder::new()
+            .push_opcode(bitcoin::blockdata::opcodes::all::OP_PUSHNUM_1)
+            .into_script(),
+        vec![],
+    );
+    let magic = signet.magic();
+    let cfg = Config::from("alice", signet.network_params(), vec![]);
+    let mut peer = Peer::config([48, 48, 48, 48], vec![], vec![], vec![], cfg, rng);
+    let remote: PeerId = ([241, 19, 44, 18], network.port()).into();
+
+    assert_ne!(magic, network.magic());
+
+    peer.connect_addr(&remote, Link::Outbound);
+    peer.step(Input::Received(
+        remote,
+        RawNetworkMessage {
+            magic,
+            payload: NetworkMessage::Ping(1),
+        },
+    ));
+    peer.outputs()
+        .find(|o| {
+            matches!(o, Out::Message(addr, RawNetworkMessage {
+                magic: m, payload: NetworkMessage::Pong(1)
+            }) if addr == &remote && *m == magic)
+        })
+        .expect("peer responds using the custom signet magic");
+
+    // Messages using the public signet magic are rejected.
+    peer.step(Input::Received(
+        remote,
+        RawNetworkMessage {
+            magic: network.magic(),
+            payload: NetworkMessage::Ping(1),
+        },
+    ));
+    peer.outputs()
+        .find(|o| matches!(o, Out::Disconnect(addr, DisconnectReason::PeerMagic(_)) if addr == &remote))
+        .expect("peer should be disconnected");
+}
+
+#[test]
+fn test_custom_network() {
+    let rng = fastrand::Rng::new();
+    let mut genesis = Network::Regtest.genesis_block();
+    genesis.header.time += 1;
+
+    let network = NetworkParams {
+        name: String::from("private"),
+        genesis,
+        magic: 0xcafebabe,
+        port: 18555,
+        ..NetworkParams::from(Network::Regtest)
+    };
+    let cfg = Config::from("alice", network.clone(), vec![]);
+    let mut peer = Peer::config([48, 48, 48, 48], vec![], vec![], vec![], cfg, rng);
+    let remote: PeerId = ([241, 19, 44, 18], network.port).into();
+
+    assert_eq!(
+        peer.protocol.tree.genesis().block_hash(),
+        network.genesis_hash()
+    );
+    assert_ne!(network.genesis_hash(), Network::Regtest.genesis_hash());
+
+    peer.connect_addr(&remote, Link::Outbound);
+    peer.step(Input::Received(
+        remote,
+        RawNetworkMessage {
+            magic: network.magic,
+            payload: NetworkMessage::Ping(1),
+        },
+    ));
+    peer.outputs()
+        .find(|o| {
+            matches!(o, Out::Message(addr, RawNetworkMessage {
+                magic, payload: NetworkMessage::Pong(1)
+            }) if addr == &remote && *magic == network.magic)
+        })
+        .expect("peer responds using the custom network magic");
+}
+
 #[test]
 fn test_maintain_connections() {
     let rng = fastrand::Rng::new();
@@ -275,7 +578,7 @@ fn test_maintain_connections() {
 fn test_getheaders_retry() {
     let rng = fastrand::Rng::new();
     let network = Network::Mainnet;
-    let msg = message::Builder::new(network);
+    let msg = message::Builder::new(network.magic());
 
     // Some hash for a nonexistent block.
     let hash =
@@ -537,7 +840,7 @@ fn test_connection_error() {
 fn test_getaddr() {
     let rng = fastrand::Rng::new();
     let network = Network::Mainnet;
-    let msg = message::Builder::new(network);
+    let msg = message::Builder::new(network.magic());
     let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng);
     let bob: PeerId = ([241, 19, 44, 18], 8333).into();
     let eve: PeerId = ([241, 19, 44, 19], 8333).into();
@@ -589,7 +892,7 @@ fn test_getaddr() {
 fn test_stale_tip() {
     let rng = fastrand::Rng::new();
     let network = Network::Mainnet;
-    let msg = message::Builder::new(network);
+    let msg = message::Builder::new(network.magic());
     let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng);
     let remote: PeerId = ([33, 33, 33, 33], network.port()).into();
     let headers = &BITCOIN_HEADERS;
@@ -641,6 +944,69 @@ fn test_stale_tip() {
         .expect("Alice emits a `StaleTipDetected` event");
 }
 
+#[test]
+fn test_orphans_expired() {
+    let rng = fastrand::Rng::new();
+    let network = Network::Mainnet;
+    let msg = message::Builder::new(network.magic());
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng);
+    let remote: PeerId = ([33, 33, 33, 33], network.port()).into();
+    let orphan = BITCOIN_HEADERS[2];
+
+    alice.connect_addr(&remote, Link::Outbound);
+    // Receive a header announcement that doesn't connect to our chain.
+    alice.step(Input::Received(
+        remote,
+        msg.raw(NetworkMessage::Headers(vec![orphan])),
+    ));
+    assert!(alice.protocol.tree.is_known(&orphan.block_hash()));
+
+    alice.time.elapse(cache::ORPHAN_EXPIRY);
+    alice.tick();
+
+    assert!(!alice.protocol.tree.is_known(&orphan.block_hash()));
+    alice
+        .events()
+        .find(|e| {
+            matches!(
+                e,
+                Event::SyncManager(syncmgr::Event::OrphansExpired(peer, 1)) if *peer == remote
+            )
+        })
+        .expect("Alice emits an `OrphansExpired` event");
+
+    // A single orphan may have been sent in good faith, so the peer isn't disconnected.
+    assert!(
+        !alice.outputs().any(|o| matches!(
+            o,
+            Out::Disconnect(a, DisconnectReason::PeerMisbehaving(_)) if a == remote
+        )),
+        "Alice doesn't disconnect the peer"
+    );
+
+    // But a peer that keeps sending orphans is eventually disconnected.
+    let mut disconnected = false;
+    for orphan in BITCOIN_HEADERS.iter().skip(3) {
+        alice.step(Input::Received(
+            remote,
+            msg.raw(NetworkMessage::Headers(vec![*orphan])),
+        ));
+        alice.time.elapse(cache::ORPHAN_EXPIRY);
+        alice.tick();
+
+        if alice.outputs().any(|o| {
+            matches!(
+                o,
+                Out::Disconnect(a, DisconnectReason::PeerMisbehaving(_)) if a == remote
+            )
+        }) {
+            disconnected = true;
+            break;
+        }
+    }
+    assert!(disconnected, "Alice disconnects the peer");
+}
+
 #[quickcheck]
 fn prop_addrs(seed: u64) {
     let rng = fastrand::Rng::with_seed(seed);
@@ -654,7 +1020,7 @@ fn prop_addrs(seed: u64) {
     let jim: PeerId = ([99, 45, 180, 58], 8333).into();
     let jon: PeerId = ([14, 48, 141, 57], 8333).into();
 
-    let msg = message::Builder::new(network);
+    let msg = message::Builder::new(network.magic());
     let time = alice.time.block_time();
 
     // Let alice know about these amazing peers.
@@ -704,7 +1070,7 @@ fn prop_connect_timeout(seed: u64) {
             ([88, 88, 88, 88], network.port()).into(),
             ([99, 99, 88, 99], network.port()).into(),
         ],
-        network,
+        network: network.into(),
         ..Config::default()
     };
     let mut alice = Peer::config(
@@ -842,7 +1208,7 @@ fn test_submit_transactions() {
     let txid = tx.txid();
     let inventory = vec![Inventory::Transaction(txid)];
     alice.connect(&remote2, Link::Outbound);
-    alice.command(Command::SubmitTransaction(tx, transmit));
+    alice.command(Command::SubmitTransaction(tx, Broadcast::All, transmit));
 
     let remotes = receive.recv().unwrap().unwrap();
     assert_eq!(Vec::from(remotes), vec![remote1.addr]);
@@ -862,87 +1228,570 @@ fn test_submit_transactions() {
         .expect("Alice responds to `getdata` with a `tx` message");
 }
 
-/// Should rebroadcast `inv` when no `getdata` is received.
-/// Should rebroadcast when a new peer connects.
 #[test]
-fn test_inv_rebroadcast() {
+fn test_submit_transaction_privately() {
     let network = Network::Mainnet;
-
     let mut rng = fastrand::Rng::new();
     let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+    let remote1 = PeerDummy {
+        addr: ([88, 88, 88, 88], 8333).into(),
+        height: 144,
+        protocol_version: alice.protocol.protocol_version,
+        services: ServiceFlags::NETWORK,
+        relay: true,
+        time: LocalTime::now(),
+    };
+    let remote2 = PeerDummy {
+        addr: ([99, 99, 99, 99], 8333).into(),
+        ..remote1
+    };
+    let remote3 = PeerDummy {
+        addr: ([77, 77, 77, 77], 8333).into(),
+        ..remote1
+    };
+    alice.connect(&remote1, Link::Outbound);
+    alice.connect(&remote2, Link::Outbound);
+    alice.drain();
 
-    let remote1 = ([88, 88, 88, 88], 8333).into();
-    let remote2 = ([99, 99, 99, 99], 8333).into();
-    let tx1 = gen::transaction(&mut rng);
-    let tx2 = gen::transaction(&mut rng);
-    let (transmit, _) = chan::unbounded();
+    let is_inv = |msg: &NetworkMessage, txid| matches!(msg, NetworkMessage::Inv(invs) if invs.contains(&Inventory::Transaction(txid)));
 
-    alice.connect_addr(&remote1, Link::Outbound);
-    alice.command(Command::SubmitTransaction(tx1, transmit.clone()));
-    alice.command(Command::SubmitTransaction(tx2, transmit));
-    alice.tick(); // Broadcasting doesn't happen immediately
-    alice
-        .messages()
-        .find(|m| {
-            matches! {
-                m, (addr, NetworkMessage::Inv(inv))
-                if inv.len() == 2 && addr == &remote1
-            }
-        })
-        .expect("Alice sends an initial `inv`");
+    // Send a transaction to a single peer.
+    let tx = gen::transaction(&mut rng);
+    let txid = tx.txid();
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::SubmitTransaction(tx, Broadcast::Single, transmit));
 
-    alice.outputs().count(); // Drain outputs
-    alice.connect_addr(&remote2, Link::Outbound); // A new peer connects
-    alice.tick(); // No delay until invs are sent to it
-    alice
-        .messages()
-        .find(|m| matches!(m, (a, NetworkMessage::Inv(_)) if a == &remote2))
-        .expect("Alice sends a first `inv` message to the new peer");
+    let peers = receive.recv().unwrap().unwrap();
+    assert_eq!(peers.len(), 1);
 
-    // Let some time pass.
-    alice.drain();
-    alice.time.elapse(LocalDuration::from_mins(5));
+    let (first, other) = if *peers.first() == remote1.addr {
+        (remote1.addr, remote2.addr)
+    } else {
+        (remote2.addr, remote1.addr)
+    };
     alice.tick();
-
-    let msgs = alice
-        .messages()
-        .filter(|m| matches!(m, (_, NetworkMessage::Inv(_))))
-        .collect::<Vec<_>>();
-
     assert_eq!(
-        msgs.len(),
-        2,
-        "Two `inv` messages are sent, one for each peer"
+        alice
+            .messages()
+            .filter(|(_, msg)| is_inv(msg, txid))
+            .map(|(addr, _)| addr)
+            .collect::<Vec<_>>(),
+        vec![first]
     );
 
-    msgs.iter()
-        .find(|(addr, _)| addr == &remote1)
-        .expect("Alice sends a second `inv` message to the first peer");
-    msgs.iter()
-        .find(|(addr, _)| addr == &remote2)
-        .expect("Alice sends a second `inv` message to the second peer");
-
-    // Let some more time pass.
-    alice.time.elapse(LocalDuration::from_mins(5));
+    // Peers connecting later aren't told about the transaction.
+    alice.connect(&remote3, Link::Inbound);
+    alice.time.elapse(invmgr::REBROADCAST_TIMEOUT);
     alice.tick();
-    alice
+    assert!(!alice
         .messages()
-        .find(|m| {
-            matches! {
-                m, (addr, NetworkMessage::Inv(_))
-                if addr == &remote1
-            }
-        })
-        .expect("Alice sends a third `inv` message to the first peer");
-}
+        .any(|(addr, msg)| addr == remote3.addr && is_inv(&msg, txid)));
 
-#[test]
-fn test_inv_partial_broadcast() {
-    let network = Network::Mainnet;
-    let msg = message::Builder::new(network);
-
-    let mut rng = fastrand::Rng::new();
-    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+    // If the transaction isn't relayed back to us, it's sent to another peer.
+    alice.time.elapse(invmgr::PRIVATE_BROADCAST_TIMEOUT);
+    alice.tick();
+    assert!(alice
+        .messages()
+        .any(|(addr, msg)| addr == other && is_inv(&msg, txid)));
+
+    // Once another peer relays it back to us, it has propagated.
+    alice.receive(
+        first,
+        NetworkMessage::Inv(vec![Inventory::Transaction(txid)]),
+    );
+    alice.receive(
+        remote3.addr,
+        NetworkMessage::Inv(vec![Inventory::Transaction(txid)]),
+    );
+    alice
+        .events()
+        .find(|e| {
+            matches!(
+                e,
+                Event::InventoryManager(invmgr::Event::Relayed { txid: t, peer })
+                if *t == txid && *peer == remote3.addr
+            )
+        })
+        .expect("Alice emits a `Relayed` event");
+}
+
+#[test]
+fn test_transaction_propagation() {
+    let network = Network::Mainnet;
+    let mut rng = fastrand::Rng::new();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+    let remote1 = PeerDummy {
+        addr: ([88, 88, 88, 88], 8333).into(),
+        height: 144,
+        protocol_version: alice.protocol.protocol_version,
+        services: ServiceFlags::NETWORK,
+        relay: true,
+        time: LocalTime::now(),
+    };
+    let remote2 = PeerDummy {
+        addr: ([99, 99, 99, 99], 8333).into(),
+        ..remote1
+    };
+    alice.connect(&remote1, Link::Outbound);
+    alice.connect(&remote2, Link::Outbound);
+
+    let is_inv = |msg: &NetworkMessage, txid| matches!(msg, NetworkMessage::Inv(invs) if invs.contains(&Inventory::Transaction(txid)));
+
+    let tx = gen::transaction(&mut rng);
+    let txid = tx.txid();
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::SubmitTransaction(tx, Broadcast::All, transmit));
+    receive.recv().unwrap().unwrap();
+    alice.tick();
+    alice.drain();
+
+    // A peer that got the transaction from us announcing it doesn't count.
+    alice.receive(
+        remote1.addr,
+        NetworkMessage::GetData(vec![Inventory::Transaction(txid)]),
+    );
+    alice.receive(
+        remote1.addr,
+        NetworkMessage::Inv(vec![Inventory::Transaction(txid)]),
+    );
+    assert!(!alice
+        .events()
+        .any(|e| matches!(e, Event::InventoryManager(invmgr::Event::Relayed { .. }))));
+
+    // Without any peer relaying it back to us, the transaction is re-announced.
+    alice.time.elapse(invmgr::PROPAGATION_TIMEOUT);
+    alice.tick();
+    assert!(alice
+        .messages()
+        .any(|(addr, msg)| addr == remote1.addr && is_inv(&msg, txid)));
+
+    // A peer that didn't get it from us announcing it means it propagated.
+    alice.receive(
+        remote2.addr,
+        NetworkMessage::Inv(vec![Inventory::Transaction(txid)]),
+    );
+    alice
+        .events()
+        .find(|e| {
+            matches!(
+                e,
+                Event::InventoryManager(invmgr::Event::Relayed { txid: t, peer })
+                if *t == txid && *peer == remote2.addr
+            )
+        })
+        .expect("Alice emits a `Relayed` event");
+
+    // It's no longer announced to that peer, or re-announced.
+    alice.time.elapse(invmgr::PROPAGATION_TIMEOUT);
+    alice.tick();
+    assert!(!alice.messages().any(|(_, msg)| is_inv(&msg, txid)));
+}
+
+#[test]
+fn test_transaction_propagation_requested() {
+    let network = Network::Mainnet;
+    let mut rng = fastrand::Rng::new();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+    let remote1 = PeerDummy {
+        addr: ([88, 88, 88, 88], 8333).into(),
+        height: 144,
+        protocol_version: alice.protocol.protocol_version,
+        services: ServiceFlags::NETWORK,
+        relay: true,
+        time: LocalTime::now(),
+    };
+    let remote2 = PeerDummy {
+        addr: ([99, 99, 99, 99], 8333).into(),
+        ..remote1
+    };
+    alice.connect(&remote1, Link::Outbound);
+    alice.connect(&remote2, Link::Outbound);
+
+    let tx = gen::transaction(&mut rng);
+    let txid = tx.txid();
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::SubmitTransaction(tx, Broadcast::All, transmit));
+    receive.recv().unwrap().unwrap();
+    alice.tick();
+    alice.drain();
+
+    // When announcing to all peers, peers get the transaction from us, and don't announce it
+    // back. Their requests count towards propagation.
+    alice.receive(
+        remote1.addr,
+        NetworkMessage::GetData(vec![Inventory::Transaction(txid)]),
+    );
+    assert!(!alice
+        .events()
+        .any(|e| matches!(e, Event::InventoryManager(invmgr::Event::Propagated { .. }))));
+
+    alice.receive(
+        remote2.addr,
+        NetworkMessage::GetData(vec![Inventory::Transaction(txid)]),
+    );
+    let mut peers = vec![remote1.addr, remote2.addr];
+    peers.sort();
+
+    alice
+        .events()
+        .find(|e| {
+            matches!(
+                e,
+                Event::InventoryManager(invmgr::Event::Propagated { txid: t, peers: p })
+                if *t == txid && *p == peers
+            )
+        })
+        .expect("Alice emits a `Propagated` event");
+
+    // The transaction isn't re-announced.
+    alice.time.elapse(invmgr::PROPAGATION_TIMEOUT);
+    alice.tick();
+    assert!(!alice.messages().any(|(_, msg)| matches!(
+        msg,
+        NetworkMessage::Inv(invs) if invs.contains(&Inventory::Transaction(txid))
+    )));
+}
+
+#[test]
+fn test_submit_transaction_ephemeral() {
+    let network = Network::Mainnet;
+    let mut rng = fastrand::Rng::new();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+    let remote = PeerDummy {
+        addr: ([88, 88, 88, 88], 8333).into(),
+        height: 144,
+        protocol_version: alice.protocol.protocol_version,
+        services: ServiceFlags::NETWORK,
+        relay: true,
+        time: LocalTime::now(),
+    };
+    let ephemeral = PeerDummy {
+        addr: ([99, 99, 99, 99], 8333).into(),
+        ..remote
+    };
+    alice.connect(&remote, Link::Outbound);
+    alice.protocol.addrmgr.insert(
+        iter::once((
+            alice.time.block_time(),
+            Address::new(&ephemeral.addr, ServiceFlags::NETWORK),
+        )),
+        Source::Dns,
+    );
+    alice.drain();
+
+    // The transaction is sent through a new connection.
+    let tx = gen::transaction(&mut rng);
+    let txid = tx.txid();
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::SubmitTransaction(
+        tx,
+        Broadcast::Ephemeral,
+        transmit,
+    ));
+
+    let peers = receive.recv().unwrap().unwrap();
+    assert_eq!(Vec::from(peers), vec![ephemeral.addr]);
+    alice
+        .outputs()
+        .find(|o| matches!(o, Out::Connect(addr, _) if *addr == ephemeral.addr))
+        .expect("Alice connects to a new peer");
+
+    alice.connect(&ephemeral, Link::Outbound);
+    alice.tick();
+
+    let invs = alice
+        .messages()
+        .filter(|(_, msg)| {
+            matches!(msg, NetworkMessage::Inv(invs) if invs.contains(&Inventory::Transaction(txid)))
+        })
+        .map(|(addr, _)| addr)
+        .collect::<Vec<_>>();
+    assert_eq!(invs, vec![ephemeral.addr]);
+
+    // Once the transaction propagated, the connection is closed.
+    alice.receive(
+        remote.addr,
+        NetworkMessage::Inv(vec![Inventory::Transaction(txid)]),
+    );
+    alice
+        .outputs()
+        .find(|o| matches!(o, Out::Disconnect(addr, _) if *addr == ephemeral.addr))
+        .expect("Alice disconnects from the ephemeral peer");
+}
+
+#[test]
+fn test_submit_package() {
+    use bitcoin::OutPoint;
+
+    let network = Network::Mainnet;
+    let mut rng = fastrand::Rng::new();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+    let remote = PeerDummy {
+        addr: ([88, 88, 88, 88], 8333).into(),
+        height: 144,
+        protocol_version: alice.protocol.protocol_version,
+        services: ServiceFlags::NETWORK,
+        relay: true,
+        time: LocalTime::now(),
+    };
+    let other = PeerDummy {
+        addr: ([99, 99, 99, 99], 8333).into(),
+        ..remote
+    };
+    alice.connect(&remote, Link::Outbound);
+    alice.connect(&other, Link::Outbound);
+
+    let parent = gen::transaction(&mut rng);
+    let mut child = gen::transaction(&mut rng);
+    child.input[0].previous_output = OutPoint {
+        txid: parent.txid(),
+        vout: 0,
+    };
+    let invs = |alice: &mut Peer<Protocol>| {
+        alice
+            .messages()
+            .filter_map(|(addr, msg)| match msg {
+                NetworkMessage::Inv(invs) => Some(invs.into_iter().map(move |inv| (addr, inv))),
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>()
+    };
+
+    // Transactions can be submitted in any order.
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::SubmitPackage(
+        vec![child.clone(), parent.clone()],
+        transmit,
+    ));
+    let mut peers = Vec::from(receive.recv().unwrap().unwrap());
+    peers.sort();
+    assert_eq!(peers, vec![remote.addr, other.addr]);
+    assert_eq!(
+        alice.protocol.invmgr.mempool.len(),
+        2,
+        "Both transactions are in the mempool"
+    );
+
+    // The child is only announced once the peer has its parent.
+    alice.tick();
+    let mut announced = invs(&mut alice);
+    announced.sort();
+    assert_eq!(
+        announced,
+        vec![
+            (remote.addr, Inventory::Transaction(parent.txid())),
+            (other.addr, Inventory::Transaction(parent.txid()))
+        ]
+    );
+
+    alice.receive(
+        remote.addr,
+        NetworkMessage::GetData(vec![Inventory::Transaction(parent.txid())]),
+    );
+    alice.tick();
+    assert_eq!(
+        invs(&mut alice),
+        vec![(remote.addr, Inventory::Transaction(child.txid()))]
+    );
+
+    // A peer that doesn't request the parent, eg. because it already has it, is announced
+    // the child after a while.
+    alice.time.elapse(invmgr::HOLD_TIMEOUT);
+    alice.tick();
+    assert!(invs(&mut alice).contains(&(other.addr, Inventory::Transaction(child.txid()))));
+
+    // Pending transactions are returned parents first.
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::GetPendingTransactions(transmit));
+    assert_eq!(receive.recv().unwrap(), vec![parent, child]);
+}
+
+#[test]
+fn test_bump_fee() {
+    use super::fees::{self, FeeBump, SEQUENCE_RBF};
+    use super::CommandError;
+
+    let network = Network::Mainnet;
+    let mut rng = fastrand::Rng::new();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+    let remote = PeerDummy {
+        addr: ([88, 88, 88, 88], 8333).into(),
+        height: 144,
+        protocol_version: alice.protocol.protocol_version,
+        services: ServiceFlags::NETWORK,
+        relay: true,
+        time: LocalTime::now(),
+    };
+    alice.connect(&remote, Link::Outbound);
+
+    // The output spent by the transaction is unknown to us. Since the replacement spends it
+    // too, the fee increase can still be computed.
+    let mut tx = gen::transaction(&mut rng);
+    tx.input.truncate(1);
+    tx.input[0].sequence = SEQUENCE_RBF;
+    tx.output.truncate(1);
+    tx.output[0].value = 90_000;
+    let txid = tx.txid();
+
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::SubmitTransaction(
+        tx.clone(),
+        Broadcast::All,
+        transmit,
+    ));
+    receive.recv().unwrap().unwrap();
+
+    // Unrelated transactions can't be used to bump the fee.
+    let unrelated = gen::transaction(&mut rng);
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::BumpFee(
+        txid,
+        FeeBump::Replace(unrelated.clone()),
+        transmit,
+    ));
+    assert_matches!(
+        receive.recv().unwrap(),
+        Err(CommandError::InvalidFeeBump(_))
+    );
+
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::BumpFee(txid, FeeBump::Child(unrelated), transmit));
+    assert_matches!(
+        receive.recv().unwrap(),
+        Err(CommandError::InvalidFeeBump(_))
+    );
+
+    // Unknown transactions can't have their fee bumped.
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::BumpFee(
+        Default::default(),
+        FeeBump::Replace(tx.clone()),
+        transmit,
+    ));
+    assert_matches!(
+        receive.recv().unwrap(),
+        Err(CommandError::TransactionNotFound(_))
+    );
+
+    // A replacement must increase the fee by at least the incremental relay fee.
+    let mut replacement = tx.clone();
+    replacement.output[0].value -= 1;
+
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::BumpFee(
+        txid,
+        FeeBump::Replace(replacement),
+        transmit,
+    ));
+    assert_matches!(
+        receive.recv().unwrap(),
+        Err(CommandError::InvalidFeeBump(_))
+    );
+    assert!(alice.protocol.invmgr.contains(&txid));
+
+    // A valid replacement replaces the original transaction.
+    let mut replacement = tx.clone();
+    replacement.output[0].value -= fees::INCREMENTAL_RELAY_FEE * fees::vsize(&tx);
+
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::BumpFee(
+        txid,
+        FeeBump::Replace(replacement.clone()),
+        transmit,
+    ));
+    let remotes = receive.recv().unwrap().unwrap();
+
+    assert_eq!(Vec::from(remotes), vec![remote.addr]);
+    assert!(!alice.protocol.invmgr.contains(&txid));
+    assert!(alice.protocol.invmgr.contains(&replacement.txid()));
+}
+
+/// Should rebroadcast `inv` when no `getdata` is received.
+/// Should rebroadcast when a new peer connects.
+#[test]
+fn test_inv_rebroadcast() {
+    let network = Network::Mainnet;
+
+    let mut rng = fastrand::Rng::new();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+
+    let remote1 = ([88, 88, 88, 88], 8333).into();
+    let remote2 = ([99, 99, 99, 99], 8333).into();
+    let tx1 = gen::transaction(&mut rng);
+    let tx2 = gen::transaction(&mut rng);
+    let (transmit, _) = chan::unbounded();
+
+    alice.connect_addr(&remote1, Link::Outbound);
+    alice.command(Command::SubmitTransaction(
+        tx1,
+        Broadcast::All,
+        transmit.clone(),
+    ));
+    alice.command(Command::SubmitTransaction(tx2, Broadcast::All, transmit));
+    alice.tick(); // Broadcasting doesn't happen immediately
+    alice
+        .messages()
+        .find(|m| {
+            matches! {
+                m, (addr, NetworkMessage::Inv(inv))
+                if inv.len() == 2 && addr == &remote1
+            }
+        })
+        .expect("Alice sends an initial `inv`");
+
+    alice.outputs().count(); // Drain outputs
+    alice.connect_addr(&remote2, Link::Outbound); // A new peer connects
+    alice.tick(); // No delay until invs are sent to it
+    alice
+        .messages()
+        .find(|m| matches!(m, (a, NetworkMessage::Inv(_)) if a == &remote2))
+        .expect("Alice sends a first `inv` message to the new peer");
+
+    // Let some time pass.
+    alice.drain();
+    alice.time.elapse(LocalDuration::from_mins(5));
+    alice.tick();
+
+    let msgs = alice
+        .messages()
+        .filter(|m| matches!(m, (_, NetworkMessage::Inv(_))))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        msgs.len(),
+        2,
+        "Two `inv` messages are sent, one for each peer"
+    );
+
+    msgs.iter()
+        .find(|(addr, _)| addr == &remote1)
+        .expect("Alice sends a second `inv` message to the first peer");
+    msgs.iter()
+        .find(|(addr, _)| addr == &remote2)
+        .expect("Alice sends a second `inv` message to the second peer");
+
+    // Let some more time pass.
+    alice.time.elapse(LocalDuration::from_mins(5));
+    alice.tick();
+    alice
+        .messages()
+        .find(|m| {
+            matches! {
+                m, (addr, NetworkMessage::Inv(_))
+                if addr == &remote1
+            }
+        })
+        .expect("Alice sends a third `inv` message to the first peer");
+}
+
+#[test]
+fn test_inv_partial_broadcast() {
+    let network = Network::Mainnet;
+    let msg = message::Builder::new(network.magic());
+
+    let mut rng = fastrand::Rng::new();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
 
     let remote1 = ([88, 88, 88, 88], 8333).into();
     let remote2 = ([99, 99, 99, 99], 8333).into();
@@ -952,8 +1801,16 @@ fn test_inv_partial_broadcast() {
 
     alice.connect_addr(&remote1, Link::Outbound);
     alice.connect_addr(&remote2, Link::Outbound);
-    alice.command(Command::SubmitTransaction(tx1.clone(), transmit.clone()));
-    alice.command(Command::SubmitTransaction(tx2.clone(), transmit));
+    alice.command(Command::SubmitTransaction(
+        tx1.clone(),
+        Broadcast::All,
+        transmit.clone(),
+    ));
+    alice.command(Command::SubmitTransaction(
+        tx2.clone(),
+        Broadcast::All,
+        transmit,
+    ));
     alice.tick();
 
     // The first peer asks only for the first inventory item.
@@ -1056,7 +1913,7 @@ fn test_confirmed_transaction() {
     let genesis = network.genesis_block();
     let chain = gen::blockchain(genesis, 16, &mut rng);
     let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
-    let msg = message::Builder::new(network);
+    let msg = message::Builder::new(network.magic());
     let mut alice = Peer::new(
         "alice",
         [48, 48, 48, 48],
@@ -1073,8 +1930,16 @@ fn test_confirmed_transaction() {
     let tx2 = &blk2.txdata[rng.usize(0..blk2.txdata.len())];
 
     alice.connect_addr(&remote, Link::Outbound);
-    alice.command(Command::SubmitTransaction(tx1.clone(), transmit.clone()));
-    alice.command(Command::SubmitTransaction(tx2.clone(), transmit));
+    alice.command(Command::SubmitTransaction(
+        tx1.clone(),
+        Broadcast::All,
+        transmit.clone(),
+    ));
+    alice.command(Command::SubmitTransaction(
+        tx2.clone(),
+        Broadcast::All,
+        transmit,
+    ));
     alice.tick();
 
     assert!(alice.protocol.invmgr.contains(&tx1.txid()));
@@ -1156,6 +2021,103 @@ fn test_confirmed_transaction() {
     assert!(alice.protocol.invmgr.is_empty());
 }
 
+#[test]
+fn test_block_body_mismatch() {
+    let mut rng = fastrand::Rng::new();
+
+    let network = Network::Regtest;
+    let genesis = network.genesis_block();
+    let chain = gen::blockchain(genesis, 16, &mut rng);
+    let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+    let mut alice = Peer::new(
+        "alice",
+        [48, 48, 48, 48],
+        network,
+        headers.tail,
+        vec![],
+        vec![],
+        rng.clone(),
+    );
+    let bob: PeerId = ([88, 88, 88, 88], 8333).into();
+    let eve: PeerId = ([99, 99, 99, 99], 8333).into();
+
+    alice.connect_addr(&bob, Link::Outbound);
+    alice.connect_addr(&eve, Link::Outbound);
+
+    let block = &chain[8];
+    let getdata = NetworkMessage::GetData(vec![Inventory::Block(block.block_hash())]);
+
+    alice.protocol.invmgr.get_block(block.block_hash());
+    alice.tick();
+
+    let (requested, _) = alice
+        .messages()
+        .find(|(_, m)| *m == getdata)
+        .expect("Alice requests the block");
+    let other = if requested == bob { eve } else { bob };
+
+    // The peer sends the block with a body that doesn't match its header.
+    let mut tampered = block.clone();
+    tampered.txdata.push(gen::transaction(&mut rng));
+    alice.receive(requested, NetworkMessage::Block(tampered));
+
+    alice
+        .outputs()
+        .find(|o| {
+            matches!(
+                o,
+                Out::Disconnect(a, DisconnectReason::PeerMisbehaving(_)) if *a == requested
+            )
+        })
+        .expect("Alice disconnects the peer");
+
+    // The header itself is still valid.
+    assert_eq!(alice.protocol.tree.height(), 16);
+    assert!(alice.protocol.tree.is_known(&block.block_hash()));
+
+    // Once disconnected, the block is requested from another peer.
+    alice.step(Input::Disconnected(
+        requested,
+        DisconnectReason::PeerMisbehaving("block doesn't match its header"),
+    ));
+    alice.tick();
+
+    assert!(
+        alice.messages().any(|(a, m)| a == other && m == getdata),
+        "Alice requests the block again from the other peer"
+    );
+}
+
+#[test]
+fn test_submitted_transaction_spends_watched() {
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::{PublicKey, Script};
+
+    let network = Network::Mainnet;
+    let mut rng = fastrand::Rng::new();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+    alice.connect_addr(&([88, 88, 88, 88], 8333).into(), Link::Outbound);
+
+    let key = PublicKey {
+        compressed: true,
+        key: bitcoin::secp256k1::PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &SecretKey::from_slice(&[1; 32]).unwrap(),
+        ),
+    };
+    let spent = Script::new_v0_wpkh(&key.wpubkey_hash().unwrap());
+    let mut tx = gen::transaction(&mut rng);
+    tx.input[0].witness = vec![vec![0; 72], key.to_bytes()];
+
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::SubmitTransaction(tx, Broadcast::All, transmit));
+    receive.recv().unwrap().unwrap();
+
+    // The output spent by the transaction is watched, so that a conflicting spend is matched.
+    assert!(alice.protocol.cbfmgr.rescan.active);
+    assert!(alice.protocol.cbfmgr.rescan.watch.contains(&spent));
+}
+
 #[test]
 fn test_submitted_transaction_filtering() {
     let height = 16;
@@ -1205,7 +2167,11 @@ fn test_submitted_transaction_filtering() {
         to: Bound::Unbounded,   // Keep scanning forever.
         watch: vec![],          // Submitted transactions are tracked automatically.
     });
-    alice.command(Command::SubmitTransaction(tx.clone(), transmit));
+    alice.command(Command::SubmitTransaction(
+        tx.clone(),
+        Broadcast::All,
+        transmit,
+    ));
     alice.tick();
 
     assert!(alice.protocol.invmgr.contains(&tx.txid()));
@@ -1322,7 +2288,11 @@ fn test_transaction_reverted_reconfirm() {
         to: Bound::Unbounded,   // Keep scanning forever.
         watch: vec![],          // Submitted transactions are tracked automatically.
     });
-    alice.command(Command::SubmitTransaction(tx.clone(), submit_reply));
+    alice.command(Command::SubmitTransaction(
+        tx.clone(),
+        Broadcast::All,
+        submit_reply,
+    ));
     alice.tick();
 
     // Alice receives the initial shorter chain.
@@ -1593,6 +2563,75 @@ fn test_block_events() {
     assert!(events.next().is_none());
 }
 
+/// Test that re-orgs are reported as a whole, and that deep re-orgs are flagged.
+#[test]
+fn test_reorg_events() {
+    let mut rng = fastrand::Rng::new();
+    let network = Network::Regtest;
+    let genesis = network.genesis();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng.clone());
+    let (transmit, import) = chan::unbounded();
+
+    let best = 16;
+    let headers = gen::headers(genesis, best, &mut rng);
+    // Shallow fork, reverting a single block.
+    let shallow = gen::headers(headers[best as usize - 1], 2, &mut rng);
+    // Deep fork, reverting the shallow fork and more.
+    let fork_height = 8;
+    let deep = gen::headers(headers[fork_height as usize], 12, &mut rng);
+
+    fn filter(events: impl Iterator<Item = Event>) -> impl Iterator<Item = syncmgr::Event> {
+        events.filter_map(|e| match e {
+            Event::SyncManager(event @ syncmgr::Event::Reorg { .. }) => Some(event),
+            Event::SyncManager(event @ syncmgr::Event::DeepReorg { .. }) => Some(event),
+            _ => None,
+        })
+    }
+
+    alice.time = LocalTime::from_block_time(deep.last().time);
+    alice.initialize();
+    alice.command(Command::ImportHeaders(
+        headers.tail.clone(),
+        transmit.clone(),
+    ));
+    import.recv().unwrap().unwrap();
+    assert_eq!(filter(alice.events()).count(), 0, "No re-org happened");
+
+    alice.command(Command::ImportHeaders(
+        shallow.tail.clone(),
+        transmit.clone(),
+    ));
+    import.recv().unwrap().unwrap();
+
+    let mut events = filter(alice.events());
+    assert_matches!(
+        events.next().unwrap(),
+        syncmgr::Event::Reorg { fork_height, reverted, connected }
+        if fork_height == best - 1
+            && reverted == vec![(best, headers.last().block_hash())]
+            && connected == shallow.tail.iter().cloned().zip(best..).map(|(h, i)| (i, h)).collect::<Vec<_>>()
+    );
+    assert_eq!(events.count(), 0, "The re-org isn't deep");
+
+    alice.command(Command::ImportHeaders(deep.tail.clone(), transmit));
+    import.recv().unwrap().unwrap();
+
+    let mut events = filter(alice.events());
+    assert_matches!(
+        events.next().unwrap(),
+        syncmgr::Event::Reorg { fork_height: height, reverted, connected }
+        if height == fork_height
+            && reverted.iter().map(|(h, _)| *h).eq(fork_height + 1..=best + 1)
+            && connected.len() == deep.tail.len()
+    );
+    assert_matches!(
+        events.next().unwrap(),
+        syncmgr::Event::DeepReorg { fork_height: height, depth }
+        if height == fork_height && depth == (best + 1 - fork_height) as usize
+    );
+    assert!(events.next().is_none());
+}
+
 #[test]
 fn test_transaction_mempool_rebroadcast() {
     // TODO: Should check mempool to rebroadcast.
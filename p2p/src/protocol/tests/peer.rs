@@ -1,6 +1,5 @@
 use super::*;
 
-use bitcoin::consensus::Params;
 use bitcoin::network::message_network::VersionMessage;
 use bitcoin::network::Address;
 
@@ -102,8 +101,7 @@ impl Peer<Protocol> {
         rng: fastrand::Rng,
     ) -> Self {
         let cfg = Config {
-            network,
-            params: Params::new(network.into()),
+            network: network.into(),
             target: name,
             // We don't actually have the required services, but we pretend to
             // for testing purposes.
@@ -131,13 +129,16 @@ impl Peer<Protocol> {
         cfg: Config,
         rng: fastrand::Rng,
     ) -> Self {
-        let network = cfg.network;
+        let network = &cfg.network;
         let genesis = network.genesis();
         let time = LocalTime::from_secs(genesis.time as u64);
         let clock = AdjustedTime::new(time);
-        let headers = NonEmpty::from((network.genesis(), headers));
+        let headers = NonEmpty::from((genesis, headers));
         let cfheaders = NonEmpty::from((
-            (FilterHash::genesis(network), FilterHeader::genesis(network)),
+            (
+                FilterHash::from_genesis_block(&network.genesis),
+                FilterHeader::from_genesis_block(&network.genesis),
+            ),
             cfheaders,
         ));
         let peers = peers
@@ -151,11 +152,11 @@ impl Peer<Protocol> {
             .collect();
 
         let store = store::Memory::new(headers);
-        let tree = BlockCache::from(store, cfg.params.clone(), &[]).unwrap();
+        let tree = BlockCache::from(store, network.params.clone(), &[]).unwrap();
         let filters = model::FilterCache::from(cfheaders);
 
         let (tx, rx) = chan::unbounded();
-        let addr = (ip.into(), network.port()).into();
+        let addr = (ip.into(), network.port).into();
         let protocol = Protocol::new(tree, filters, peers, clock, rng, cfg.clone(), tx);
 
         Self {
@@ -209,7 +210,7 @@ impl Peer<Protocol> {
             Input::Received(
                 remote,
                 RawNetworkMessage {
-                    magic: self.protocol.network.magic(),
+                    magic: self.protocol.network.magic,
                     payload,
                 },
             ),
@@ -225,7 +226,7 @@ impl Peer<Protocol> {
         self.initialize();
 
         let local = self.addr;
-        let msg = message::Builder::new(self.protocol.network);
+        let msg = message::Builder::new(self.protocol.network.magic);
         let rng = self.protocol.rng.clone();
         let time = self.time;
 
@@ -361,7 +362,7 @@ pub fn network(network: Network, size: usize, rng: fastrand::Rng) -> Vec<Peer<Pr
         .map(|(i, (addr, _, _))| {
             let peers = address_books.get(addr).unwrap_or(&Vec::new()).clone();
             let cfg = Config {
-                network,
+                network: network.into(),
                 target: names[i],
                 // These nodes don't need to try connecting to other nodes.
                 target_outbound_peers: 0,
@@ -23,7 +23,7 @@
 //! /// Run the light-client.
 //! fn main() -> Result<(), Error> {
 //!     let cfg = Config {
-//!         network: Network::Testnet,
+//!         network: Network::Testnet.into(),
 //!         ..Config::default()
 //!     };
 //!     // Create a client using the above network reactor.
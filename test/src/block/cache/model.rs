@@ -170,10 +170,10 @@ impl BlockTree for Cache {
         }
     }
 
-    fn get_block(&self, hash: &BlockHash) -> Option<(Height, &BlockHeader)> {
+    fn get_block(&self, hash: &BlockHash) -> Option<(Height, BlockHeader)> {
         for (height, header) in self.chain.iter().enumerate() {
             if hash == &header.block_hash() {
-                return Some((height as Height, header));
+                return Some((height as Height, *header));
             }
         }
         None
@@ -200,8 +200,8 @@ impl BlockTree for Cache {
         vec![self.chain.last().block_hash()]
     }
 
-    fn get_block_by_height(&self, height: Height) -> Option<&BlockHeader> {
-        self.chain.get(height as usize)
+    fn get_block_by_height(&self, height: Height) -> Option<BlockHeader> {
+        self.chain.get(height as usize).copied()
     }
 
     fn tip(&self) -> (BlockHash, BlockHeader) {